@@ -0,0 +1,508 @@
+//! Procedural macros for the diesel extensions of this crate
+//!
+//! See `table_function` for details.
+#![recursion_limit = "128"]
+
+extern crate proc_macro;
+
+use proc_macro2::{Span, TokenStream};
+use quote::{quote, quote_spanned};
+use syn::parse::{Parse, ParseStream};
+use syn::spanned::Spanned;
+use syn::{Attribute, Data, DeriveInput, Fields, Ident, LitStr, Token, Type};
+
+/// Declare a sql function returning a table as diesel table
+///
+/// The attribute arguments are the arguments of the sql function with
+/// their sql type, the fields of the annotated struct are the returned
+/// columns with their sql type. The name of the struct is used as name
+/// of the sql function. At least one column needs to be marked with
+/// `#[primary_key]`, multiple marked columns form a composite key.
+///
+/// ```ignore
+/// #[table_function(version: Nullable<Integer>, as_of: Nullable<Timestamptz>)]
+/// pub struct posts_at_version {
+///     #[primary_key]
+///     id: Int4,
+///     title: Text,
+/// }
+/// ```
+///
+/// This generates a module `posts_at_version` containing the table
+/// type and the columns, and a function `posts_at_version(version, as_of)`
+/// to construct the table with the given arguments. The sql types are
+/// resolved with `diesel::sql_types::*` in scope. Arguments are accepted
+/// as `crate::diesel_ext::FunctionArgument`, which needs to be in scope of
+/// the calling crate.
+///
+/// The table is rendered as `name(arguments) AS name`, which is understood
+/// by postgres functions returning a table and by sqlite table-valued
+/// functions. It is generated for `crate::diesel_ext::FunctionBackend`,
+/// another backend could be chosen with a `backend = Type` argument:
+///
+/// ```ignore
+/// #[table_function(version: Nullable<Integer>, backend = diesel::sqlite::Sqlite)]
+/// ```
+///
+/// Backends without functions returning a table could use a `subquery = "..."`
+/// argument instead. The table is then rendered as subquery, with the arguments
+/// available as columns of a single row table `args`:
+///
+/// ```ignore
+/// #[table_function(
+///     version: Nullable<Integer>,
+///     subquery = "SELECT posts.* FROM posts, args WHERE posts.version_start = args.version"
+/// )]
+/// ```
+#[proc_macro_attribute]
+pub fn table_function(
+    attr: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let signature = syn::parse_macro_input!(attr as Signature);
+    let item = syn::parse_macro_input!(item as DeriveInput);
+    match TableFunction::new(signature, item) {
+        Ok(function) => function.expand().into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// The arguments of the sql function and the
+/// optional `backend = Type` and `subquery = "..."` options
+struct Signature {
+    args: Vec<Argument>,
+    backend: Option<Type>,
+    subquery: Option<LitStr>,
+}
+
+impl Parse for Signature {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut signature = Signature {
+            args: Vec::new(),
+            backend: None,
+            subquery: None,
+        };
+        while !input.is_empty() {
+            if input.peek(Ident) && input.peek2(Token![=]) {
+                let option = input.parse::<Ident>()?;
+                let duplicated = if option == "backend" {
+                    signature.backend.is_some()
+                } else if option == "subquery" {
+                    signature.subquery.is_some()
+                } else {
+                    return Err(syn::Error::new(
+                        option.span(),
+                        format!(
+                            "unknown option `{}`, expected `backend` or `subquery`",
+                            option
+                        ),
+                    ));
+                };
+                if duplicated {
+                    return Err(syn::Error::new(
+                        option.span(),
+                        format!("duplicated option `{}`", option),
+                    ));
+                }
+                input.parse::<Token![=]>()?;
+                if option == "backend" {
+                    let backend = input.parse().map_err(|e| {
+                        syn::Error::new(
+                            e.span(),
+                            "expected the backend type, e.g. `backend = diesel::pg::Pg`",
+                        )
+                    })?;
+                    signature.backend = Some(backend);
+                } else {
+                    signature.subquery = Some(input.parse()?);
+                }
+            } else {
+                signature.args.push(input.parse()?);
+            }
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+        Ok(signature)
+    }
+}
+
+struct Argument {
+    name: Ident,
+    ty: Type,
+}
+
+impl Parse for Argument {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name = input.parse()?;
+        input.parse::<Token![:]>()?;
+        let ty = input.parse()?;
+        Ok(Argument { name, ty })
+    }
+}
+
+struct Column {
+    name: Ident,
+    ty: Type,
+    docs: Vec<Attribute>,
+    primary_key: bool,
+}
+
+struct TableFunction {
+    item: DeriveInput,
+    args: Vec<Argument>,
+    backend: TokenStream,
+    subquery: Option<LitStr>,
+    columns: Vec<Column>,
+}
+
+impl TableFunction {
+    fn new(signature: Signature, item: DeriveInput) -> syn::Result<Self> {
+        if !item.generics.params.is_empty() {
+            return Err(syn::Error::new_spanned(
+                &item.generics,
+                "table functions could not be generic",
+            ));
+        }
+        let fields = match &item.data {
+            Data::Struct(s) => match &s.fields {
+                Fields::Named(fields) => fields,
+                _ => {
+                    return Err(syn::Error::new_spanned(
+                        &s.fields,
+                        "expected named fields for the returned columns",
+                    ))
+                }
+            },
+            _ => {
+                return Err(syn::Error::new(
+                    item.ident.span(),
+                    "`#[table_function]` could only be used on structs",
+                ))
+            }
+        };
+
+        let mut columns = Vec::with_capacity(fields.named.len());
+        for field in &fields.named {
+            let mut column = Column {
+                name: field.ident.clone().expect("Fields are named"),
+                ty: field.ty.clone(),
+                docs: Vec::new(),
+                primary_key: false,
+            };
+            for attr in &field.attrs {
+                if attr.path.is_ident("doc") {
+                    column.docs.push(attr.clone());
+                } else if attr.path.is_ident("primary_key") {
+                    if !attr.tokens.is_empty() {
+                        return Err(syn::Error::new_spanned(
+                            &attr.tokens,
+                            "`#[primary_key]` does not accept arguments",
+                        ));
+                    }
+                    column.primary_key = true;
+                } else {
+                    return Err(syn::Error::new_spanned(
+                        attr,
+                        "unsupported attribute, expected `#[primary_key]` or a doc comment",
+                    ));
+                }
+            }
+            if columns.iter().any(|c: &Column| c.name == column.name) {
+                return Err(syn::Error::new(
+                    column.name.span(),
+                    format!("duplicated column `{}`", column.name),
+                ));
+            }
+            columns.push(column);
+        }
+
+        if columns.is_empty() {
+            return Err(syn::Error::new(
+                item.ident.span(),
+                "a table function needs to return at least one column",
+            ));
+        }
+        if !columns.iter().any(|c| c.primary_key) {
+            return Err(syn::Error::new(
+                item.ident.span(),
+                format!(
+                    "`{}` has no primary key, mark the key columns with `#[primary_key]`",
+                    item.ident
+                ),
+            ));
+        }
+
+        let args = signature.args;
+        for (idx, arg) in args.iter().enumerate() {
+            if args[..idx].iter().any(|a| a.name == arg.name) {
+                return Err(syn::Error::new(
+                    arg.name.span(),
+                    format!("duplicated argument `{}`", arg.name),
+                ));
+            }
+        }
+
+        let backend = match signature.backend {
+            Some(backend) => quote!(#backend),
+            None => quote!(crate::diesel_ext::FunctionBackend),
+        };
+
+        Ok(TableFunction {
+            item,
+            args,
+            backend,
+            subquery: signature.subquery,
+            columns,
+        })
+    }
+
+    fn primary_key(&self) -> TokenStream {
+        let keys = self
+            .columns
+            .iter()
+            .filter(|c| c.primary_key)
+            .map(|c| &c.name)
+            .collect::<Vec<_>>();
+        if keys.len() == 1 {
+            let key = keys[0];
+            quote!(columns::#key)
+        } else {
+            quote!((#(columns::#keys,)*))
+        }
+    }
+
+    fn expand(&self) -> TokenStream {
+        let fn_name = &self.item.ident;
+        let vis = &self.item.vis;
+        let attrs = &self.item.attrs;
+        let backend = &self.backend;
+        let primary_key = self.primary_key();
+        let arg_names = self.args.iter().map(|a| &a.name).collect::<Vec<_>>();
+        let arg_fields = self.args.iter().map(|Argument { name, ty }| {
+            quote_spanned! {ty.span()=>
+                #name: std::sync::Arc<
+                    dyn diesel::expression::BoxableExpression<(), #backend, SqlType = #ty>,
+                >
+            }
+        });
+        let arg_bounds = self.args.iter().map(|Argument { name, ty }| {
+            let name = Ident::new(&format!("__{}", name), Span::call_site());
+            quote_spanned! {ty.span()=>
+                #name: crate::diesel_ext::FunctionArgument<#ty, #backend>
+            }
+        });
+        let arg_type_params = self
+            .args
+            .iter()
+            .map(|a| Ident::new(&format!("__{}", a.name), Span::call_site()))
+            .collect::<Vec<_>>();
+        let walk_args = arg_names.iter().enumerate().map(|(idx, name)| {
+            let separator = if idx == 0 {
+                None
+            } else {
+                Some(quote!(pass.push_sql(", ");))
+            };
+            quote! {
+                #separator
+                self.#name.walk_ast(pass.reborrow())?;
+            }
+        });
+        let walk_table = match &self.subquery {
+            Some(subquery) => {
+                let arg_list = arg_names
+                    .iter()
+                    .map(|name| name.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                quote! {
+                    pass.push_sql(concat!("(WITH args(", #arg_list, ") AS (SELECT "));
+                    #(#walk_args)*
+                    pass.push_sql(") ");
+                    pass.push_sql(#subquery);
+                    pass.push_sql(") AS ");
+                }
+            }
+            None => quote! {
+                pass.push_sql(stringify!(#fn_name));
+                pass.push_sql("(");
+                #(#walk_args)*
+                pass.push_sql(") AS ");
+            },
+        };
+        let column_names = self.columns.iter().map(|c| &c.name).collect::<Vec<_>>();
+        let columns = self.columns.iter().map(|Column { name, ty, docs, .. }| {
+            quote! {
+                #(#docs)*
+                #[derive(Debug, Default, Copy, Clone)]
+                #[allow(non_camel_case_types)]
+                pub struct #name;
+
+                impl diesel::expression::Expression for #name {
+                    type SqlType = #ty;
+                }
+
+                impl SelectableExpression<super::#fn_name> for #name {}
+                impl AppearsOnTable<super::#fn_name> for #name {}
+                impl NonAggregate for #name {}
+                impl QueryId for #name {
+                    type QueryId = Self;
+                    const HAS_STATIC_QUERY_ID: bool = true;
+                }
+                impl Column for #name {
+                    type Table = super::#fn_name;
+                    const NAME: &'static str = stringify!(#name);
+                }
+
+                impl<DB: diesel::backend::Backend> QueryFragment<DB> for #name {
+                    fn walk_ast(&self, mut pass: AstPass<DB>) -> diesel::result::QueryResult<()> {
+                        pass.push_identifier(stringify!(#fn_name))?;
+                        pass.push_sql(".");
+                        pass.push_identifier(stringify!(#name))?;
+                        Ok(())
+                    }
+                }
+
+                impl<T> diesel::EqAll<T> for #name
+                where
+                    T: diesel::expression::AsExpression<#ty>,
+                    diesel::dsl::Eq<#name, T>: diesel::Expression<SqlType = diesel::sql_types::Bool>,
+                {
+                    type Output = diesel::dsl::Eq<Self, T>;
+
+                    fn eq_all(self, rhs: T) -> Self::Output {
+                        diesel::expression::operators::Eq::new(self, rhs.as_expression())
+                    }
+                }
+            }
+        });
+
+        quote! {
+            #[allow(dead_code, non_snake_case)]
+            #vis mod #fn_name {
+                use diesel::query_source::*;
+                use diesel::expression::*;
+                use diesel::query_builder::*;
+                use diesel::sql_types::*;
+                use wundergraph::helper::NamedTable;
+
+                #(#attrs)*
+                #[allow(non_camel_case_types)]
+                pub struct #fn_name {
+                    #(#arg_fields,)*
+                }
+
+                #[allow(non_camel_case_types)]
+                pub type table = #fn_name;
+
+                impl Clone for #fn_name {
+                    fn clone(&self) -> Self {
+                        Self {
+                            #(#arg_names: self.#arg_names.clone(),)*
+                        }
+                    }
+                }
+
+                impl Table for #fn_name {
+                    type PrimaryKey = #primary_key;
+                    type AllColumns = (#(columns::#column_names,)*);
+
+                    fn primary_key(&self) -> Self::PrimaryKey {
+                        #primary_key
+                    }
+
+                    fn all_columns() -> Self::AllColumns {
+                        (#(columns::#column_names,)*)
+                    }
+                }
+
+                impl QueryId for #fn_name {
+                    type QueryId = ();
+                    const HAS_STATIC_QUERY_ID: bool = false;
+                }
+
+                impl AppearsInFromClause<#fn_name> for #fn_name {
+                    type Count = Once;
+                }
+
+                impl AppearsInFromClause<#fn_name> for () {
+                    type Count = Never;
+                }
+
+                impl AsQuery for #fn_name {
+                    type SqlType = <<Self as Table>::AllColumns as Expression>::SqlType;
+                    type Query = SelectStatement<#fn_name>;
+
+                    fn as_query(self) -> Self::Query {
+                        SelectStatement::simple(self)
+                    }
+                }
+
+                impl QuerySource for #fn_name {
+                    type FromClause = Self;
+                    type DefaultSelection = <Self as Table>::AllColumns;
+
+                    fn from_clause(&self) -> Self::FromClause {
+                        self.clone()
+                    }
+
+                    fn default_selection(&self) -> Self::DefaultSelection {
+                        Self::all_columns()
+                    }
+                }
+
+                impl diesel::associations::HasTable for #fn_name {
+                    type Table = Self;
+
+                    fn table() -> Self {
+                        Self {
+                            #(#arg_names: std::sync::Arc::new(diesel::dsl::sql("")),)*
+                        }
+                    }
+                }
+
+                impl QueryFragment<#backend> for #fn_name {
+                    fn walk_ast(&self, mut pass: AstPass<#backend>) -> diesel::result::QueryResult<()> {
+                        #walk_table
+                        pass.push_sql(stringify!(#fn_name));
+                        Ok(())
+                    }
+                }
+
+                impl NamedTable for #fn_name {
+                    fn name(&self) -> std::borrow::Cow<'static, str> {
+                        std::borrow::Cow::Borrowed(stringify!(#fn_name))
+                    }
+                }
+
+                mod columns {
+                    use diesel::sql_types::*;
+                    use diesel::prelude::*;
+                    use diesel::expression::NonAggregate;
+                    use diesel::query_builder::{QueryFragment, QueryId, AstPass};
+
+                    #(#columns)*
+                }
+
+                pub use self::columns::*;
+
+                #[allow(non_camel_case_types)]
+                pub(super) mod function {
+                    use diesel::sql_types::*;
+
+                    pub fn #fn_name<#(#arg_type_params,)*>(#(#arg_names: #arg_type_params,)*) -> super::#fn_name
+                    where
+                        #(#arg_bounds,)*
+                    {
+                        super::#fn_name {
+                            #(#arg_names: #arg_names.into_argument(),)*
+                        }
+                    }
+                }
+            }
+
+            #[allow(dead_code)]
+            #vis use self::#fn_name::function::#fn_name;
+        }
+    }
+}
@@ -0,0 +1,6 @@
+use diesel_ext_macros::table_function;
+
+#[table_function(version: Nullable<Integer>)]
+pub struct posts_at_version {}
+
+fn main() {}
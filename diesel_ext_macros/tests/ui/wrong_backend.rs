@@ -0,0 +1,9 @@
+use diesel_ext_macros::table_function;
+
+#[table_function(version: Nullable<Integer>, backend = "diesel::pg::Pg")]
+pub struct posts_at_version {
+    #[primary_key]
+    id: Int4,
+}
+
+fn main() {}
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+
+DROP TRIGGER comments_notify_change ON comments;
+DROP TRIGGER posts_notify_change ON posts;
+DROP FUNCTION notify_comment_change;
+DROP FUNCTION notify_post_change;
@@ -0,0 +1,59 @@
+-- Your SQL goes here
+
+-- Publish changes of posts and comments on the `wundergraph_events` channel.
+-- Notifications are only delivered once the transaction commits.
+
+CREATE OR REPLACE FUNCTION notify_post_change() RETURNS trigger AS $$
+DECLARE
+kind text;
+post_id integer;
+BEGIN
+IF TG_OP = 'INSERT' THEN
+	post_id := NEW.id;
+	-- Updates insert a new version of the post
+	IF NEW.version_start = 0 THEN
+		kind := 'Created';
+	ELSE
+		kind := 'Updated';
+	END IF;
+ELSIF TG_OP = 'UPDATE' THEN
+	-- Closing the current version is always followed by inserting a new one
+	IF OLD.version_end IS NULL AND NEW.version_end IS NOT NULL THEN
+		RETURN NULL;
+	END IF;
+	post_id := NEW.id;
+	kind := 'Updated';
+ELSE
+	post_id := OLD.id;
+	kind := 'Deleted';
+END IF;
+PERFORM pg_notify('wundergraph_events', json_build_object(
+	'entity', 'Post', 'kind', kind, 'id', post_id, 'post', post_id
+)::text);
+RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION notify_comment_change() RETURNS trigger AS $$
+BEGIN
+IF TG_OP = 'DELETE' THEN
+	PERFORM pg_notify('wundergraph_events', json_build_object(
+		'entity', 'Comment', 'kind', 'Deleted', 'id', OLD.id, 'post', OLD.post
+	)::text);
+ELSE
+	PERFORM pg_notify('wundergraph_events', json_build_object(
+		'entity', 'Comment',
+		'kind', CASE WHEN TG_OP = 'INSERT' THEN 'Created' ELSE 'Updated' END,
+		'id', NEW.id,
+		'post', NEW.post
+	)::text);
+END IF;
+RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER posts_notify_change AFTER INSERT OR UPDATE OR DELETE ON posts
+FOR EACH ROW EXECUTE PROCEDURE notify_post_change();
+
+CREATE TRIGGER comments_notify_change AFTER INSERT OR UPDATE OR DELETE ON comments
+FOR EACH ROW EXECUTE PROCEDURE notify_comment_change();
@@ -0,0 +1,24 @@
+-- Your SQL goes here
+
+-- All revisions of all posts. A revision is valid from `version`
+-- until (excluding) `valid_until`, the current one has no end.
+-- `changed_fields` lists the fields that differ from the previous
+-- revision and is empty for the revision that created the post.
+CREATE VIEW post_versions AS
+SELECT posts.id,
+       posts.version_start AS version,
+       posts.version_end AS valid_until,
+       posts.title,
+       posts.content,
+       posts.published_at,
+       posts.author,
+       posts.post_state,
+       CASE WHEN previous.id IS NULL THEN ARRAY[]::text[] ELSE array_remove(ARRAY[
+           CASE WHEN posts.title IS DISTINCT FROM previous.title THEN 'title' END,
+           CASE WHEN posts.content IS DISTINCT FROM previous.content THEN 'content' END,
+           CASE WHEN posts.author IS DISTINCT FROM previous.author THEN 'author' END,
+           CASE WHEN posts.post_state IS DISTINCT FROM previous.post_state THEN 'post_state' END
+       ], NULL) END AS changed_fields
+FROM posts
+LEFT JOIN posts AS previous
+       ON previous.id = posts.id AND previous.version_end = posts.version_start;
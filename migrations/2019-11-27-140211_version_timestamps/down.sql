@@ -0,0 +1,40 @@
+-- This file should undo anything in `up.sql`
+
+DROP FUNCTION posts_at_version(int, timestamptz);
+
+CREATE OR REPLACE FUNCTION posts_at_version (version int DEFAULT NULL)
+RETURNS TABLE(id Integer, title Text, content Text, published_at Timestamp with time zone, author Integer, post_state post_state) AS $$
+DECLARE
+result record;
+BEGIN
+IF version IS NULL THEN
+	RETURN QUERY SELECT posts.id, posts.title, posts.content, posts.published_at, posts.author, posts.post_state FROM posts WHERE projects.version_end IS NULL;
+ELSE
+	RETURN QUERY SELECT posts.id, posts.title, posts.content, posts.published_at, posts.author, posts.post_state FROM posts WHERE int4range(version_start, version_end, '[)') @> version;
+END IF;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP VIEW post_versions;
+
+CREATE VIEW post_versions AS
+SELECT posts.id,
+       posts.version_start AS version,
+       posts.version_end AS valid_until,
+       posts.title,
+       posts.content,
+       posts.published_at,
+       posts.author,
+       posts.post_state,
+       CASE WHEN previous.id IS NULL THEN ARRAY[]::text[] ELSE array_remove(ARRAY[
+           CASE WHEN posts.title IS DISTINCT FROM previous.title THEN 'title' END,
+           CASE WHEN posts.content IS DISTINCT FROM previous.content THEN 'content' END,
+           CASE WHEN posts.author IS DISTINCT FROM previous.author THEN 'author' END,
+           CASE WHEN posts.post_state IS DISTINCT FROM previous.post_state THEN 'post_state' END
+       ], NULL) END AS changed_fields
+FROM posts
+LEFT JOIN posts AS previous
+       ON previous.id = posts.id AND previous.version_end = posts.version_start;
+
+ALTER TABLE posts DROP COLUMN version_end_at;
+ALTER TABLE posts DROP COLUMN version_start_at;
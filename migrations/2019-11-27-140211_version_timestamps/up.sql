@@ -0,0 +1,52 @@
+-- Your SQL goes here
+
+-- Record when each version became valid and when it was replaced
+ALTER TABLE posts ADD COLUMN version_start_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE posts ADD COLUMN version_end_at TIMESTAMP WITH TIME ZONE DEFAULT NULL;
+
+-- Until now every version got a new `published_at` timestamp on insert
+UPDATE posts SET version_start_at = published_at;
+UPDATE posts SET version_end_at = next.published_at
+FROM posts AS next
+WHERE next.id = posts.id AND next.version_start = posts.version_end;
+
+ALTER TABLE posts ALTER COLUMN version_start_at SET NOT NULL;
+ALTER TABLE posts ALTER COLUMN version_start_at SET DEFAULT now();
+
+CREATE OR REPLACE VIEW post_versions AS
+SELECT posts.id,
+       posts.version_start AS version,
+       posts.version_end AS valid_until,
+       posts.title,
+       posts.content,
+       posts.published_at,
+       posts.author,
+       posts.post_state,
+       CASE WHEN previous.id IS NULL THEN ARRAY[]::text[] ELSE array_remove(ARRAY[
+           CASE WHEN posts.title IS DISTINCT FROM previous.title THEN 'title' END,
+           CASE WHEN posts.content IS DISTINCT FROM previous.content THEN 'content' END,
+           CASE WHEN posts.author IS DISTINCT FROM previous.author THEN 'author' END,
+           CASE WHEN posts.post_state IS DISTINCT FROM previous.post_state THEN 'post_state' END
+       ], NULL) END AS changed_fields,
+       posts.version_start_at,
+       posts.version_end_at
+FROM posts
+LEFT JOIN posts AS previous
+       ON previous.id = posts.id AND previous.version_end = posts.version_start;
+
+-- Select posts either by version number or by the point in time they were valid.
+-- Without any of them the current version of all posts is returned.
+DROP FUNCTION posts_at_version(int);
+
+CREATE FUNCTION posts_at_version (version int DEFAULT NULL, as_of timestamptz DEFAULT NULL)
+RETURNS TABLE(id Integer, title Text, content Text, published_at Timestamp with time zone, author Integer, post_state post_state) AS $$
+BEGIN
+IF version IS NULL AND as_of IS NULL THEN
+	RETURN QUERY SELECT posts.id, posts.title, posts.content, posts.published_at, posts.author, posts.post_state FROM posts WHERE posts.version_end IS NULL;
+ELSE
+	RETURN QUERY SELECT posts.id, posts.title, posts.content, posts.published_at, posts.author, posts.post_state FROM posts
+	WHERE (version IS NULL OR int4range(version_start, version_end, '[)') @> version)
+	  AND (as_of IS NULL OR tstzrange(version_start_at, version_end_at, '[)') @> as_of);
+END IF;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,55 @@
+-- This file should undo anything in `up.sql`
+
+CREATE OR REPLACE FUNCTION notify_comment_change() RETURNS trigger AS $$
+BEGIN
+IF TG_OP = 'DELETE' THEN
+	PERFORM pg_notify('wundergraph_events', json_build_object(
+		'entity', 'Comment', 'kind', 'Deleted', 'id', OLD.id, 'post', OLD.post
+	)::text);
+ELSE
+	PERFORM pg_notify('wundergraph_events', json_build_object(
+		'entity', 'Comment',
+		'kind', CASE WHEN TG_OP = 'INSERT' THEN 'Created' ELSE 'Updated' END,
+		'id', NEW.id,
+		'post', NEW.post
+	)::text);
+END IF;
+RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP FUNCTION users_at_version;
+DROP FUNCTION comments_at_version;
+
+CREATE OR REPLACE FUNCTION posts_at_version (version int DEFAULT NULL, as_of timestamptz DEFAULT NULL)
+RETURNS TABLE(id Integer, title Text, content Text, published_at Timestamp with time zone, author Integer, post_state post_state) AS $$
+BEGIN
+IF version IS NULL AND as_of IS NULL THEN
+	RETURN QUERY SELECT posts.id, posts.title, posts.content, posts.published_at, posts.author, posts.post_state FROM posts WHERE posts.version_end IS NULL;
+ELSE
+	RETURN QUERY SELECT posts.id, posts.title, posts.content, posts.published_at, posts.author, posts.post_state FROM posts
+	WHERE (version IS NULL OR int4range(version_start, version_end, '[)') @> version)
+	  AND (as_of IS NULL OR tstzrange(version_start_at, version_end_at, '[)') @> as_of);
+END IF;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP FUNCTION version_is_valid;
+
+DELETE FROM users WHERE version_end IS NOT NULL;
+ALTER TABLE users DROP CONSTRAINT users_pkey;
+ALTER TABLE users ADD PRIMARY KEY (id);
+ALTER TABLE comments ADD FOREIGN KEY (author) REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE;
+ALTER TABLE posts ADD FOREIGN KEY (author) REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE;
+ALTER TABLE users DROP COLUMN version_end_at;
+ALTER TABLE users DROP COLUMN version_start_at;
+ALTER TABLE users DROP COLUMN version_end;
+ALTER TABLE users DROP COLUMN version_start;
+
+DELETE FROM comments WHERE version_end IS NOT NULL;
+ALTER TABLE comments DROP CONSTRAINT comments_pkey;
+ALTER TABLE comments ADD PRIMARY KEY (id);
+ALTER TABLE comments DROP COLUMN version_end_at;
+ALTER TABLE comments DROP COLUMN version_start_at;
+ALTER TABLE comments DROP COLUMN version_end;
+ALTER TABLE comments DROP COLUMN version_start;
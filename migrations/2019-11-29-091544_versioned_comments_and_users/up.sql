@@ -0,0 +1,83 @@
+-- Your SQL goes here
+
+-- Keep older versions of comments and users in the same way as for posts
+ALTER TABLE comments ADD COLUMN version_start INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE comments ADD COLUMN version_end INTEGER DEFAULT NULL;
+ALTER TABLE comments ADD COLUMN version_start_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now();
+ALTER TABLE comments ADD COLUMN version_end_at TIMESTAMP WITH TIME ZONE DEFAULT NULL;
+UPDATE comments SET version_start_at = published_at;
+ALTER TABLE comments DROP CONSTRAINT comments_pkey;
+ALTER TABLE comments ADD PRIMARY KEY (id, version_start);
+
+ALTER TABLE users ADD COLUMN version_start INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN version_end INTEGER DEFAULT NULL;
+ALTER TABLE users ADD COLUMN version_start_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now();
+ALTER TABLE users ADD COLUMN version_end_at TIMESTAMP WITH TIME ZONE DEFAULT NULL;
+UPDATE users SET version_start_at = joined_at;
+ALTER TABLE posts DROP CONSTRAINT posts_author_fkey;
+ALTER TABLE comments DROP CONSTRAINT comments_author_fkey;
+ALTER TABLE users DROP CONSTRAINT users_pkey;
+ALTER TABLE users ADD PRIMARY KEY (id, version_start);
+
+-- Shared by all `*_at_version` functions
+--
+-- Selects the version with the given number, the version valid at `as_of`
+-- or both. Without any of them the current version is selected.
+CREATE FUNCTION version_is_valid (
+	version_start int, version_end int,
+	version_start_at timestamptz, version_end_at timestamptz,
+	version int, as_of timestamptz
+) RETURNS boolean AS $$
+SELECT CASE WHEN version IS NULL AND as_of IS NULL THEN version_end IS NULL
+ELSE (version IS NULL OR int4range(version_start, version_end, '[)') @> version)
+	AND (as_of IS NULL OR tstzrange(version_start_at, version_end_at, '[)') @> as_of)
+END
+$$ LANGUAGE sql IMMUTABLE;
+
+CREATE OR REPLACE FUNCTION posts_at_version (version int DEFAULT NULL, as_of timestamptz DEFAULT NULL)
+RETURNS TABLE(id Integer, title Text, content Text, published_at Timestamp with time zone, author Integer, post_state post_state) AS $$
+SELECT posts.id, posts.title, posts.content, posts.published_at, posts.author, posts.post_state FROM posts
+WHERE version_is_valid(posts.version_start, posts.version_end, posts.version_start_at, posts.version_end_at, version, as_of)
+$$ LANGUAGE sql STABLE;
+
+CREATE FUNCTION comments_at_version (version int DEFAULT NULL, as_of timestamptz DEFAULT NULL)
+RETURNS TABLE(id Integer, comment Text, published_at Timestamp with time zone, author Integer, post Integer) AS $$
+SELECT comments.id, comments.comment, comments.published_at, comments.author, comments.post FROM comments
+WHERE version_is_valid(comments.version_start, comments.version_end, comments.version_start_at, comments.version_end_at, version, as_of)
+$$ LANGUAGE sql STABLE;
+
+CREATE FUNCTION users_at_version (version int DEFAULT NULL, as_of timestamptz DEFAULT NULL)
+RETURNS TABLE(id Integer, name Text, joined_at Timestamp with time zone) AS $$
+SELECT users.id, users.name, users.joined_at FROM users
+WHERE version_is_valid(users.version_start, users.version_end, users.version_start_at, users.version_end_at, version, as_of)
+$$ LANGUAGE sql STABLE;
+
+-- Updating a comment now closes the current version and inserts a new one
+CREATE OR REPLACE FUNCTION notify_comment_change() RETURNS trigger AS $$
+DECLARE
+kind text;
+changed comments;
+BEGIN
+IF TG_OP = 'INSERT' THEN
+	changed := NEW;
+	IF NEW.version_start = 0 THEN
+		kind := 'Created';
+	ELSE
+		kind := 'Updated';
+	END IF;
+ELSIF TG_OP = 'UPDATE' THEN
+	IF OLD.version_end IS NULL AND NEW.version_end IS NOT NULL THEN
+		RETURN NULL;
+	END IF;
+	changed := NEW;
+	kind := 'Updated';
+ELSE
+	changed := OLD;
+	kind := 'Deleted';
+END IF;
+PERFORM pg_notify('wundergraph_events', json_build_object(
+	'entity', 'Comment', 'kind', kind, 'id', changed.id, 'post', changed.post
+)::text);
+RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+
+DROP TRIGGER users_remove_roles ON users;
+DROP TRIGGER users_grant_default_role ON users;
+DROP FUNCTION manage_user_roles();
+DROP TABLE user_roles;
+DROP TYPE user_role;
@@ -0,0 +1,37 @@
+-- Your SQL goes here
+
+CREATE TYPE user_role AS ENUM ('Reader', 'Author', 'Moderator', 'Admin');
+
+-- Users are versioned, so the roles are kept in their own table
+-- and are shared by all versions of a user
+CREATE TABLE user_roles (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    role user_role NOT NULL,
+    UNIQUE (user_id, role)
+);
+
+-- Existing users keep being able to write posts
+INSERT INTO user_roles (user_id, role)
+SELECT DISTINCT users.id, roles.role
+FROM users, unnest(ARRAY['Reader', 'Author']::user_role[]) AS roles(role);
+
+-- New users are readers, the remaining roles are granted by admins.
+-- Roles are removed together with the last version of a user.
+CREATE FUNCTION manage_user_roles() RETURNS trigger AS $$
+BEGIN
+IF TG_OP = 'INSERT' THEN
+	INSERT INTO user_roles (user_id, role) VALUES (NEW.id, 'Reader')
+	ON CONFLICT DO NOTHING;
+ELSIF NOT EXISTS (SELECT 1 FROM users WHERE users.id = OLD.id) THEN
+	DELETE FROM user_roles WHERE user_id = OLD.id;
+END IF;
+RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER users_grant_default_role AFTER INSERT ON users
+FOR EACH ROW WHEN (NEW.version_start = 0) EXECUTE PROCEDURE manage_user_roles();
+
+CREATE TRIGGER users_remove_roles AFTER DELETE ON users
+FOR EACH ROW EXECUTE PROCEDURE manage_user_roles();
@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+
+DROP TRIGGER users_delete_author ON users;
+DROP TRIGGER users_close_author ON users;
+DROP TRIGGER comments_author_exists ON comments;
+DROP TRIGGER posts_author_exists ON posts;
+DROP FUNCTION check_author_unreferenced();
+DROP FUNCTION check_author_exists();
@@ -0,0 +1,50 @@
+-- Your SQL goes here
+
+-- The foreign keys of `author` were dropped together with the primary key of
+-- users, as the id is shared by all versions of a user. Constraint triggers
+-- check them instead: the current version of a post or comment must be written
+-- by a user with a current version, and such a user can not be removed.
+-- Updates close a version before inserting the next one, so the checks are
+-- deferred to the end of the transaction.
+CREATE FUNCTION check_author_exists() RETURNS trigger AS $$
+BEGIN
+IF NEW.version_end IS NULL AND NOT EXISTS (
+	SELECT 1 FROM users WHERE users.id = NEW.author AND users.version_end IS NULL
+) THEN
+	RAISE EXCEPTION 'author % of % % does not exist', NEW.author, TG_TABLE_NAME, NEW.id
+	USING ERRCODE = 'foreign_key_violation', CONSTRAINT = TG_TABLE_NAME || '_author_fkey';
+END IF;
+RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE FUNCTION check_author_unreferenced() RETURNS trigger AS $$
+BEGIN
+IF NOT EXISTS (SELECT 1 FROM users WHERE users.id = OLD.id AND users.version_end IS NULL)
+	AND (EXISTS (SELECT 1 FROM posts WHERE posts.author = OLD.id AND posts.version_end IS NULL)
+	OR EXISTS (SELECT 1 FROM comments WHERE comments.author = OLD.id AND comments.version_end IS NULL))
+THEN
+	RAISE EXCEPTION 'user % is the author of posts or comments', OLD.id
+	USING ERRCODE = 'foreign_key_violation', CONSTRAINT = 'users_author_fkey';
+END IF;
+RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE CONSTRAINT TRIGGER posts_author_exists AFTER INSERT OR UPDATE OF author ON posts
+DEFERRABLE INITIALLY DEFERRED
+FOR EACH ROW EXECUTE PROCEDURE check_author_exists();
+
+CREATE CONSTRAINT TRIGGER comments_author_exists AFTER INSERT OR UPDATE OF author ON comments
+DEFERRABLE INITIALLY DEFERRED
+FOR EACH ROW EXECUTE PROCEDURE check_author_exists();
+
+CREATE CONSTRAINT TRIGGER users_close_author AFTER UPDATE ON users
+DEFERRABLE INITIALLY DEFERRED
+FOR EACH ROW WHEN (OLD.version_end IS NULL AND NEW.version_end IS NOT NULL)
+EXECUTE PROCEDURE check_author_unreferenced();
+
+CREATE CONSTRAINT TRIGGER users_delete_author AFTER DELETE ON users
+DEFERRABLE INITIALLY DEFERRED
+FOR EACH ROW WHEN (OLD.version_end IS NULL)
+EXECUTE PROCEDURE check_author_unreferenced();
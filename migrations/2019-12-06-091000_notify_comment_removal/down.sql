@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+
+DROP TRIGGER comments_notify_removal ON comments;
+DROP FUNCTION notify_comment_removal();
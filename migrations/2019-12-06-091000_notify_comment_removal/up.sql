@@ -0,0 +1,20 @@
+-- Your SQL goes here
+
+-- Removing a comment only closes its current version. `notify_comment_change`
+-- ignores closed versions as updates insert the next version afterwards, so
+-- whether the comment was removed is only known at the end of the transaction.
+CREATE FUNCTION notify_comment_removal() RETURNS trigger AS $$
+BEGIN
+IF NOT EXISTS (SELECT 1 FROM comments WHERE comments.id = OLD.id AND comments.version_end IS NULL) THEN
+	PERFORM pg_notify('wundergraph_events', json_build_object(
+		'entity', 'Comment', 'kind', 'Deleted', 'id', OLD.id, 'post', OLD.post
+	)::text);
+END IF;
+RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE CONSTRAINT TRIGGER comments_notify_removal AFTER UPDATE ON comments
+DEFERRABLE INITIALLY DEFERRED
+FOR EACH ROW WHEN (OLD.version_end IS NULL AND NEW.version_end IS NOT NULL)
+EXECUTE PROCEDURE notify_comment_removal();
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+
+UPDATE posts SET published_at = version_start_at WHERE published_at IS NULL;
+
+ALTER TABLE posts ALTER COLUMN published_at SET NOT NULL;
+ALTER TABLE posts ALTER COLUMN published_at SET DEFAULT now();
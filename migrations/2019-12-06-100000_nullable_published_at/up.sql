@@ -0,0 +1,14 @@
+-- Your SQL goes here
+
+-- `published_at` is only set by publishing a post, drafts that were never
+-- published have none. Until now it defaulted to the time of the insert.
+ALTER TABLE posts ALTER COLUMN published_at DROP DEFAULT;
+ALTER TABLE posts ALTER COLUMN published_at DROP NOT NULL;
+
+UPDATE posts SET published_at = NULL
+WHERE NOT EXISTS (
+	SELECT 1 FROM posts AS earlier
+	WHERE earlier.id = posts.id
+	AND earlier.version_start <= posts.version_start
+	AND earlier.post_state = 'Published'
+);
@@ -0,0 +1,25 @@
+-- This file should undo anything in `up.sql`
+
+DROP VIEW post_versions;
+CREATE VIEW post_versions AS
+SELECT posts.id,
+       posts.version_start AS version,
+       posts.version_end AS valid_until,
+       posts.title,
+       posts.content,
+       posts.published_at,
+       posts.author,
+       posts.post_state,
+       CASE WHEN previous.id IS NULL THEN ARRAY[]::text[] ELSE array_remove(ARRAY[
+           CASE WHEN posts.title IS DISTINCT FROM previous.title THEN 'title' END,
+           CASE WHEN posts.content IS DISTINCT FROM previous.content THEN 'content' END,
+           CASE WHEN posts.author IS DISTINCT FROM previous.author THEN 'author' END,
+           CASE WHEN posts.post_state IS DISTINCT FROM previous.post_state THEN 'post_state' END
+       ], NULL) END AS changed_fields,
+       posts.version_start_at,
+       posts.version_end_at
+FROM posts
+LEFT JOIN posts AS previous
+       ON previous.id = posts.id AND previous.version_end = posts.version_start;
+
+ALTER TABLE posts DROP COLUMN editor;
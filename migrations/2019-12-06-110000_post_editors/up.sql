@@ -0,0 +1,21 @@
+-- Your SQL goes here
+
+-- The user who wrote a version of a post, unknown for older versions.
+-- `changed_fields` is computed by the service instead of the view,
+-- so that the view is the same with postgres and SQLite.
+ALTER TABLE posts ADD COLUMN editor INTEGER DEFAULT NULL;
+
+DROP VIEW post_versions;
+CREATE VIEW post_versions AS
+SELECT posts.id,
+       posts.version_start AS version,
+       posts.version_end AS valid_until,
+       posts.title,
+       posts.content,
+       posts.published_at,
+       posts.author,
+       posts.post_state,
+       posts.editor,
+       posts.version_start_at,
+       posts.version_end_at
+FROM posts;
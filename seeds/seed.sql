@@ -0,0 +1,26 @@
+-- Example data for local development, loaded by the `seed` subcommand.
+-- Works with postgres and SQLite, new users are readers (see the
+-- `user_roles` migration).
+
+INSERT INTO users (name) VALUES ('alice'), ('bob'), ('carol');
+
+INSERT INTO user_roles (user_id, role)
+SELECT id, 'Admin' FROM users WHERE name = 'alice';
+
+INSERT INTO user_roles (user_id, role)
+SELECT id, 'Author' FROM users WHERE name = 'bob';
+
+INSERT INTO posts (title, content, author, post_state, published_at)
+SELECT 'Hello Barcelona', 'Welcome to the workshop', id, 'Published', CURRENT_TIMESTAMP
+FROM users WHERE name = 'bob';
+
+INSERT INTO posts (title, content, author, post_state)
+SELECT 'Unfinished thoughts', NULL, id, 'Draft' FROM users WHERE name = 'bob';
+
+INSERT INTO comments (comment, author, post)
+SELECT 'Nice post!', users.id, posts.id FROM users, posts
+WHERE users.name = 'carol' AND posts.title = 'Hello Barcelona';
+
+INSERT INTO comments (comment, author, post)
+SELECT 'Thanks', users.id, posts.id FROM users, posts
+WHERE users.name = 'bob' AND posts.title = 'Hello Barcelona';
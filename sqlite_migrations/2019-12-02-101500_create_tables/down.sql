@@ -0,0 +1,4 @@
+DROP VIEW post_versions;
+DROP TABLE comments;
+DROP TABLE posts;
+DROP TABLE users;
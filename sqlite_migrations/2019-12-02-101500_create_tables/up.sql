@@ -0,0 +1,73 @@
+-- The SQLite schema, it matches the schema built by the postgres migrations.
+-- Timestamps are stored as UTC, `post_state` as text.
+
+-- Versioned tables have a composite primary key and no autoincrement,
+-- new rows get their rowid as id
+CREATE TABLE users (
+    id INTEGER,
+    name TEXT NOT NULL,
+    joined_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    version_start INTEGER NOT NULL DEFAULT 0,
+    version_end INTEGER DEFAULT NULL,
+    version_start_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    version_end_at TIMESTAMP DEFAULT NULL,
+    PRIMARY KEY (id, version_start)
+);
+
+CREATE TRIGGER users_assign_id AFTER INSERT ON users WHEN NEW.id IS NULL
+BEGIN
+    UPDATE users SET id = NEW.rowid WHERE rowid = NEW.rowid;
+END;
+
+CREATE TABLE posts (
+    id INTEGER,
+    title TEXT NOT NULL,
+    content TEXT,
+    published_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    author INTEGER NOT NULL,
+    post_state TEXT NOT NULL DEFAULT 'Published'
+        CHECK (post_state IN ('Draft', 'Published', 'Deleted')),
+    version_start INTEGER NOT NULL DEFAULT 0,
+    version_end INTEGER DEFAULT NULL,
+    version_start_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    version_end_at TIMESTAMP DEFAULT NULL,
+    PRIMARY KEY (id, version_start)
+);
+
+CREATE TRIGGER posts_assign_id AFTER INSERT ON posts WHEN NEW.id IS NULL
+BEGIN
+    UPDATE posts SET id = NEW.rowid WHERE rowid = NEW.rowid;
+END;
+
+CREATE TABLE comments (
+    id INTEGER,
+    comment TEXT,
+    published_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    author INTEGER NOT NULL,
+    post INTEGER NOT NULL,
+    version_start INTEGER NOT NULL DEFAULT 0,
+    version_end INTEGER DEFAULT NULL,
+    version_start_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    version_end_at TIMESTAMP DEFAULT NULL,
+    PRIMARY KEY (id, version_start)
+);
+
+CREATE TRIGGER comments_assign_id AFTER INSERT ON comments WHEN NEW.id IS NULL
+BEGIN
+    UPDATE comments SET id = NEW.rowid WHERE rowid = NEW.rowid;
+END;
+
+-- All revisions of all posts, see the `post_versions` postgres migration.
+-- SQLite has no arrays, so there are no `changed_fields`.
+CREATE VIEW post_versions AS
+SELECT posts.id,
+       posts.version_start AS version,
+       posts.version_end AS valid_until,
+       posts.title,
+       posts.content,
+       posts.published_at,
+       posts.author,
+       posts.post_state,
+       posts.version_start_at,
+       posts.version_end_at
+FROM posts;
@@ -0,0 +1,3 @@
+DROP TRIGGER users_remove_roles;
+DROP TRIGGER users_grant_default_role;
+DROP TABLE user_roles;
@@ -0,0 +1,26 @@
+-- See the `user_roles` postgres migration, `role` is stored as text
+
+CREATE TABLE user_roles (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    role TEXT NOT NULL CHECK (role IN ('Reader', 'Author', 'Moderator', 'Admin')),
+    UNIQUE (user_id, role)
+);
+
+INSERT INTO user_roles (user_id, role)
+SELECT DISTINCT users.id, roles.role
+FROM users, (SELECT 'Reader' AS role UNION ALL SELECT 'Author') AS roles;
+
+-- New rows only get their id assigned by `users_assign_id`
+CREATE TRIGGER users_grant_default_role AFTER INSERT ON users WHEN NEW.version_start = 0
+BEGIN
+    INSERT OR IGNORE INTO user_roles (user_id, role)
+    VALUES (COALESCE(NEW.id, NEW.rowid), 'Reader');
+END;
+
+-- Row ids of deleted users could be reused by new users
+CREATE TRIGGER users_remove_roles AFTER DELETE ON users
+WHEN NOT EXISTS (SELECT 1 FROM users WHERE id = OLD.id)
+BEGIN
+    DELETE FROM user_roles WHERE user_id = OLD.id;
+END;
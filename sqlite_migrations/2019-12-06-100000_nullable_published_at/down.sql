@@ -0,0 +1,43 @@
+DROP VIEW post_versions;
+
+CREATE TABLE old_posts (
+    id INTEGER,
+    title TEXT NOT NULL,
+    content TEXT,
+    published_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    author INTEGER NOT NULL,
+    post_state TEXT NOT NULL DEFAULT 'Published'
+        CHECK (post_state IN ('Draft', 'Published', 'Deleted')),
+    version_start INTEGER NOT NULL DEFAULT 0,
+    version_end INTEGER DEFAULT NULL,
+    version_start_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    version_end_at TIMESTAMP DEFAULT NULL,
+    PRIMARY KEY (id, version_start)
+);
+
+INSERT INTO old_posts (rowid, id, title, content, published_at, author, post_state,
+                       version_start, version_end, version_start_at, version_end_at)
+SELECT rowid, id, title, content, COALESCE(published_at, version_start_at), author,
+       post_state, version_start, version_end, version_start_at, version_end_at
+FROM posts;
+
+DROP TABLE posts;
+ALTER TABLE old_posts RENAME TO posts;
+
+CREATE TRIGGER posts_assign_id AFTER INSERT ON posts WHEN NEW.id IS NULL
+BEGIN
+    UPDATE posts SET id = NEW.rowid WHERE rowid = NEW.rowid;
+END;
+
+CREATE VIEW post_versions AS
+SELECT posts.id,
+       posts.version_start AS version,
+       posts.version_end AS valid_until,
+       posts.title,
+       posts.content,
+       posts.published_at,
+       posts.author,
+       posts.post_state,
+       posts.version_start_at,
+       posts.version_end_at
+FROM posts;
@@ -0,0 +1,18 @@
+-- See the `post_editors` postgres migration
+
+ALTER TABLE posts ADD COLUMN editor INTEGER DEFAULT NULL;
+
+DROP VIEW post_versions;
+CREATE VIEW post_versions AS
+SELECT posts.id,
+       posts.version_start AS version,
+       posts.version_end AS valid_until,
+       posts.title,
+       posts.content,
+       posts.published_at,
+       posts.author,
+       posts.post_state,
+       posts.editor,
+       posts.version_start_at,
+       posts.version_end_at
+FROM posts;
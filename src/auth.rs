@@ -0,0 +1,207 @@
+//! Authentication with signed bearer tokens
+//!
+//! Clients send a HS256 signed JWT in the `Authorization: Bearer <token>`
+//! header. Tokens are issued elsewhere with the same signing key, the `sub`
+//! claim contains the id of the authenticated user as string and `exp` is
+//! required.
+//!
+//! Browsers cannot set headers on websockets, so subscription clients
+//! could send the token in the `connection_init` message instead.
+//!
+//! Requests without a token are anonymous, invalid tokens are rejected
+//! with 401. Handlers that need to know the caller take an `Identity`
+//! argument, which rejects anonymous requests with 401 as well.
+
+use crate::error::ApiError;
+use actix_web::dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderMap, AUTHORIZATION};
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest};
+use futures::future::{self, Either, FutureResult};
+use futures::Poll;
+use jsonwebtoken::{Algorithm, Validation};
+use serde::Deserialize;
+use std::sync::Arc;
+
+/// The authenticated caller of a request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Identity {
+    pub user_id: i32,
+}
+
+#[derive(Debug, Deserialize)]
+struct Claims {
+    sub: Subject,
+}
+
+/// The `sub` claim, a string as of RFC 7519,
+/// numbers are accepted for older tokens
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Subject {
+    Text(String),
+    Number(i32),
+}
+
+impl Subject {
+    fn user_id(&self) -> Result<i32, ApiError> {
+        match self {
+            Subject::Text(sub) => sub.parse().map_err(|_| {
+                ApiError::unauthenticated(format!(
+                    "Invalid token: `sub` {:?} is not a user id",
+                    sub
+                ))
+            }),
+            Subject::Number(id) => Ok(*id),
+        }
+    }
+}
+
+impl FromRequest for Identity {
+    type Error = Error;
+    type Future = Result<Self, Error>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        req.extensions()
+            .get::<Identity>()
+            .copied()
+            .ok_or_else(|| ApiError::unauthenticated("Authentication required").into())
+    }
+}
+
+/// Middleware checking the bearer token of each request
+///
+/// The identity of authenticated requests is stored
+/// in the request extensions
+#[derive(Clone)]
+pub struct Authentication {
+    key: Arc<Vec<u8>>,
+}
+
+impl Authentication {
+    pub fn new(key: &str) -> Self {
+        Authentication {
+            key: Arc::new(key.as_bytes().to_vec()),
+        }
+    }
+
+    fn authenticate(&self, headers: &HeaderMap) -> Result<Option<Identity>, Error> {
+        let header = match headers.get(AUTHORIZATION) {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+        let header = header
+            .to_str()
+            .map_err(|_| ApiError::unauthenticated("Expected a bearer token"))?;
+        Ok(Some(self.verify_bearer(header)?))
+    }
+
+    /// Verify a `Bearer <token>` credential
+    pub fn verify_bearer(&self, credential: &str) -> Result<Identity, ApiError> {
+        let token = credential
+            .strip_prefix("Bearer ")
+            .ok_or_else(|| ApiError::unauthenticated("Expected a bearer token"))?;
+        self.verify(token)
+    }
+
+    /// Verify a token and return the identity it was issued for
+    pub fn verify(&self, token: &str) -> Result<Identity, ApiError> {
+        let token = jsonwebtoken::decode::<Claims>(
+            token.trim(),
+            &self.key,
+            &Validation::new(Algorithm::HS256),
+        )
+        .map_err(|e| ApiError::unauthenticated(format!("Invalid token: {}", e)))?;
+        Ok(Identity {
+            user_id: token.claims.sub.user_id()?,
+        })
+    }
+}
+
+impl<S, B> Transform<S> for Authentication
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AuthenticationMiddleware<S>;
+    type Future = FutureResult<Self::Transform, Self::InitError>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        future::ok(AuthenticationMiddleware {
+            service,
+            authentication: self.clone(),
+        })
+    }
+}
+
+pub struct AuthenticationMiddleware<S> {
+    service: S,
+    authentication: Authentication,
+}
+
+impl<S, B> Service for AuthenticationMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<S::Future, FutureResult<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.service.poll_ready()
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        match self.authentication.authenticate(req.headers()) {
+            Ok(identity) => {
+                if let Some(identity) = identity {
+                    req.extensions_mut().insert(identity);
+                }
+                Either::A(self.service.call(req))
+            }
+            Err(e) => Either::B(future::ok(req.error_response(e))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::Header;
+    use serde_json::json;
+
+    const KEY: &str = "secret";
+
+    fn token(sub: serde_json::Value) -> String {
+        // Far in the future, the tokens of the tests do not expire
+        let claims = json!({"sub": sub, "exp": 4_000_000_000u64});
+        jsonwebtoken::encode(&Header::default(), &claims, KEY.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn subjects_are_user_ids() {
+        let authentication = Authentication::new(KEY);
+        let expected = Identity { user_id: 42 };
+        assert_eq!(
+            authentication.verify(&token(json!("42"))).ok(),
+            Some(expected)
+        );
+        assert_eq!(
+            authentication.verify(&token(json!(42))).ok(),
+            Some(expected)
+        );
+    }
+
+    #[test]
+    fn other_subjects_are_unauthenticated() {
+        let authentication = Authentication::new(KEY);
+        for sub in &[json!("alice"), json!(""), json!("4.2"), json!(4.2)] {
+            let e = authentication.verify(&token(sub.clone())).unwrap_err();
+            assert_eq!(e.code(), "UNAUTHENTICATED", "{}", sub);
+        }
+    }
+}
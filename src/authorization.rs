@@ -0,0 +1,259 @@
+//! Rules for creating and changing content
+//!
+//! Creating rows may require a role, see `Owned::CREATE_ROLE`. Rows
+//! may only be changed by their owner, they may be deleted by their
+//! owner and users with `Owned::DELETE_ROLE`. Only admins may create or
+//! change rows in the name of another user, see `Authored`. Admins may do
+//! everything.
+//! The rules are shared by the REST handlers (see `Authorized` and
+//! `Admin`) and the graphql mutations.
+
+use crate::auth::Identity;
+use crate::db::DbConnection;
+use crate::error::ApiError;
+use crate::model::users::{has_role, Role};
+use crate::schema::{comments, posts, users};
+use crate::AppState;
+use actix_web::dev::Payload;
+use actix_web::http::{Method, StatusCode};
+use actix_web::{FromRequest, HttpRequest};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use std::fmt;
+use std::marker::PhantomData;
+
+/// A table containing rows owned by a user
+pub trait Owned {
+    /// The role required to create rows, `None`
+    /// if every authenticated user may create them
+    const CREATE_ROLE: Option<Role>;
+    /// The role allowing to delete rows of other users
+    const DELETE_ROLE: Role;
+
+    /// Load the id of the user owning the current version
+    /// of the row with the given id
+    ///
+    /// Returns `None` if there is no such row
+    fn owner(conn: &DbConnection, id: i32) -> QueryResult<Option<i32>>;
+}
+
+impl Owned for posts::table {
+    const CREATE_ROLE: Option<Role> = Some(Role::Author);
+    const DELETE_ROLE: Role = Role::Admin;
+
+    fn owner(conn: &DbConnection, id: i32) -> QueryResult<Option<i32>> {
+        posts::table
+            .select(posts::author)
+            .filter(posts::id.eq(id))
+            .filter(posts::version_end.is_null())
+            .first(conn)
+            .optional()
+    }
+}
+
+impl Owned for comments::table {
+    const CREATE_ROLE: Option<Role> = Some(Role::Reader);
+    const DELETE_ROLE: Role = Role::Moderator;
+
+    fn owner(conn: &DbConnection, id: i32) -> QueryResult<Option<i32>> {
+        comments::table
+            .select(comments::author)
+            .filter(comments::id.eq(id))
+            .filter(comments::version_end.is_null())
+            .first(conn)
+            .optional()
+    }
+}
+
+/// Users own their own account
+impl Owned for users::table {
+    const CREATE_ROLE: Option<Role> = None;
+    const DELETE_ROLE: Role = Role::Admin;
+
+    fn owner(conn: &DbConnection, id: i32) -> QueryResult<Option<i32>> {
+        users::table
+            .select(users::id)
+            .filter(users::id.eq(id))
+            .filter(users::version_end.is_null())
+            .first(conn)
+            .optional()
+    }
+}
+
+/// Input naming the user a row is created or changed for
+pub trait Authored {
+    /// The author of the row, `None` if the input keeps the current author
+    fn author(&self) -> Option<i32>;
+}
+
+/// The kind of access to a table
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Create,
+    Change(i32),
+    Delete(i32),
+}
+
+#[derive(Debug)]
+pub enum AuthorizationError {
+    /// The caller does not have the role
+    MissingRole(Role),
+    /// The caller neither owns the row nor has the role
+    NotOwner(Role),
+    /// The caller names another user as author without being an admin
+    NotAuthor,
+    Database(diesel::result::Error),
+}
+
+impl fmt::Display for AuthorizationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthorizationError::MissingRole(role) => write!(f, "Requires the {:?} role", role),
+            AuthorizationError::NotOwner(role) => write!(
+                f,
+                "Only the author or users with the {:?} role may do this",
+                role
+            ),
+            AuthorizationError::NotAuthor => {
+                write!(f, "Only admins may name another user as author")
+            }
+            AuthorizationError::Database(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<diesel::result::Error> for AuthorizationError {
+    fn from(e: diesel::result::Error) -> Self {
+        AuthorizationError::Database(e)
+    }
+}
+
+impl From<AuthorizationError> for ApiError {
+    fn from(e: AuthorizationError) -> Self {
+        match e {
+            AuthorizationError::MissingRole(_)
+            | AuthorizationError::NotOwner(_)
+            | AuthorizationError::NotAuthor => {
+                ApiError::new(StatusCode::FORBIDDEN, "FORBIDDEN", e.to_string())
+            }
+            AuthorizationError::Database(e) => e.into(),
+        }
+    }
+}
+
+/// Checks whether the caller has the given role
+pub fn require_role(
+    conn: &DbConnection,
+    identity: Identity,
+    role: Role,
+) -> Result<(), AuthorizationError> {
+    if has_role(conn, identity.user_id, role)? {
+        Ok(())
+    } else {
+        Err(AuthorizationError::MissingRole(role))
+    }
+}
+
+/// Checks whether the caller may access the rows of `T`
+///
+/// Missing rows are not checked, so the caller
+/// reports them as it would without this check.
+pub fn authorize<T: Owned>(
+    conn: &DbConnection,
+    identity: Identity,
+    action: Action,
+) -> Result<(), AuthorizationError> {
+    let (id, role) = match action {
+        Action::Create => {
+            return match T::CREATE_ROLE {
+                Some(role) => require_role(conn, identity, role),
+                None => Ok(()),
+            };
+        }
+        Action::Change(id) => (id, Role::Admin),
+        Action::Delete(id) => (id, T::DELETE_ROLE),
+    };
+    match T::owner(conn, id)? {
+        Some(owner) if owner != identity.user_id && !has_role(conn, identity.user_id, role)? => {
+            Err(AuthorizationError::NotOwner(role))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Checks whether the caller may create or change a row with
+/// the author given by `input`, see `Authored`
+pub fn authorize_author(
+    conn: &DbConnection,
+    identity: Identity,
+    input: &impl Authored,
+) -> Result<(), AuthorizationError> {
+    match input.author() {
+        Some(author)
+            if author != identity.user_id && !has_role(conn, identity.user_id, Role::Admin)? =>
+        {
+            Err(AuthorizationError::NotAuthor)
+        }
+        _ => Ok(()),
+    }
+}
+
+fn connection(
+    req: &HttpRequest,
+) -> Result<PooledConnection<ConnectionManager<DbConnection>>, ApiError> {
+    Ok(req
+        .app_data::<AppState>()
+        .expect("AppData set")
+        .pool
+        .get()?)
+}
+
+/// Extractor checking that the caller may access the rows of `T`
+///
+/// Requests without an `id` segment in their path create a row, otherwise
+/// `DELETE` requests delete the row with that id and all other requests
+/// change it. Rejects anonymous requests with 401 and others with 403,
+/// unparsable ids with 404.
+#[derive(Debug)]
+pub struct Authorized<T>(PhantomData<T>);
+
+impl<T: Owned> FromRequest for Authorized<T> {
+    type Error = actix_web::Error;
+    type Future = Result<Self, actix_web::Error>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let identity = Identity::from_request(req, payload)?;
+        let action = match req.match_info().get("id") {
+            None => Action::Create,
+            Some(id) => {
+                let id = id.parse().map_err(|_| ApiError::not_found())?;
+                if req.method() == Method::DELETE {
+                    Action::Delete(id)
+                } else {
+                    Action::Change(id)
+                }
+            }
+        };
+        let conn = connection(req)?;
+        authorize::<T>(&conn, identity, action).map_err(ApiError::from)?;
+        Ok(Authorized(PhantomData))
+    }
+}
+
+/// Extractor rejecting requests of users that are not admins
+#[derive(Debug)]
+pub struct Admin;
+
+impl FromRequest for Admin {
+    type Error = actix_web::Error;
+    type Future = Result<Self, actix_web::Error>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let identity = Identity::from_request(req, payload)?;
+        let conn = connection(req)?;
+        require_role(&conn, identity, Role::Admin).map_err(ApiError::from)?;
+        Ok(Admin)
+    }
+}
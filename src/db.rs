@@ -0,0 +1,276 @@
+//! The database backend
+//!
+//! Postgres is used by default. With the `sqlite` feature the service runs
+//! against a single SQLite database file instead, build it with
+//! `cargo build --no-default-features --features sqlite`.
+//!
+//! Everything that differs between both backends is collected here,
+//! the rest of the service only refers to the types of this module.
+
+use diesel::connection::SimpleConnection;
+use diesel::insertable::Insertable;
+use diesel::prelude::*;
+use diesel::query_builder::InsertStatement;
+use diesel::query_dsl::methods::LoadQuery;
+use diesel::r2d2::{ConnectionManager, Pool};
+#[cfg(feature = "sqlite")]
+use diesel::{
+    dsl::Filter, expression::SqlLiteral, query_builder::QueryFragment, query_builder::QueryId,
+    query_dsl::methods::FilterDsl, sql_types::Bool,
+};
+use std::path::PathBuf;
+
+#[cfg(all(feature = "postgres", feature = "sqlite"))]
+compile_error!(
+    "The `postgres` and `sqlite` features are exclusive, \
+     build with `--no-default-features --features sqlite` to use SQLite"
+);
+
+#[cfg(not(any(feature = "postgres", feature = "sqlite")))]
+compile_error!("Either the `postgres` or the `sqlite` feature needs to be enabled");
+
+#[cfg(feature = "postgres")]
+pub type DbBackend = diesel::pg::Pg;
+#[cfg(feature = "sqlite")]
+pub type DbBackend = diesel::sqlite::Sqlite;
+
+#[cfg(feature = "postgres")]
+pub type DbConnection = diesel::pg::PgConnection;
+#[cfg(feature = "sqlite")]
+pub type DbConnection = diesel::sqlite::SqliteConnection;
+
+pub type DbPool = Pool<ConnectionManager<DbConnection>>;
+
+/// Points in time as stored in the database
+///
+/// SQLite has no timestamps with time zone,
+/// all timestamps are stored as UTC there.
+#[cfg(feature = "postgres")]
+pub type Timestamp = chrono::DateTime<chrono::Utc>;
+#[cfg(feature = "sqlite")]
+pub type Timestamp = chrono::NaiveDateTime;
+
+/// The sql type of `Timestamp`
+#[cfg(feature = "postgres")]
+pub use diesel::sql_types::Timestamptz;
+#[cfg(feature = "sqlite")]
+pub type Timestamptz = diesel::sql_types::Timestamp;
+
+/// The migrations of the backend, relative to the `migrations`
+/// directory diesel uses for postgres
+#[cfg(feature = "postgres")]
+const MIGRATIONS: &str = "migrations";
+#[cfg(feature = "sqlite")]
+const MIGRATIONS: &str = "sqlite_migrations";
+
+pub fn build_pool(database_url: &str) -> DbPool {
+    let manager = ConnectionManager::<DbConnection>::new(database_url);
+    let builder = Pool::builder();
+    #[cfg(feature = "sqlite")]
+    let builder = builder.connection_customizer(Box::new(sqlite::SetupConnection));
+    builder.build(manager).expect("Failed to init pool")
+}
+
+fn migrations_directory() -> Result<PathBuf, failure::Error> {
+    Ok(diesel_migrations::find_migrations_directory()?.with_file_name(MIGRATIONS))
+}
+
+pub fn run_pending_migrations(conn: &DbConnection) -> Result<(), failure::Error> {
+    let dir = migrations_directory()?;
+    diesel_migrations::run_pending_migrations_in_directory(conn, &dir, &mut std::io::stdout())?;
+    Ok(())
+}
+
+/// Revert the latest `steps` migrations, returns their versions
+pub fn revert_migrations(conn: &DbConnection, steps: usize) -> Result<Vec<String>, failure::Error> {
+    let dir = migrations_directory()?;
+    (0..steps)
+        .map(|_| {
+            Ok(diesel_migrations::revert_latest_migration_in_directory(
+                conn, &dir,
+            )?)
+        })
+        .collect()
+}
+
+/// The names of all migrations and whether they were run, oldest first
+pub fn migration_status(conn: &DbConnection) -> Result<Vec<(String, bool)>, failure::Error> {
+    let dir = migrations_directory()?;
+    let mut status = diesel_migrations::mark_migrations_in_directory(conn, &dir)?
+        .into_iter()
+        .map(|(migration, run)| (diesel_migrations::name(&*migration).to_string(), run))
+        .collect::<Vec<_>>();
+    status.sort();
+    Ok(status)
+}
+
+/// Insert the example data of `seeds/seed.sql`
+///
+/// Returns `false` without changing anything if there are users already
+pub fn seed(conn: &DbConnection) -> Result<bool, failure::Error> {
+    use crate::schema::users;
+
+    conn.transaction(|| {
+        let empty = users::table
+            .select(users::id)
+            .first::<i32>(conn)
+            .optional()?
+            .is_none();
+        if empty {
+            conn.batch_execute(include_str!("../seeds/seed.sql"))?;
+        }
+        Ok(empty)
+    })
+}
+
+/// The database the tests run against
+///
+/// Postgres tests need the `DATABASE_URL` of a database and are skipped if
+/// it is not set, every SQLite connection opens a new in-memory database.
+#[cfg(all(test, feature = "postgres"))]
+fn test_database_url() -> Option<String> {
+    std::env::var("DATABASE_URL").ok()
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+fn test_database_url() -> Option<String> {
+    Some(String::from(":memory:"))
+}
+
+/// Prepares the connections of the tests
+///
+/// Runs the pending migrations. Postgres connections start a
+/// transaction that is never committed, so the tests leave
+/// the database unchanged.
+#[cfg(test)]
+#[derive(Debug)]
+struct SetupTestConnection;
+
+#[cfg(test)]
+impl diesel::r2d2::CustomizeConnection<DbConnection, diesel::r2d2::Error> for SetupTestConnection {
+    fn on_acquire(&self, conn: &mut DbConnection) -> Result<(), diesel::r2d2::Error> {
+        #[cfg(feature = "sqlite")]
+        sqlite::SetupConnection.on_acquire(conn)?;
+        let dir = migrations_directory().expect("Failed to find the migrations");
+        diesel_migrations::run_pending_migrations_in_directory(conn, &dir, &mut std::io::sink())
+            .expect("Failed to run migrations");
+        #[cfg(feature = "postgres")]
+        conn.begin_test_transaction()
+            .map_err(diesel::r2d2::Error::QueryError)?;
+        Ok(())
+    }
+}
+
+/// A connection to the database of the tests, `None` if there is none
+#[cfg(test)]
+pub fn test_connection() -> Option<DbConnection> {
+    use diesel::r2d2::CustomizeConnection;
+
+    let mut conn =
+        DbConnection::establish(&test_database_url()?).expect("Failed to connect to the database");
+    SetupTestConnection
+        .on_acquire(&mut conn)
+        .expect("Failed to set up the connection");
+    Some(conn)
+}
+
+/// A pool with a single connection to the database
+/// of the tests, `None` if there is none
+#[cfg(test)]
+pub fn test_pool() -> Option<DbPool> {
+    let manager = ConnectionManager::<DbConnection>::new(test_database_url()?);
+    let pool = Pool::builder()
+        .max_size(1)
+        .connection_customizer(Box::new(SetupTestConnection))
+        .build(manager)
+        .expect("Failed to init pool");
+    Some(pool)
+}
+
+/// Insert a single row and load it afterwards
+///
+/// SQLite does not support `RETURNING`, there the
+/// row is loaded again by its `rowid`.
+#[cfg(feature = "postgres")]
+pub fn insert_and_load<T, V, U>(conn: &DbConnection, table: T, values: V) -> QueryResult<U>
+where
+    T: Table,
+    V: Insertable<T>,
+    InsertStatement<T, V::Values>: LoadQuery<DbConnection, U>,
+{
+    diesel::insert_into(table).values(values).get_result(conn)
+}
+
+#[cfg(feature = "sqlite")]
+pub fn insert_and_load<T, V, Values, U>(conn: &DbConnection, table: T, values: V) -> QueryResult<U>
+where
+    T: Table + Copy + FilterDsl<SqlLiteral<Bool>>,
+    V: Insertable<T, Values = Values>,
+    InsertStatement<T, Values>: QueryFragment<DbBackend> + QueryId,
+    Filter<T, SqlLiteral<Bool>>: LoadQuery<DbConnection, U>,
+{
+    diesel::insert_into(table).values(values).execute(conn)?;
+    table
+        .filter(diesel::dsl::sql::<Bool>("rowid = last_insert_rowid()"))
+        .get_result(conn)
+}
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::{DbConnection, Timestamp, Timestamptz};
+    use diesel::connection::SimpleConnection;
+    use diesel::r2d2::{CustomizeConnection, Error};
+    use diesel::sql_types::{Bool, Integer, Nullable};
+
+    sql_function! {
+        /// The `version_is_valid` function of the
+        /// `versioned_comments_and_users` postgres migration
+        fn version_is_valid(
+            version_start: Integer,
+            version_end: Nullable<Integer>,
+            version_start_at: Timestamptz,
+            version_end_at: Nullable<Timestamptz>,
+            version: Nullable<Integer>,
+            as_of: Nullable<Timestamptz>
+        ) -> Bool;
+    }
+
+    /// Checks whether a version of a row is valid at
+    /// the given version and point in time
+    ///
+    /// Without version and point in time only the current version is valid,
+    /// a version is valid from its start until (excluding) its end.
+    fn version_is_valid_impl(
+        version_start: i32,
+        version_end: Option<i32>,
+        version_start_at: Timestamp,
+        version_end_at: Option<Timestamp>,
+        version: Option<i32>,
+        as_of: Option<Timestamp>,
+    ) -> bool {
+        if version.is_none() && as_of.is_none() {
+            return version_end.is_none();
+        }
+        let in_version = version.map_or(true, |version| {
+            version_start <= version && version_end.map_or(true, |end| version < end)
+        });
+        let in_time = as_of.map_or(true, |as_of| {
+            version_start_at <= as_of && version_end_at.map_or(true, |end| as_of < end)
+        });
+        in_version && in_time
+    }
+
+    /// Registers the functions used by the queries
+    /// on each new connection
+    #[derive(Debug)]
+    pub(super) struct SetupConnection;
+
+    impl CustomizeConnection<DbConnection, Error> for SetupConnection {
+        fn on_acquire(&self, conn: &mut DbConnection) -> Result<(), Error> {
+            // Wait for concurrent writers instead of failing with `SQLITE_BUSY`
+            conn.batch_execute("PRAGMA busy_timeout = 5000;")
+                .map_err(Error::QueryError)?;
+            version_is_valid::register_impl(conn, version_is_valid_impl).map_err(Error::QueryError)
+        }
+    }
+}
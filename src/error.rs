@@ -0,0 +1,311 @@
+//! Errors of the REST api
+//!
+//! Handlers return `ApiError`, it is rendered as a RFC 7807
+//! `application/problem+json` document:
+//!
+//! ```json
+//! {
+//!   "type": "about:blank",
+//!   "title": "Not Found",
+//!   "status": 404,
+//!   "detail": "The requested resource does not exist",
+//!   "code": "NOT_FOUND"
+//! }
+//! ```
+//!
+//! `code` is stable and meant to be checked by clients,
+//! the wording of `detail` may change. Invalid input is reported
+//! with the `VALIDATION_FAILED` code, the failed rules are listed
+//! as `invalid-params`.
+
+use crate::validation::{ValidationError, Violation};
+use actix_web::error::{JsonPayloadError, PathError, QueryPayloadError};
+use actix_web::http::{header, StatusCode};
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use diesel::r2d2::PoolError;
+use diesel::result::{DatabaseErrorInformation, DatabaseErrorKind, Error as DieselError};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::fmt;
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// An error response with a stable error code
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    detail: String,
+    invalid_params: Vec<Violation>,
+}
+
+/// A RFC 7807 problem document
+#[derive(Serialize, JsonSchema)]
+pub struct Problem<'a> {
+    /// Always `about:blank`
+    #[serde(rename = "type")]
+    problem_type: &'static str,
+    title: &'a str,
+    status: u16,
+    detail: &'a str,
+    /// Stable error code
+    code: &'a str,
+    /// The fields failing validation
+    #[serde(rename = "invalid-params", skip_serializing_if = "<[_]>::is_empty")]
+    #[schemars(default)]
+    invalid_params: &'a [Violation],
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, detail: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            code,
+            detail: detail.into(),
+            invalid_params: Vec::new(),
+        }
+    }
+
+    /// 404, the requested row does not exist
+    pub fn not_found() -> Self {
+        ApiError::new(
+            StatusCode::NOT_FOUND,
+            "NOT_FOUND",
+            "The requested resource does not exist",
+        )
+    }
+
+    /// 401, the caller is not or not correctly authenticated
+    pub fn unauthenticated(detail: impl Into<String>) -> Self {
+        ApiError::new(StatusCode::UNAUTHORIZED, "UNAUTHENTICATED", detail)
+    }
+
+    /// 422, the request is well-formed but could not be processed
+    pub fn unprocessable(code: &'static str, detail: impl Into<String>) -> Self {
+        ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, code, detail)
+    }
+
+    /// 500, details are only logged
+    pub fn internal() -> Self {
+        ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "INTERNAL_ERROR",
+            "An unexpected error occurred",
+        )
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.detail)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status)
+            .header(header::CONTENT_TYPE, PROBLEM_JSON)
+            .json(Problem {
+                problem_type: "about:blank",
+                title: self.status.canonical_reason().unwrap_or_default(),
+                status: self.status.as_u16(),
+                detail: &self.detail,
+                code: self.code,
+                invalid_params: &self.invalid_params,
+            })
+    }
+
+    // The default implementation replaces the body by the `Display` output
+    fn render_response(&self) -> HttpResponse {
+        self.error_response()
+    }
+}
+
+/// Client facing details of the named constraints, the messages
+/// of the database mention tables and constraints and are only logged
+const CONSTRAINT_DETAILS: &[(&str, &str)] = &[
+    ("users_pkey", "A version of this user already exists"),
+    ("posts_pkey", "A version of this post already exists"),
+    ("comments_pkey", "A version of this comment already exists"),
+    ("user_roles_pkey", "The role assignment already exists"),
+    (
+        "user_roles_user_id_role_key",
+        "The user already has this role",
+    ),
+    ("posts_author_fkey", "The author does not exist"),
+    ("comments_author_fkey", "The author does not exist"),
+    (
+        "users_author_fkey",
+        "The user is still the author of posts or comments",
+    ),
+];
+
+fn constraint_detail(info: &dyn DatabaseErrorInformation, fallback: &'static str) -> &'static str {
+    info.constraint_name()
+        .and_then(|name| {
+            CONSTRAINT_DETAILS
+                .iter()
+                .find(|(constraint, _)| *constraint == name)
+        })
+        .map_or(fallback, |(_, detail)| *detail)
+}
+
+impl From<DieselError> for ApiError {
+    fn from(e: DieselError) -> Self {
+        match e {
+            DieselError::NotFound => ApiError::not_found(),
+            DieselError::DatabaseError(kind, info) => match kind {
+                DatabaseErrorKind::UniqueViolation => {
+                    log::warn!("Unique violation: {}", info.message());
+                    ApiError::new(
+                        StatusCode::CONFLICT,
+                        "ALREADY_EXISTS",
+                        constraint_detail(&*info, "The resource already exists"),
+                    )
+                }
+                DatabaseErrorKind::SerializationFailure => ApiError::new(
+                    StatusCode::CONFLICT,
+                    "CONCURRENT_MODIFICATION",
+                    "The resource was changed concurrently, retry the request",
+                ),
+                DatabaseErrorKind::ForeignKeyViolation => {
+                    log::warn!("Foreign key violation: {}", info.message());
+                    ApiError::unprocessable(
+                        "INVALID_REFERENCE",
+                        constraint_detail(&*info, "A referenced resource does not exist"),
+                    )
+                }
+                // Check and not null constraints have no kind of their own
+                _ if info.constraint_name().is_some() || info.column_name().is_some() => {
+                    log::warn!("Constraint violation: {}", info.message());
+                    ApiError::unprocessable(
+                        "CONSTRAINT_VIOLATION",
+                        constraint_detail(&*info, "The request violates a constraint of the data"),
+                    )
+                }
+                _ => {
+                    log::error!("Database error: {}", info.message());
+                    ApiError::internal()
+                }
+            },
+            e => {
+                log::error!("Database error: {}", e);
+                ApiError::internal()
+            }
+        }
+    }
+}
+
+impl From<PoolError> for ApiError {
+    fn from(e: PoolError) -> Self {
+        log::error!("No database connection available: {}", e);
+        ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "DATABASE_UNAVAILABLE",
+            "The database is currently not available, retry later",
+        )
+    }
+}
+
+impl From<ValidationError> for ApiError {
+    fn from(e: ValidationError) -> Self {
+        match e {
+            ValidationError::Invalid(violations) => ApiError {
+                status: StatusCode::UNPROCESSABLE_ENTITY,
+                code: "VALIDATION_FAILED",
+                detail: String::from("The request contains invalid fields"),
+                invalid_params: violations,
+            },
+            ValidationError::Database(e) => e.into(),
+        }
+    }
+}
+
+/// Error handler of `web::JsonConfig` for request bodies
+pub fn json_error(e: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    match e {
+        JsonPayloadError::Deserialize(ref e) if e.is_data() => {
+            ApiError::unprocessable("INVALID_BODY", format!("Invalid request body: {}", e))
+        }
+        JsonPayloadError::Overflow => ApiError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "BODY_TOO_LARGE",
+            "The request body is too large",
+        ),
+        e => ApiError::new(StatusCode::BAD_REQUEST, "MALFORMED_BODY", e.to_string()),
+    }
+    .into()
+}
+
+/// Error handler of `web::PathConfig`, paths with invalid ids do not exist
+pub fn path_error(_e: PathError, _req: &HttpRequest) -> actix_web::Error {
+    ApiError::not_found().into()
+}
+
+/// Error handler of `web::QueryConfig` for query strings
+pub fn query_error(e: QueryPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    ApiError::new(StatusCode::BAD_REQUEST, "INVALID_QUERY", e.to_string()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Info {
+        constraint: Option<&'static str>,
+    }
+
+    impl DatabaseErrorInformation for Info {
+        fn message(&self) -> &str {
+            "duplicate key value violates unique constraint \"secret_internal_name\""
+        }
+        fn details(&self) -> Option<&str> {
+            None
+        }
+        fn hint(&self) -> Option<&str> {
+            None
+        }
+        fn table_name(&self) -> Option<&str> {
+            None
+        }
+        fn column_name(&self) -> Option<&str> {
+            None
+        }
+        fn constraint_name(&self) -> Option<&str> {
+            self.constraint
+        }
+    }
+
+    fn violation(kind: DatabaseErrorKind, constraint: Option<&'static str>) -> ApiError {
+        DieselError::DatabaseError(kind, Box::new(Info { constraint })).into()
+    }
+
+    #[test]
+    fn known_constraints_have_stable_details() {
+        let e = violation(
+            DatabaseErrorKind::UniqueViolation,
+            Some("user_roles_user_id_role_key"),
+        );
+        assert_eq!(e.code(), "ALREADY_EXISTS");
+        assert_eq!(e.to_string(), "The user already has this role");
+    }
+
+    #[test]
+    fn database_messages_are_not_exposed() {
+        let e = violation(
+            DatabaseErrorKind::UniqueViolation,
+            Some("secret_internal_name"),
+        );
+        assert_eq!(e.to_string(), "The resource already exists");
+        let e = violation(DatabaseErrorKind::ForeignKeyViolation, None);
+        assert_eq!(e.code(), "INVALID_REFERENCE");
+        assert!(!e.to_string().contains("secret_internal_name"));
+        let e = violation(DatabaseErrorKind::__Unknown, Some("secret_internal_name"));
+        assert_eq!(e.code(), "CONSTRAINT_VIOLATION");
+        assert!(!e.to_string().contains("secret_internal_name"));
+    }
+}
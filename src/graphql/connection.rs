@@ -0,0 +1,618 @@
+use crate::graphql::RequestContext;
+use crate::pagination::{decode_cursor, encode_cursor, DEFAULT_PER_PAGE, MAX_PAGE_SIZE};
+use juniper::meta::{Argument, MetaType};
+use juniper::parser::SourcePosition;
+use juniper::{
+    Arguments, ExecutionResult, Executor, FieldResult, GraphQLObject, GraphQLType,
+    LookAheadMethods, LookAheadSelection, Registry, Selection, Value,
+};
+use std::marker::PhantomData;
+use wundergraph::juniper_ext::FromLookAheadValue;
+use wundergraph::scalar::WundergraphScalarValue;
+
+/// A wundergraph entity that could be queried as relay connection
+///
+/// Use `relay_node!` to implement this trait
+pub trait RelayNode: Sized {
+    const CONNECTION_NAME: &'static str;
+    const EDGE_NAME: &'static str;
+
+    /// The graphql type used for the `node` field of an edge
+    type Node: GraphQLType<WundergraphScalarValue, TypeInfo = ()>;
+
+    /// Register the `filter` argument of the connection field
+    fn filter_argument<'r>(
+        registry: &mut Registry<'r, WundergraphScalarValue>,
+    ) -> Argument<'r, WundergraphScalarValue>;
+
+    /// Load the ids of the requested page, ordered by id
+    ///
+    /// `select` is the look ahead of the connection field, its
+    /// `filter` argument is applied. If `backwards` is set the
+    /// order is reversed
+    fn load_ids(
+        select: &LookAheadSelection<'_, WundergraphScalarValue>,
+        after: Option<i32>,
+        before: Option<i32>,
+        backwards: bool,
+        limit: i64,
+        ctx: &RequestContext,
+    ) -> FieldResult<Vec<i32>, WundergraphScalarValue>;
+
+    /// Load and resolve the nodes with the given ids, ordered by id
+    fn load_nodes(
+        select: &LookAheadSelection<'_, WundergraphScalarValue>,
+        selection: Option<&'_ [Selection<'_, WundergraphScalarValue>]>,
+        executor: &Executor<'_, RequestContext, WundergraphScalarValue>,
+        ids: &[i32],
+    ) -> FieldResult<Vec<Value<WundergraphScalarValue>>, WundergraphScalarValue>;
+}
+
+/// Implement `RelayNode` for a wundergraph entity with
+/// an integer `id` primary key
+///
+/// Ids and nodes are both loaded by the query of
+/// `LoadingHandler::build_query`, so they see the same rows
+#[macro_export]
+macro_rules! relay_node {
+    ($entity: ident, $table: ident) => {
+        impl $crate::graphql::connection::RelayNode for $entity {
+            const CONNECTION_NAME: &'static str = concat!(stringify!($entity), "Connection");
+            const EDGE_NAME: &'static str = concat!(stringify!($entity), "Edge");
+
+            type Node = wundergraph::graphql_type::GraphqlWrapper<
+                $entity,
+                $crate::db::DbBackend,
+                $crate::graphql::RequestContext,
+            >;
+
+            fn filter_argument<'r>(
+                registry: &mut juniper::Registry<'r, wundergraph::scalar::WundergraphScalarValue>,
+            ) -> juniper::meta::Argument<'r, wundergraph::scalar::WundergraphScalarValue> {
+                registry.arg_with_default::<Option<
+                    wundergraph::query_builder::selection::filter::Filter<
+                        <$entity as wundergraph::query_builder::selection::LoadingHandler<
+                            $crate::db::DbBackend,
+                            $crate::graphql::RequestContext,
+                        >>::Filter,
+                        $table::table,
+                    >,
+                >>("filter", &None, &Default::default())
+            }
+
+            fn load_ids(
+                select: &juniper::LookAheadSelection<
+                    '_,
+                    wundergraph::scalar::WundergraphScalarValue,
+                >,
+                after: Option<i32>,
+                before: Option<i32>,
+                backwards: bool,
+                limit: i64,
+                ctx: &$crate::graphql::RequestContext,
+            ) -> juniper::FieldResult<Vec<i32>, wundergraph::scalar::WundergraphScalarValue> {
+                use diesel::prelude::*;
+                use wundergraph::helper::{TupleIndex, TupleIndex0};
+                use wundergraph::query_builder::selection::fields::WundergraphFieldList;
+                use wundergraph::query_builder::selection::{LoadingHandler, QueryModifier};
+
+                // The connection selects no fields of the entity, so
+                // only the id is loaded and all other fields are null
+                type Row = <<$entity as LoadingHandler<
+                    $crate::db::DbBackend,
+                    $crate::graphql::RequestContext,
+                >>::FieldList as WundergraphFieldList<
+                    $crate::db::DbBackend,
+                    TupleIndex0,
+                    $table::table,
+                    $crate::graphql::RequestContext,
+                >>::PlaceHolder;
+
+                let query =
+                    <$entity as LoadingHandler<_, $crate::graphql::RequestContext>>::build_query(
+                        &[],
+                        select,
+                    )?;
+                let mut query = <_ as QueryModifier<$entity, _>>::modify_query(ctx, select, query)?;
+                if let Some(after) = after {
+                    query = query.filter($table::id.gt(after));
+                }
+                if let Some(before) = before {
+                    query = query.filter($table::id.lt(before));
+                }
+                query = if backwards {
+                    query.order($table::id.desc())
+                } else {
+                    query.order($table::id)
+                };
+                let rows = query.limit(limit).load::<Row>(ctx.get_connection())?;
+                Ok(rows
+                    .iter()
+                    .filter_map(|row| <Row as TupleIndex<TupleIndex0>>::get(row).into())
+                    .collect())
+            }
+
+            fn load_nodes(
+                select: &juniper::LookAheadSelection<
+                    '_,
+                    wundergraph::scalar::WundergraphScalarValue,
+                >,
+                selection: Option<
+                    &'_ [juniper::Selection<'_, wundergraph::scalar::WundergraphScalarValue>],
+                >,
+                executor: &juniper::Executor<
+                    '_,
+                    $crate::graphql::RequestContext,
+                    wundergraph::scalar::WundergraphScalarValue,
+                >,
+                ids: &[i32],
+            ) -> juniper::FieldResult<
+                Vec<juniper::Value<wundergraph::scalar::WundergraphScalarValue>>,
+                wundergraph::scalar::WundergraphScalarValue,
+            > {
+                use diesel::prelude::*;
+                use wundergraph::query_builder::selection::LoadingHandler;
+
+                let query =
+                    <$entity as LoadingHandler<_, $crate::graphql::RequestContext>>::build_query(
+                        &[],
+                        select,
+                    )?
+                    .filter($table::id.eq_any(ids.to_vec()))
+                    .order($table::id);
+                Ok(<$entity as LoadingHandler<
+                    _,
+                    $crate::graphql::RequestContext,
+                >>::load(select, selection, executor, query)?)
+            }
+        }
+    };
+}
+
+#[derive(Debug, Clone, GraphQLObject)]
+#[graphql(scalar = WundergraphScalarValue)]
+pub struct PageInfo {
+    has_next_page: bool,
+    has_previous_page: bool,
+    start_cursor: Option<String>,
+    end_cursor: Option<String>,
+}
+
+/// A relay connection for the entity `T`
+///
+/// Resolving this type loads the requested page
+#[derive(Debug)]
+pub struct RelayConnection<T>(PhantomData<T>);
+
+impl<T> Default for RelayConnection<T> {
+    fn default() -> Self {
+        RelayConnection(PhantomData)
+    }
+}
+
+impl<T: RelayNode> RelayConnection<T> {
+    /// Register a field returning this connection, including
+    /// the relay pagination arguments and the entity filter
+    pub fn field<'r>(
+        name: &str,
+        registry: &mut Registry<'r, WundergraphScalarValue>,
+    ) -> juniper::meta::Field<'r, WundergraphScalarValue> {
+        let first = registry.arg::<Option<i32>>("first", &());
+        let after = registry.arg::<Option<String>>("after", &());
+        let last = registry.arg::<Option<i32>>("last", &());
+        let before = registry.arg::<Option<String>>("before", &());
+        let filter = T::filter_argument(registry);
+        registry
+            .field::<Self>(name, &())
+            .argument(first)
+            .argument(after)
+            .argument(last)
+            .argument(before)
+            .argument(filter)
+    }
+
+    fn load(
+        selection: Option<&[Selection<WundergraphScalarValue>]>,
+        executor: &Executor<RequestContext, WundergraphScalarValue>,
+    ) -> FieldResult<LoadedConnection<T>, WundergraphScalarValue> {
+        let look_ahead = executor.look_ahead();
+        let int_arg = |name| {
+            look_ahead
+                .argument(name)
+                .and_then(|a| i32::from_look_ahead(a.value()))
+        };
+        let cursor_arg = |name| -> FieldResult<Option<i32>, WundergraphScalarValue> {
+            match look_ahead
+                .argument(name)
+                .and_then(|a| String::from_look_ahead(a.value()))
+            {
+                Some(cursor) => Ok(Some(decode_cursor::<(i32,)>(&cursor)?.0)),
+                None => Ok(None),
+            }
+        };
+        let (first, last) = (int_arg("first"), int_arg("last"));
+        let (after, before) = (cursor_arg("after")?, cursor_arg("before")?);
+
+        let (limit, backwards) = match (first, last) {
+            (Some(_), Some(_)) => {
+                return Err("Passing both `first` and `last` is not supported".into());
+            }
+            (Some(n), None) | (None, Some(n)) if n < 0 => {
+                return Err("`first` and `last` must not be negative".into());
+            }
+            (None, Some(last)) => (i64::from(last).min(MAX_PAGE_SIZE), true),
+            (Some(first), None) => (i64::from(first).min(MAX_PAGE_SIZE), false),
+            (None, None) => (DEFAULT_PER_PAGE, false),
+        };
+
+        // Fetch one additional id to know if there is another page
+        let mut ids = T::load_ids(
+            &look_ahead,
+            after,
+            before,
+            backwards,
+            limit + 1,
+            executor.context(),
+        )?;
+        let has_more = ids.len() as i64 > limit;
+        ids.truncate(limit as usize);
+        let (has_next_page, has_previous_page) = if backwards {
+            ids.reverse();
+            (before.is_some(), has_more)
+        } else {
+            (has_more, after.is_some())
+        };
+
+        let edges = find_field(executor, selection, "edges");
+        let node = edges
+            .as_ref()
+            .and_then(|edges| find_field(executor, edges.selection(), "node"));
+        let nodes = match (&edges, &node) {
+            (Some(edges), Some(node)) if !ids.is_empty() => {
+                // The edges executor selects only the merged `node` field,
+                // so the look ahead of the node executor finds all its fields
+                let edges_selection = [node.field.clone()];
+                let edges_executor = executor.field_sub_executor(
+                    edges.alias,
+                    "edges",
+                    edges.position,
+                    Some(&edges_selection),
+                );
+                let node_executor = edges_executor.field_sub_executor(
+                    node.alias,
+                    "node",
+                    node.position,
+                    node.selection(),
+                );
+                let node_look_ahead = node_executor.look_ahead();
+                T::load_nodes(&node_look_ahead, node.selection(), &node_executor, &ids)?
+            }
+            _ => vec![Value::Null; ids.len()],
+        };
+
+        let edges = ids
+            .into_iter()
+            .zip(nodes)
+            .map(|(id, node)| Edge {
+                node,
+                cursor: encode_cursor(&(id,)),
+                node_type: PhantomData,
+            })
+            .collect::<Vec<_>>();
+        let page_info = PageInfo {
+            has_next_page,
+            has_previous_page,
+            start_cursor: edges.first().map(|e| e.cursor.clone()),
+            end_cursor: edges.last().map(|e| e.cursor.clone()),
+        };
+
+        Ok(LoadedConnection { edges, page_info })
+    }
+}
+
+/// A field found by `find_field`
+pub struct SubField<'a> {
+    pub alias: &'a str,
+    pub position: SourcePosition,
+    /// The first occurrence of the field, selecting
+    /// the sub selections of all occurrences
+    pub field: Selection<'a, WundergraphScalarValue>,
+}
+
+impl<'a> SubField<'a> {
+    /// The sub selection of the field
+    pub fn selection(&self) -> Option<&[Selection<'a, WundergraphScalarValue>]> {
+        match self.field {
+            Selection::Field(ref f) => f.item.selection_set.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+/// Find the field with the given name in a selection set
+///
+/// Fields selected by fragments are found as well. A field selected more
+/// than once is returned with the sub selections of all occurrences, so
+/// the look ahead of an executor resolving `SubField::field` sees them all.
+pub fn find_field<'a, CtxT>(
+    executor: &'a Executor<'a, CtxT, WundergraphScalarValue>,
+    selection: Option<&'a [Selection<'a, WundergraphScalarValue>]>,
+    field_name: &str,
+) -> Option<SubField<'a>> {
+    let mut fields = Vec::new();
+    collect_fields(executor, selection?, field_name, &mut fields);
+    let sub_selection = fields
+        .iter()
+        .flat_map(|s| match s {
+            Selection::Field(f) => f.item.selection_set.as_deref(),
+            _ => None,
+        })
+        .flatten()
+        .cloned()
+        .collect::<Vec<_>>();
+    match (*fields.first()?).clone() {
+        Selection::Field(mut f) => {
+            if f.item.selection_set.is_some() {
+                f.item.selection_set = Some(sub_selection);
+            }
+            Some(SubField {
+                alias: f.item.alias.unwrap_or(f.item.name).item,
+                position: f.start,
+                field: Selection::Field(f),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Collect the fields with the given name, see `find_field`
+fn collect_fields<'a, CtxT>(
+    executor: &'a Executor<'a, CtxT, WundergraphScalarValue>,
+    selection: &'a [Selection<'a, WundergraphScalarValue>],
+    field_name: &str,
+    fields: &mut Vec<&'a Selection<'a, WundergraphScalarValue>>,
+) {
+    for s in selection {
+        match s {
+            Selection::Field(f) if f.item.name.item == field_name => fields.push(s),
+            Selection::Field(_) => {}
+            Selection::FragmentSpread(spread) => {
+                if let Some(fragment) = executor.fragment_by_name(spread.item.name.item) {
+                    collect_fields(executor, &fragment.selection_set, field_name, fields);
+                }
+            }
+            Selection::InlineFragment(fragment) => {
+                collect_fields(executor, &fragment.item.selection_set, field_name, fields);
+            }
+        }
+    }
+}
+
+fn connection_meta<'r, T: RelayNode, G: GraphQLType<WundergraphScalarValue, TypeInfo = ()>>(
+    registry: &mut Registry<'r, WundergraphScalarValue>,
+) -> MetaType<'r, WundergraphScalarValue> {
+    let fields = &[
+        registry.field::<Vec<Edge<T>>>("edges", &()),
+        registry.field::<PageInfo>("pageInfo", &()),
+    ];
+    registry.build_object_type::<G>(&(), fields).into_meta()
+}
+
+impl<T: RelayNode> GraphQLType<WundergraphScalarValue> for RelayConnection<T> {
+    type Context = RequestContext;
+    type TypeInfo = ();
+
+    fn name(_info: &Self::TypeInfo) -> Option<&str> {
+        Some(T::CONNECTION_NAME)
+    }
+
+    fn meta<'r>(
+        _info: &Self::TypeInfo,
+        registry: &mut Registry<'r, WundergraphScalarValue>,
+    ) -> MetaType<'r, WundergraphScalarValue>
+    where
+        WundergraphScalarValue: 'r,
+    {
+        connection_meta::<T, Self>(registry)
+    }
+
+    fn resolve(
+        &self,
+        info: &Self::TypeInfo,
+        selection_set: Option<&[Selection<WundergraphScalarValue>]>,
+        executor: &Executor<Self::Context, WundergraphScalarValue>,
+    ) -> Value<WundergraphScalarValue> {
+        match Self::load(selection_set, executor) {
+            Ok(loaded) => executor.resolve_into_value(info, &loaded),
+            Err(e) => {
+                executor.push_error(e);
+                Value::null()
+            }
+        }
+    }
+}
+
+/// A loaded page of a relay connection
+///
+/// Shares the graphql type with `RelayConnection<T>`
+struct LoadedConnection<T> {
+    edges: Vec<Edge<T>>,
+    page_info: PageInfo,
+}
+
+impl<T: RelayNode> GraphQLType<WundergraphScalarValue> for LoadedConnection<T> {
+    type Context = RequestContext;
+    type TypeInfo = ();
+
+    fn name(_info: &Self::TypeInfo) -> Option<&str> {
+        Some(T::CONNECTION_NAME)
+    }
+
+    fn meta<'r>(
+        _info: &Self::TypeInfo,
+        registry: &mut Registry<'r, WundergraphScalarValue>,
+    ) -> MetaType<'r, WundergraphScalarValue>
+    where
+        WundergraphScalarValue: 'r,
+    {
+        connection_meta::<T, Self>(registry)
+    }
+
+    fn resolve_field(
+        &self,
+        info: &Self::TypeInfo,
+        field_name: &str,
+        _arguments: &Arguments<WundergraphScalarValue>,
+        executor: &Executor<Self::Context, WundergraphScalarValue>,
+    ) -> ExecutionResult<WundergraphScalarValue> {
+        match field_name {
+            "edges" => executor.resolve(info, &self.edges),
+            "pageInfo" => executor.resolve_with_ctx(info, &self.page_info),
+            e => Err(juniper::FieldError::new("Unknown field:", Value::scalar(e))),
+        }
+    }
+}
+
+struct Edge<T> {
+    node: Value<WundergraphScalarValue>,
+    cursor: String,
+    node_type: PhantomData<T>,
+}
+
+impl<T: RelayNode> GraphQLType<WundergraphScalarValue> for Edge<T> {
+    type Context = RequestContext;
+    type TypeInfo = ();
+
+    fn name(_info: &Self::TypeInfo) -> Option<&str> {
+        Some(T::EDGE_NAME)
+    }
+
+    fn meta<'r>(
+        info: &Self::TypeInfo,
+        registry: &mut Registry<'r, WundergraphScalarValue>,
+    ) -> MetaType<'r, WundergraphScalarValue>
+    where
+        WundergraphScalarValue: 'r,
+    {
+        let fields = &[
+            registry.field::<T::Node>("node", info),
+            registry.field::<String>("cursor", info),
+        ];
+        registry.build_object_type::<Self>(info, fields).into_meta()
+    }
+
+    fn resolve_field(
+        &self,
+        _info: &Self::TypeInfo,
+        field_name: &str,
+        _arguments: &Arguments<WundergraphScalarValue>,
+        _executor: &Executor<Self::Context, WundergraphScalarValue>,
+    ) -> ExecutionResult<WundergraphScalarValue> {
+        match field_name {
+            // Nodes are loaded at once for the whole page
+            "node" => Ok(self.node.clone()),
+            "cursor" => Ok(Value::scalar(self.cursor.clone())),
+            e => Err(juniper::FieldError::new("Unknown field:", Value::scalar(e))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{test_pool, DbConnection};
+    use crate::graphql::tests::execute as execute_query;
+    use crate::model::users::tests::insert_user;
+    use crate::schema::users;
+    use crate::versioning::Versioned;
+    use diesel::prelude::*;
+
+    /// Execute the queries against a database containing two new users
+    fn execute(queries: &[&str]) -> Option<Vec<serde_json::Value>> {
+        execute_with(queries, |conn| {
+            insert_user(conn, &[]);
+            insert_user(conn, &[]);
+        })
+    }
+
+    /// Execute the queries after preparing the database by `setup`
+    fn execute_with(
+        queries: &[&str],
+        setup: impl FnOnce(&DbConnection),
+    ) -> Option<Vec<serde_json::Value>> {
+        let pool = test_pool()?;
+        setup(&pool.get().unwrap());
+        let results = queries
+            .iter()
+            .map(|query| {
+                let response = execute_query(&pool, None, query);
+                assert_eq!(response["errors"], serde_json::json!([]), "{}", response);
+                response["data"].clone()
+            })
+            .collect();
+        Some(results)
+    }
+
+    #[test]
+    fn nodes_are_loaded_through_fragments() {
+        let results = match execute(&[
+            "{ usersConnection(last: 2) { edges { node { id name } } } }",
+            "{ usersConnection(last: 2) { ...Edges } }
+             fragment Edges on UserConnection { edges { node { id name } } }",
+            "{ usersConnection(last: 2) { ... on UserConnection { edges { ... { node { id name } } } } } }",
+            "{ usersConnection(last: 2) { edges { node { id } } ...Names } }
+             fragment Names on UserConnection { edges { node { name } } }",
+        ]) {
+            Some(results) => results,
+            None => return,
+        };
+        let edges = &results[0]["usersConnection"]["edges"];
+        assert_eq!(edges.as_array().map(Vec::len), Some(2));
+        assert_eq!(edges[0]["node"]["name"], "Test user");
+        for result in &results[1..] {
+            assert_eq!(result, &results[0]);
+        }
+    }
+
+    #[test]
+    fn pages_contain_the_filtered_current_rows() {
+        let name = format!("Connection {}", uuid::Uuid::new_v4());
+        let filter = |name: &str| {
+            format!(
+                "{{ usersConnection(filter: {{ name: {{ eq: \"{}\" }} }}) {{ edges {{ node {{ name }} }} pageInfo {{ hasNextPage }} }} }}",
+                name
+            )
+        };
+        let (old, new) = (filter(&name), filter(&format!("{} renamed", name)));
+        let rename = |conn: &DbConnection, id: i32, name: String| {
+            let version_start = users::table.close_current_version(conn, id).unwrap();
+            diesel::insert_into(users::table)
+                .values((
+                    users::id.eq(id),
+                    users::name.eq(name),
+                    users::version_start.eq(version_start),
+                ))
+                .execute(conn)
+                .unwrap();
+        };
+        let results = match execute_with(&[&old, &new], |conn| {
+            let user = insert_user(conn, &[]);
+            rename(conn, user, name.clone());
+            rename(conn, user, format!("{} renamed", name));
+        }) {
+            Some(results) => results,
+            None => return,
+        };
+        // Earlier versions of the user match the filter, but are not current
+        assert_eq!(
+            results[0]["usersConnection"]["edges"],
+            serde_json::json!([])
+        );
+        let edges = &results[1]["usersConnection"]["edges"];
+        assert_eq!(edges.as_array().map(Vec::len), Some(1));
+        assert_eq!(edges[0]["node"]["name"], format!("{} renamed", name));
+        assert_eq!(
+            results[1]["usersConnection"]["pageInfo"]["hasNextPage"],
+            false
+        );
+    }
+}
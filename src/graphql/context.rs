@@ -0,0 +1,181 @@
+use crate::auth::Identity;
+use crate::db::{DbBackend, DbConnection};
+use crate::error::ApiError;
+use crate::AppState;
+use actix_web::dev::Payload;
+use actix_web::{Error, FromRequest, HttpRequest};
+use diesel::query_builder::QueryFragment;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::QuerySource;
+use juniper::{FieldResult, IntoFieldError, LookAheadSelection};
+use std::collections::HashSet;
+use std::sync::Arc;
+use wundergraph::query_builder::selection::{BoxedQuery, LoadingHandler, QueryModifier};
+use wundergraph::scalar::WundergraphScalarValue;
+use wundergraph::WundergraphContext;
+
+/// Header containing the id of a request, a new
+/// id is generated for requests without one
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Used for requests without a usable `Accept-Language` header
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// Experimental features enabled on the command line
+#[derive(Debug, Default)]
+pub struct FeatureFlags(HashSet<String>);
+
+impl FeatureFlags {
+    pub fn new(flags: impl IntoIterator<Item = String>) -> Self {
+        FeatureFlags(flags.into_iter().collect())
+    }
+
+    pub fn is_enabled(&self, flag: &str) -> bool {
+        self.0.contains(flag)
+    }
+}
+
+/// Data about a single request available to all resolvers
+#[derive(Debug, Clone)]
+pub struct RequestMetadata {
+    /// The caller, `None` for anonymous requests
+    pub identity: Option<Identity>,
+    pub request_id: String,
+    /// The preferred language of the caller
+    pub locale: String,
+    pub feature_flags: Arc<FeatureFlags>,
+}
+
+impl FromRequest for RequestMetadata {
+    type Error = Error;
+    type Future = Result<Self, Error>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let header = |name| req.headers().get(name).and_then(|h| h.to_str().ok());
+        let request_id = header(REQUEST_ID_HEADER)
+            .map(String::from)
+            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+        let locale = header("accept-language")
+            .and_then(preferred_language)
+            .unwrap_or_else(|| String::from(DEFAULT_LOCALE));
+        Ok(RequestMetadata {
+            identity: req.extensions().get::<Identity>().copied(),
+            request_id,
+            locale,
+            feature_flags: req
+                .app_data::<AppState>()
+                .expect("AppData set")
+                .feature_flags
+                .clone(),
+        })
+    }
+}
+
+/// The language with the highest quality in an `Accept-Language` header
+fn preferred_language(header: &str) -> Option<String> {
+    let mut preferred = None;
+    let mut best_quality = 0.0;
+    for entry in header.split(',') {
+        let mut parts = entry.split(';').map(str::trim);
+        let language = parts.next().unwrap_or_default();
+        let quality = parts
+            .find_map(|p| p.strip_prefix("q="))
+            .map_or(Some(1.0), |q| q.parse::<f32>().ok())
+            .unwrap_or(0.0);
+        if !language.is_empty() && language != "*" && quality > best_quality {
+            preferred = Some(language);
+            best_quality = quality;
+        }
+    }
+    preferred.map(String::from)
+}
+
+/// Context graphql queries and mutations are executed with
+///
+/// Wraps the database connection used by wundergraph
+/// and carries the metadata of the current request
+pub struct RequestContext {
+    conn: PooledConnection<ConnectionManager<DbConnection>>,
+    metadata: RequestMetadata,
+}
+
+impl RequestContext {
+    pub fn new(
+        conn: PooledConnection<ConnectionManager<DbConnection>>,
+        metadata: RequestMetadata,
+    ) -> Self {
+        RequestContext { conn, metadata }
+    }
+
+    /// The caller, `None` for anonymous requests
+    pub fn identity(&self) -> Option<Identity> {
+        self.metadata.identity
+    }
+
+    /// The caller, fails for anonymous requests
+    pub fn require_identity(&self) -> FieldResult<Identity, WundergraphScalarValue> {
+        self.identity()
+            .ok_or_else(|| ApiError::unauthenticated("Authentication required").into_field_error())
+    }
+
+    pub fn request_id(&self) -> &str {
+        &self.metadata.request_id
+    }
+
+    pub fn locale(&self) -> &str {
+        &self.metadata.locale
+    }
+
+    pub fn feature_enabled(&self, flag: &str) -> bool {
+        self.metadata.feature_flags.is_enabled(flag)
+    }
+}
+
+impl WundergraphContext for RequestContext {
+    type Connection = DbConnection;
+
+    fn get_connection(&self) -> &DbConnection {
+        &self.conn
+    }
+}
+
+impl juniper::Context for RequestContext {}
+
+impl<T> QueryModifier<T, DbBackend> for RequestContext
+where
+    T: LoadingHandler<DbBackend, Self>,
+    T::Table: 'static,
+    <T::Table as QuerySource>::FromClause: QueryFragment<DbBackend>,
+{
+    fn modify_query<'a>(
+        &self,
+        _select: &LookAheadSelection<'_, WundergraphScalarValue>,
+        query: BoxedQuery<'a, T, DbBackend, Self>,
+    ) -> wundergraph::error::Result<BoxedQuery<'a, T, DbBackend, Self>> {
+        Ok(query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_language_with_the_highest_quality_is_preferred() {
+        let preferred = preferred_language;
+        assert_eq!(preferred("de"), Some(String::from("de")));
+        assert_eq!(
+            preferred("en;q=0.5, de-CH, fr;q=0.8"),
+            Some(String::from("de-CH"))
+        );
+        assert_eq!(preferred("en;q=0.5,fr;q=0.8"), Some(String::from("fr")));
+        // Languages without quality have the quality 1
+        assert_eq!(preferred("fr;q=1, en"), Some(String::from("fr")));
+        assert_eq!(preferred("*, en;q=0.1"), Some(String::from("en")));
+        assert_eq!(preferred("en;q=abc, de;q=0.1"), Some(String::from("de")));
+        assert_eq!(preferred("en;q=0"), None);
+        assert_eq!(preferred("*"), None);
+        assert_eq!(preferred(""), None);
+    }
+}
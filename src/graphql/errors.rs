@@ -0,0 +1,424 @@
+//! Graphql errors with a stable error code
+//!
+//! Errors of mutations use the same codes as the REST api in
+//! `extensions.code`. If the error is caused by a single input field,
+//! `extensions.field` contains the path to it, starting with the name
+//! of the argument:
+//!
+//! ```json
+//! {
+//!   "message": "The requested resource does not exist",
+//!   "path": ["UpdatePost"],
+//!   "extensions": { "code": "NOT_FOUND", "field": ["UpdatePost", "id"] }
+//! }
+//! ```
+//!
+//! Invalid input is reported by one error for each failed rule, with the
+//! `VALIDATION_FAILED` code and the name of the rule in `extensions.rule`.
+//! Paths to the inputs of batch mutations contain the index of the input.
+//!
+//! With the experimental `localized-errors` feature, messages with a
+//! translation are shown in the preferred language of the caller.
+
+use crate::authorization::AuthorizationError;
+use crate::error::ApiError;
+use crate::validation::ValidationError;
+use diesel::result::Error as DieselError;
+use juniper::{FieldError, IntoFieldError, Object, Value};
+use std::fmt::Display;
+use wundergraph::scalar::WundergraphScalarValue;
+
+/// Feature flag translating error messages to the locale of the caller
+pub const LOCALIZED_ERRORS: &str = "localized-errors";
+
+/// Translations of error messages by language
+const LOCALIZED_MESSAGES: &[(&str, &str, &str)] = &[
+    (
+        "de",
+        "The requested resource does not exist",
+        "Die angeforderte Ressource existiert nicht",
+    ),
+    ("de", "Authentication required", "Anmeldung erforderlich"),
+    (
+        "de",
+        "An unexpected error occurred",
+        "Ein unerwarteter Fehler ist aufgetreten",
+    ),
+    (
+        "de",
+        "The resource was changed concurrently, retry the request",
+        "Die Ressource wurde gleichzeitig geändert, bitte die Anfrage wiederholen",
+    ),
+    (
+        "de",
+        "The database is currently not available, retry later",
+        "Die Datenbank ist derzeit nicht verfügbar, bitte später erneut versuchen",
+    ),
+    (
+        "de",
+        "The author does not exist",
+        "Der Autor existiert nicht",
+    ),
+];
+
+/// A graphql error with `code` and, if given, the `field` path as extensions
+pub fn graphql_error(
+    code: &str,
+    message: impl Display,
+    field: Option<&[&str]>,
+) -> FieldError<WundergraphScalarValue> {
+    let mut extensions = Object::with_capacity(2);
+    extensions.add_field("code", Value::scalar(code));
+    if let Some(field) = field {
+        extensions.add_field(
+            "field",
+            Value::list(field.iter().map(|f| Value::scalar(*f)).collect()),
+        );
+    }
+    FieldError::new(message, Value::Object(extensions))
+}
+
+impl IntoFieldError<WundergraphScalarValue> for ApiError {
+    fn into_field_error(self) -> FieldError<WundergraphScalarValue> {
+        graphql_error(self.code(), &self, None)
+    }
+}
+
+/// The error of a mutation failing with `e`
+///
+/// `argument` is the name of the input object of the mutation, missing
+/// rows are reported for its `id`, constraint violations for the field
+/// of the violated column if the database names it.
+pub fn database_error(e: DieselError, argument: &str) -> FieldError<WundergraphScalarValue> {
+    let field = match e {
+        DieselError::NotFound => Some(String::from("id")),
+        DieselError::DatabaseError(_, ref info) => info.column_name().map(camel_case),
+        _ => None,
+    };
+    let error = ApiError::from(e);
+    match field {
+        Some(field) => graphql_error(error.code(), &error, Some(&[argument, &field])),
+        None => error.into_field_error(),
+    }
+}
+
+/// The error of an authorization check, `argument` is the
+/// input object containing the id of the changed row
+pub fn authorization_error(
+    e: AuthorizationError,
+    argument: Option<&str>,
+) -> FieldError<WundergraphScalarValue> {
+    match (e, argument) {
+        (e @ AuthorizationError::NotOwner(_), Some(argument)) => {
+            let error = ApiError::from(e);
+            graphql_error(error.code(), &error, Some(&[argument, "id"]))
+        }
+        (e, _) => ApiError::from(e).into_field_error(),
+    }
+}
+
+/// The error of the author check of the input given as `argument`,
+/// `index` is the position of the input in the list of a batch mutation
+pub fn author_error(
+    e: AuthorizationError,
+    argument: &str,
+    index: Option<usize>,
+) -> FieldError<WundergraphScalarValue> {
+    let error = match e {
+        AuthorizationError::NotAuthor => ApiError::from(e),
+        e => return ApiError::from(e).into_field_error(),
+    };
+    let mut field = vec![Value::scalar(argument)];
+    field.extend(index.map(|i| Value::scalar(i as i32)));
+    field.push(Value::scalar("author"));
+    let mut extensions = Object::with_capacity(2);
+    extensions.add_field("code", Value::scalar(error.code()));
+    extensions.add_field("field", Value::list(field));
+    FieldError::new(error, Value::Object(extensions))
+}
+
+/// The errors of the input given as `argument` failing validation,
+/// `index` is the position of the input in the list of a batch mutation
+pub fn validation_errors(
+    e: ValidationError,
+    argument: &str,
+    index: Option<usize>,
+) -> Vec<FieldError<WundergraphScalarValue>> {
+    let violations = match e {
+        ValidationError::Invalid(violations) => violations,
+        ValidationError::Database(e) => return vec![ApiError::from(e).into_field_error()],
+    };
+    violations
+        .into_iter()
+        .map(|violation| {
+            let mut field = vec![Value::scalar(argument)];
+            field.extend(index.map(|i| Value::scalar(i as i32)));
+            field.push(Value::scalar(violation.field));
+            let mut extensions = Object::with_capacity(3);
+            extensions.add_field("code", Value::scalar("VALIDATION_FAILED"));
+            extensions.add_field("rule", Value::scalar(violation.rule));
+            extensions.add_field("field", Value::list(field));
+            FieldError::new(
+                format!("`{}` {}", violation.field, violation.message),
+                Value::Object(extensions),
+            )
+        })
+        .collect()
+}
+
+/// Translate the messages of the errors in a graphql `response` to
+/// `locale`, messages without a translation are kept
+pub fn localize_errors(response: &mut serde_json::Value, locale: &str) {
+    let language = locale.split('-').next().unwrap_or_default();
+    let errors = match response.get_mut("errors").and_then(|e| e.as_array_mut()) {
+        Some(errors) => errors,
+        None => return,
+    };
+    for message in errors.iter_mut().filter_map(|e| e.get_mut("message")) {
+        let translation = LOCALIZED_MESSAGES.iter().find(|(l, original, _)| {
+            l.eq_ignore_ascii_case(language) && message.as_str() == Some(original)
+        });
+        if let Some((_, _, translated)) = translation {
+            *message = serde_json::Value::from(*translated);
+        }
+    }
+}
+
+/// Input fields are named like their columns in camel case
+fn camel_case(column: &str) -> String {
+    let mut parts = column.split('_');
+    let mut name = String::from(parts.next().unwrap_or_default());
+    for part in parts {
+        let mut chars = part.chars();
+        if let Some(first) = chars.next() {
+            name.extend(first.to_uppercase());
+            name.push_str(chars.as_str());
+        }
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_pool;
+    use crate::graphql::tests::execute;
+    use crate::model::users::tests::insert_user;
+    use crate::model::users::Role;
+    use serde_json::json;
+
+    /// The code and field of the only error of `response`
+    fn error_of(response: &serde_json::Value) -> (&serde_json::Value, &serde_json::Value) {
+        let errors = response["errors"].as_array().unwrap();
+        assert_eq!(errors.len(), 1, "{}", response);
+        (
+            &errors[0]["extensions"]["code"],
+            &errors[0]["extensions"]["field"],
+        )
+    }
+
+    #[test]
+    fn missing_rows_are_reported_for_the_id() {
+        let pool = match test_pool() {
+            Some(pool) => pool,
+            None => return,
+        };
+        let user = insert_user(&pool.get().unwrap(), &[Role::Author]);
+        let update = execute(
+            &pool,
+            Some(user),
+            &format!(
+                "mutation {{ UpdatePost(UpdatePost: {{ id: -1, title: \"Title\", author: {} }}) {{ id }} }}",
+                user
+            ),
+        );
+        assert_eq!(
+            error_of(&update),
+            (&json!("NOT_FOUND"), &json!(["UpdatePost", "id"]))
+        );
+        let delete = execute(
+            &pool,
+            Some(user),
+            "mutation { DeleteComment(DeleteComment: { id: -1 }) { count } }",
+        );
+        assert_eq!(
+            error_of(&delete),
+            (&json!("NOT_FOUND"), &json!(["DeleteComment", "id"]))
+        );
+    }
+
+    #[test]
+    fn missing_versions_are_reported_for_their_argument() {
+        let pool = match test_pool() {
+            Some(pool) => pool,
+            None => return,
+        };
+        let user = insert_user(&pool.get().unwrap(), &[Role::Author]);
+        let created = execute(
+            &pool,
+            Some(user),
+            &format!(
+                "mutation {{ CreatePost(NewPost: {{ title: \"Title\", author: {} }}) {{ id }} }}",
+                user
+            ),
+        );
+        let diff = |from, to| {
+            let query = format!(
+                "{{ postDiff(id: {}, from: {}, to: {}) {{ id }} }}",
+                created["data"]["CreatePost"]["id"], from, to
+            );
+            execute(&pool, None, &query)
+        };
+        assert_eq!(
+            diff(0, 0)["data"]["postDiff"]["id"],
+            created["data"]["CreatePost"]["id"]
+        );
+        assert_eq!(error_of(&diff(0, 1)), (&json!("NOT_FOUND"), &json!(["to"])));
+        assert_eq!(
+            error_of(&diff(1, 0)),
+            (&json!("NOT_FOUND"), &json!(["from"]))
+        );
+    }
+
+    #[test]
+    fn authorization_failures_are_reported_for_the_checked_field() {
+        let pool = match test_pool() {
+            Some(pool) => pool,
+            None => return,
+        };
+        let (user, other) = {
+            let conn = pool.get().unwrap();
+            (
+                insert_user(&conn, &[Role::Author]),
+                insert_user(&conn, &[Role::Author]),
+            )
+        };
+        let create = |author| {
+            format!(
+                "mutation {{ CreatePost(NewPost: {{ title: \"Title\", author: {} }}) {{ id }} }}",
+                author
+            )
+        };
+        let anonymous = execute(&pool, None, &create(user));
+        assert_eq!(
+            error_of(&anonymous),
+            (&json!("UNAUTHENTICATED"), &serde_json::Value::Null)
+        );
+        let foreign = execute(&pool, Some(user), &create(other));
+        assert_eq!(
+            error_of(&foreign),
+            (&json!("FORBIDDEN"), &json!(["NewPost", "author"]))
+        );
+        let batch = execute(
+            &pool,
+            Some(user),
+            &format!(
+                "mutation {{ CreatePosts(NewPosts: [
+                    {{ title: \"Title\", author: {} }},
+                    {{ title: \"Title\", author: {} }}
+                ]) {{ id }} }}",
+                user, other
+            ),
+        );
+        assert_eq!(
+            error_of(&batch),
+            (&json!("FORBIDDEN"), &json!(["NewPosts", 1, "author"]))
+        );
+
+        let created = execute(&pool, Some(other), &create(other));
+        let update = execute(
+            &pool,
+            Some(user),
+            &format!(
+                "mutation {{ UpdatePost(UpdatePost: {{ id: {}, title: \"Mine\", author: {} }}) {{ id }} }}",
+                created["data"]["CreatePost"]["id"], user
+            ),
+        );
+        assert_eq!(
+            error_of(&update),
+            (&json!("FORBIDDEN"), &json!(["UpdatePost", "id"]))
+        );
+    }
+
+    #[test]
+    fn each_failed_rule_is_reported_for_its_field() {
+        let pool = match test_pool() {
+            Some(pool) => pool,
+            None => return,
+        };
+        let user = insert_user(&pool.get().unwrap(), &[Role::Author]);
+        let response = execute(
+            &pool,
+            Some(user),
+            &format!(
+                "mutation {{ CreatePosts(NewPosts: [
+                    {{ title: \"Title\", author: {0} }},
+                    {{ title: \"\\t\", author: {0} }}
+                ]) {{ id }} }}",
+                user
+            ),
+        );
+        let mut errors: Vec<_> = response["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["extensions"].clone())
+            .collect();
+        errors.sort_by_key(|e| e["rule"].to_string());
+        assert_eq!(
+            errors,
+            vec![
+                json!({ "code": "VALIDATION_FAILED", "rule": "allowedCharacters", "field": ["NewPosts", 1, "title"] }),
+                json!({ "code": "VALIDATION_FAILED", "rule": "notBlank", "field": ["NewPosts", 1, "title"] }),
+            ]
+        );
+        assert_eq!(response["data"], serde_json::Value::Null);
+    }
+
+    #[test]
+    #[cfg(feature = "postgres")]
+    fn constraint_violations_are_reported_for_the_column() {
+        use crate::db::test_connection;
+        use diesel::RunQueryDsl;
+
+        let conn = match test_connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let e = diesel::sql_query(
+            "INSERT INTO posts (title, author, post_state) VALUES ('Title', 1, NULL)",
+        )
+        .execute(&conn)
+        .unwrap_err();
+        let extensions = serde_json::to_value(database_error(e, "NewPost").extensions()).unwrap();
+        assert_eq!(
+            extensions,
+            json!({ "code": "CONSTRAINT_VIOLATION", "field": ["NewPost", "postState"] })
+        );
+    }
+
+    #[test]
+    fn messages_are_translated_by_language() {
+        let mut response = json!({
+            "data": null,
+            "errors": [
+                { "message": "Authentication required", "extensions": { "code": "UNAUTHENTICATED" } },
+                { "message": "`title` must not be empty" },
+            ],
+        });
+        localize_errors(&mut response, "de-CH");
+        assert_eq!(response["errors"][0]["message"], "Anmeldung erforderlich");
+        assert_eq!(
+            response["errors"][0]["extensions"]["code"],
+            "UNAUTHENTICATED"
+        );
+        assert_eq!(
+            response["errors"][1]["message"],
+            "`title` must not be empty"
+        );
+
+        let mut response = json!({ "errors": [{ "message": "Authentication required" }] });
+        localize_errors(&mut response, "fr");
+        assert_eq!(response["errors"][0]["message"], "Authentication required");
+    }
+}
@@ -0,0 +1,430 @@
+//! Limits for the size of graphql queries
+//!
+//! The relations between entities allow a single query to load large
+//! parts of the database (`Users { posts { comments { author { posts ... }}}}`).
+//! Before a request is executed it is therefore run against `MeasureSchema`.
+//! That schema shares all types with the real one, so juniper parses and
+//! validates the request as usual, but its root fields only measure the
+//! selection below them instead of loading anything.
+//!
+//! A query is measured by three numbers:
+//!
+//! * its depth, the number of nested fields
+//! * the number of selected fields, fragments are counted every time they are used
+//! * its complexity, each field costs 1 and the cost of the selection of a
+//!   list field is multiplied by its `limit` argument, or by
+//!   `DEFAULT_LIST_SIZE` if it has none. The `edges` of a relay connection
+//!   are counted as often as its `first` or `last` argument, capped like by
+//!   the connection itself, or `DEFAULT_PER_PAGE` times without one
+//!
+//! Introspection queries are not measured.
+
+use crate::pagination::{DEFAULT_PER_PAGE, MAX_PAGE_SIZE};
+use juniper::meta::{Field, MetaType};
+use juniper::{
+    Arguments, ExecutionResult, Executor, FieldError, FromInputValue, GraphQLType, InputValue,
+    Registry, Selection, Type, Value,
+};
+use std::cell::Cell;
+use std::convert::TryFrom;
+use std::fmt;
+use std::marker::PhantomData;
+use wundergraph::scalar::WundergraphScalarValue;
+
+/// The assumed number of items of a list field without a `limit` argument
+pub const DEFAULT_LIST_SIZE: usize = 10;
+
+/// The maximal size of queries accepted by `/graphql`
+#[derive(Debug, Clone, Copy)]
+pub struct QueryLimits {
+    pub max_depth: usize,
+    pub max_fields: usize,
+    pub max_complexity: usize,
+}
+
+impl QueryLimits {
+    /// Returns a graphql error describing the first exceeded limit
+    pub fn check(&self, cost: QueryCost) -> Result<(), FieldError<WundergraphScalarValue>> {
+        let exceeded = [
+            ("depth", cost.depth, self.max_depth),
+            ("fieldCount", cost.fields, self.max_fields),
+            ("complexity", cost.complexity, self.max_complexity),
+        ]
+        .iter()
+        .copied()
+        .find(|&(_, actual, maximum)| actual > maximum);
+        match exceeded {
+            None => Ok(()),
+            Some((limit, actual, maximum)) => {
+                let mut extensions = juniper::Object::with_capacity(3);
+                extensions.add_field("limit", Value::scalar(limit));
+                extensions.add_field("actual", Value::scalar(saturating_i32(actual)));
+                extensions.add_field("maximum", Value::scalar(saturating_i32(maximum)));
+                Err(FieldError::new(
+                    format!(
+                        "Query is too large: its {} of {} exceeds the maximum of {}",
+                        limit, actual, maximum
+                    ),
+                    Value::Object(extensions),
+                ))
+            }
+        }
+    }
+}
+
+fn saturating_i32(value: usize) -> i32 {
+    i32::try_from(value).unwrap_or(i32::MAX)
+}
+
+/// The size of a query or of a part of it
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct QueryCost {
+    pub depth: usize,
+    pub fields: usize,
+    pub complexity: usize,
+}
+
+impl QueryCost {
+    /// The cost of two selections next to each other
+    fn and(self, other: QueryCost) -> QueryCost {
+        QueryCost {
+            depth: self.depth.max(other.depth),
+            fields: self.fields.saturating_add(other.fields),
+            complexity: self.complexity.saturating_add(other.complexity),
+        }
+    }
+
+    /// The cost of a field returning `items` objects with this selection
+    fn below_field(self, items: usize) -> QueryCost {
+        QueryCost {
+            depth: self.depth + 1,
+            fields: self.fields.saturating_add(1),
+            complexity: self.complexity.saturating_mul(items).saturating_add(1),
+        }
+    }
+}
+
+/// Context of `MeasureSchema` collecting the cost of the executed query
+#[derive(Debug, Default)]
+pub struct Measurement {
+    total: Cell<QueryCost>,
+    selection: Cell<QueryCost>,
+}
+
+impl Measurement {
+    pub fn cost(&self) -> QueryCost {
+        self.total.get()
+    }
+}
+
+impl juniper::Context for Measurement {}
+
+/// Root object measuring each field of `T` instead of resolving it
+pub struct Measure<T>(PhantomData<T>);
+
+impl<T> Default for Measure<T> {
+    fn default() -> Self {
+        Measure(PhantomData)
+    }
+}
+
+impl<T> fmt::Debug for Measure<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Measure")
+    }
+}
+
+impl<T> GraphQLType<WundergraphScalarValue> for Measure<T>
+where
+    T: GraphQLType<WundergraphScalarValue, TypeInfo = ()>,
+{
+    type Context = Measurement;
+    type TypeInfo = ();
+
+    fn name(info: &Self::TypeInfo) -> Option<&str> {
+        T::name(info)
+    }
+
+    fn meta<'r>(
+        info: &Self::TypeInfo,
+        registry: &mut Registry<'r, WundergraphScalarValue>,
+    ) -> MetaType<'r, WundergraphScalarValue>
+    where
+        WundergraphScalarValue: 'r,
+    {
+        T::meta(info, registry)
+    }
+
+    fn resolve_field(
+        &self,
+        info: &Self::TypeInfo,
+        field_name: &str,
+        arguments: &Arguments<WundergraphScalarValue>,
+        executor: &Executor<Self::Context, WundergraphScalarValue>,
+    ) -> ExecutionResult<WundergraphScalarValue> {
+        let field = Self::name(info)
+            .and_then(|name| executor.schema().concrete_type_by_name(name))
+            .and_then(|meta| meta.field_by_name(field_name));
+        if let Some(field) = field {
+            let ctx = executor.context();
+            ctx.selection.set(QueryCost::default());
+            let page_size = connection_page_size(field, |name| arguments.get::<i32>(name));
+            executor.resolve(
+                &field.field_type.innermost_name().to_owned(),
+                &SelectionCost { page_size },
+            )?;
+            let limit = arguments.get::<i32>("limit");
+            let cost = ctx
+                .selection
+                .get()
+                .below_field(list_size(&field.field_type, limit, None));
+            ctx.total.set(ctx.total.get().and(cost));
+        }
+        Ok(Value::null())
+    }
+}
+
+/// Measures the selection of the field it is resolved for
+///
+/// It is never part of a schema, the type info is the
+/// name of the type the selection is applied to.
+struct SelectionCost {
+    /// The page size if the field returns a relay connection
+    page_size: Option<usize>,
+}
+
+impl GraphQLType<WundergraphScalarValue> for SelectionCost {
+    type Context = Measurement;
+    type TypeInfo = String;
+
+    fn name(_info: &Self::TypeInfo) -> Option<&str> {
+        None
+    }
+
+    fn meta<'r>(
+        _info: &Self::TypeInfo,
+        _registry: &mut Registry<'r, WundergraphScalarValue>,
+    ) -> MetaType<'r, WundergraphScalarValue>
+    where
+        WundergraphScalarValue: 'r,
+    {
+        unreachable!("Only used to measure selections")
+    }
+
+    fn resolve(
+        &self,
+        info: &Self::TypeInfo,
+        selection_set: Option<&[Selection<WundergraphScalarValue>]>,
+        executor: &Executor<Self::Context, WundergraphScalarValue>,
+    ) -> Value<WundergraphScalarValue> {
+        let cost = selection_set
+            .map(|s| selection_cost(executor, info, s, self.page_size))
+            .unwrap_or_default();
+        executor.context().selection.set(cost);
+        Value::null()
+    }
+}
+
+/// The cost of a selection applied to `type_name`, `page_size` is
+/// the page size of the relay connection the type belongs to
+fn selection_cost(
+    executor: &Executor<Measurement, WundergraphScalarValue>,
+    type_name: &str,
+    selection_set: &[Selection<WundergraphScalarValue>],
+    page_size: Option<usize>,
+) -> QueryCost {
+    selection_set
+        .iter()
+        .map(|selection| match selection {
+            Selection::Field(field) => {
+                let field = &field.item;
+                let meta = executor
+                    .schema()
+                    .concrete_type_by_name(type_name)
+                    .and_then(|t| t.field_by_name(field.name.item));
+                // Fields missing in the schema are introspection fields like `__typename`
+                let meta = match meta {
+                    Some(meta) => meta,
+                    None => return QueryCost::default().below_field(1),
+                };
+                let argument = |argument: &str| {
+                    field
+                        .arguments
+                        .iter()
+                        .flat_map(|arguments| &arguments.item.items)
+                        .find(|(name, _)| name.item == argument)
+                        .and_then(|(_, value)| match value.item {
+                            InputValue::Variable(ref name) => executor
+                                .variables()
+                                .get(name)
+                                .and_then(i32::from_input_value),
+                            ref value => i32::from_input_value(value),
+                        })
+                };
+                let children = field
+                    .selection_set
+                    .as_ref()
+                    .map(|s| {
+                        let page_size = connection_page_size(meta, argument);
+                        selection_cost(executor, meta.field_type.innermost_name(), s, page_size)
+                    })
+                    .unwrap_or_default();
+                children.below_field(list_size(&meta.field_type, argument("limit"), page_size))
+            }
+            Selection::FragmentSpread(spread) => executor
+                .fragment_by_name(spread.item.name.item)
+                .map(|fragment| {
+                    selection_cost(
+                        executor,
+                        fragment.type_condition.item,
+                        &fragment.selection_set,
+                        page_size,
+                    )
+                })
+                .unwrap_or_default(),
+            Selection::InlineFragment(fragment) => {
+                let type_name = fragment
+                    .item
+                    .type_condition
+                    .as_ref()
+                    .map_or(type_name, |t| t.item);
+                selection_cost(executor, type_name, &fragment.item.selection_set, page_size)
+            }
+        })
+        .fold(QueryCost::default(), QueryCost::and)
+}
+
+/// The number of items a field of the given type is assumed to return
+///
+/// Lists without `limit` are assumed to be as large as the page
+/// of the relay connection they belong to, if there is one.
+fn list_size(field_type: &Type<'_>, limit: Option<i32>, page_size: Option<usize>) -> usize {
+    match field_type {
+        Type::List(_) | Type::NonNullList(_) => match limit {
+            Some(limit) => limit.max(0) as usize,
+            None => page_size.unwrap_or(DEFAULT_LIST_SIZE),
+        },
+        Type::Named(_) | Type::NonNullNamed(_) => 1,
+    }
+}
+
+/// The number of edges loaded by a field returning a relay connection,
+/// `None` for other fields
+///
+/// Connections are recognized by their `first` argument.
+fn connection_page_size(
+    field: &Field<'_, WundergraphScalarValue>,
+    argument: impl Fn(&str) -> Option<i32>,
+) -> Option<usize> {
+    let is_connection = field
+        .arguments
+        .iter()
+        .flatten()
+        .any(|argument| argument.name == "first");
+    if !is_connection {
+        return None;
+    }
+    let size = argument("first")
+        .or_else(|| argument("last"))
+        .map_or(DEFAULT_PER_PAGE, i64::from);
+    Some(size.clamp(0, MAX_PAGE_SIZE) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MeasureSchema;
+    use juniper::Variables;
+
+    fn measure(query: &str, variables: Variables<WundergraphScalarValue>) -> QueryCost {
+        let schema = MeasureSchema::new(Measure::default(), Measure::default());
+        let measurement = Measurement::default();
+        let (_, errors) = juniper::execute(query, None, &schema, &variables, &measurement).unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+        measurement.cost()
+    }
+
+    fn complexity(query: &str) -> usize {
+        measure(query, Variables::new()).complexity
+    }
+
+    #[test]
+    fn lists_count_their_limit() {
+        let cost = measure("{ Users { id name } }", Variables::new());
+        assert_eq!(
+            cost,
+            QueryCost {
+                depth: 2,
+                fields: 3,
+                complexity: 2 * DEFAULT_LIST_SIZE + 1,
+            }
+        );
+        assert_eq!(complexity("{ Users(limit: 3) { id name } }"), 2 * 3 + 1);
+        assert_eq!(
+            complexity("{ Users(limit: 3) { posts { id } } }"),
+            (DEFAULT_LIST_SIZE + 1) * 3 + 1
+        );
+    }
+
+    #[test]
+    fn connections_count_their_page_size() {
+        // `node { id }` costs 2, each edge costs 1 more
+        let edges = |n: usize| 2 * n + 1 + 1;
+        assert_eq!(
+            complexity("{ usersConnection(first: 50) { edges { node { id } } } }"),
+            edges(50)
+        );
+        assert_eq!(
+            complexity("{ usersConnection(last: 5) { edges { node { id } } } }"),
+            edges(5)
+        );
+        assert_eq!(
+            complexity("{ usersConnection { edges { node { id } } } }"),
+            edges(DEFAULT_PER_PAGE as usize)
+        );
+        assert_eq!(
+            complexity("{ usersConnection(first: 100000) { edges { node { id } } } }"),
+            edges(MAX_PAGE_SIZE as usize)
+        );
+    }
+
+    #[test]
+    fn connection_page_sizes_are_read_from_variables_and_fragments() {
+        let mut variables = Variables::new();
+        variables.insert("first".into(), InputValue::scalar(30));
+        let cost = measure(
+            "query($first: Int) { usersConnection(first: $first) { ...Page } }
+             fragment Page on UserConnection { edges { node { id } } }",
+            variables,
+        );
+        assert_eq!(cost.complexity, 2 * 30 + 1 + 1);
+    }
+
+    #[test]
+    fn lists_below_connections_use_their_own_limit() {
+        // Only the edges of the connection are loaded `first` times
+        assert_eq!(
+            complexity("{ usersConnection(first: 50) { edges { node { posts { id } } } } }"),
+            ((DEFAULT_LIST_SIZE + 1) + 1) * 50 + 1 + 1
+        );
+    }
+
+    #[test]
+    fn exceeded_limits_are_named() {
+        let limits = QueryLimits {
+            max_depth: 10,
+            max_fields: 200,
+            max_complexity: 100,
+        };
+        let cost = measure(
+            "{ usersConnection(first: 50) { edges { node { id } } } }",
+            Variables::new(),
+        );
+        let error = limits.check(cost).unwrap_err();
+        assert_eq!(
+            error.message(),
+            "Query is too large: its complexity of 102 exceeds the maximum of 100"
+        );
+        assert!(limits.check(QueryCost::default()).is_ok());
+    }
+}
@@ -1,64 +1,243 @@
-use crate::model::comments::NewComment;
-use crate::model::posts::PostState;
-use crate::model::users::NewUser;
+use crate::auth::Identity;
+use crate::authorization::{authorize, authorize_author, require_role, Action, Authored};
+use crate::db::{DbBackend, DbConnection, Timestamp};
+use crate::error::ApiError;
+use crate::model::comments::{remove_comment, NewComment};
+use crate::model::posts::{
+    diff_versions, post_version, revert_post, transition_post, PostDiff, PostState, PostTransition,
+};
+use crate::model::users::{
+    grant_role, remove_user, revoke_role, NewUser, RemoveUserError, RevokeRoleError, Role,
+};
 use crate::schema::*;
+use crate::validation::{
+    Validate, ValidationError, Validator, COMMENT, POST_CONTENT, POST_TITLE, USER_NAME,
+};
+use crate::views::*;
 use chrono::{DateTime, Utc};
-use diesel::pg::Pg;
 use diesel::prelude::*;
-use juniper::{ExecutionResult, Executor, GraphQLInputObject, Selection, Value};
+use juniper::meta::{MetaType, ObjectMeta};
+use juniper::{
+    Arguments, ExecutionResult, Executor, FieldError, FieldResult, FromInputValue,
+    GraphQLInputObject, GraphQLType, IntoFieldError, LookAheadMethods, LookAheadValue, Registry,
+    Selection, Value,
+};
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use wundergraph::graphql_type::GraphqlWrapper;
+use wundergraph::juniper_ext::FromLookAheadValue;
 use wundergraph::prelude::*;
-use wundergraph::query_builder::mutations::{HandleBatchInsert, HandleInsert, HandleUpdate};
+use wundergraph::query_builder::mutations::{
+    DeletedCount, HandleBatchInsert, HandleDelete, HandleInsert,
+};
 use wundergraph::query_builder::selection::LoadingHandler;
 use wundergraph::scalar::WundergraphScalarValue;
 
-mod post_at_version;
+#[macro_use]
+mod connection;
+mod context;
+mod errors;
+mod limits;
+mod sdl;
+#[macro_use]
+mod versioning;
+#[cfg(feature = "postgres")]
+pub mod subscription;
 
-use self::post_at_version::*;
+use self::connection::RelayConnection;
+pub use self::context::{FeatureFlags, RequestContext, RequestMetadata, REQUEST_ID_HEADER};
+use self::errors::{
+    author_error, authorization_error, database_error, graphql_error, validation_errors,
+};
+pub use self::errors::{localize_errors, LOCALIZED_ERRORS};
+pub use self::limits::{Measure, Measurement, QueryLimits};
+#[cfg(feature = "sqlite")]
+pub use self::sdl::schema_language;
+#[cfg(feature = "postgres")]
+pub use self::sdl::schema_language_with_subscriptions;
+use self::versioning::*;
 
+current_entity! {
+    User(users, "users") {
+        id: i32,
+        name: String,
+        joined_at: Timestamp,
+    }
+    relations {
+        posts: HasMany<Post, posts::author>,
+        comments: HasMany<Comment, comments::author>,
+        roles: HasMany<UserRole, user_roles::user_id>,
+    }
+}
+
+/// A role granted to a user
 #[derive(WundergraphEntity, Identifiable, Debug, Clone)]
-#[table_name = "users"]
-pub struct User {
+#[table_name = "user_roles"]
+pub struct UserRole {
     id: i32,
-    name: String,
-    joined_at: DateTime<Utc>,
-    posts: HasMany<Post, posts::author>,
-    comments: HasMany<Comment, comments::author>,
+    #[column_name = "user_id"]
+    user: HasOne<i32, User>,
+    role: Role,
+}
+
+current_entity! {
+    Post(posts, "posts") {
+        id: i32,
+        title: String,
+        content: Option<String>,
+        published_at: Option<Timestamp>,
+        author: HasOne<i32, User>,
+        post_state: PostState,
+    }
+    relations {
+        comments: HasMany<Comment, comments::post>,
+        versions: HasMany<PostVersion, post_versions::id>,
+    }
 }
 
+/// A single revision of a post
+///
+/// Valid from `version` until (excluding) `valid_until`,
+/// the current revision has no end
 #[derive(WundergraphEntity, Identifiable, Debug, Clone)]
-#[table_name = "posts"]
-pub struct Post {
+#[table_name = "post_versions"]
+#[primary_key(id, version)]
+pub struct PostVersion {
     id: i32,
+    version: i32,
+    valid_until: Option<i32>,
     title: String,
     content: Option<String>,
-    published_at: DateTime<Utc>,
+    published_at: Option<Timestamp>,
     author: HasOne<i32, User>,
-    comments: HasMany<Comment, comments::post>,
     post_state: PostState,
+    /// The id of the user who wrote the revision, unknown for older revisions
+    editor: Option<i32>,
+    version_start_at: Timestamp,
+    version_end_at: Option<Timestamp>,
+    #[column_name = "id"]
+    post: HasOne<i32, Post>,
 }
 
-#[derive(WundergraphEntity, Identifiable, Debug, Clone)]
-#[table_name = "comments"]
-pub struct Comment {
-    id: i32,
-    comment: String,
-    published_at: DateTime<Utc>,
-    author: HasOne<i32, User>,
-    post: HasOne<i32, Post>,
-    #[column_name = "post"]
-    posts_at_version: HasOne<i32, PostAtVersion>,
+current_entity! {
+    Comment(comments, "comments") {
+        id: i32,
+        comment: String,
+        published_at: Timestamp,
+        author: HasOne<i32, User>,
+        post: HasOne<i32, Post>,
+        #[column_name = "post"]
+        posts_at_version: HasOne<i32, PostAtVersion> as post,
+    }
+    relations {}
 }
 
+// `asOf` is a `DateTimeUtc` with both backends, see `as_of_argument`
 wundergraph::query_object! {
-    Query {
+    EntityQuery {
         User,
         Post,
         Comment,
-        PostAtVersion(version: Option<i32>),
+        PostAtVersion(version: Option<i32>, asOf: Option<DateTime<Utc>>),
+        CommentAtVersion(version: Option<i32>, asOf: Option<DateTime<Utc>>),
+        UserAtVersion(version: Option<i32>, asOf: Option<DateTime<Utc>>),
     }
 }
 
-#[derive(GraphQLInputObject, Identifiable, AsChangeset)]
+// Older versions share the id of the current version,
+// so only current versions could be part of a connection
+relay_node!(User, users);
+relay_node!(Post, posts);
+relay_node!(Comment, comments);
+
+/// The main query object
+///
+/// Extends the entity fields generated by wundergraph
+/// with relay style connections
+#[derive(Debug)]
+pub struct Query<C>(PhantomData<Arc<Mutex<C>>>);
+
+impl<C> Default for Query<C> {
+    fn default() -> Self {
+        Query(PhantomData)
+    }
+}
+
+impl GraphQLType<WundergraphScalarValue> for Query<RequestContext> {
+    type Context = RequestContext;
+    type TypeInfo = ();
+
+    fn name(_info: &Self::TypeInfo) -> Option<&str> {
+        Some("Query")
+    }
+
+    fn meta<'r>(
+        info: &Self::TypeInfo,
+        registry: &mut Registry<'r, WundergraphScalarValue>,
+    ) -> MetaType<'r, WundergraphScalarValue>
+    where
+        WundergraphScalarValue: 'r,
+    {
+        let mut fields = match EntityQuery::<RequestContext>::meta(info, registry) {
+            MetaType::Object(ObjectMeta { fields, .. }) => fields,
+            _ => unreachable!("Wundergraph query objects are always objects"),
+        };
+        fields.push(RelayConnection::<User>::field("usersConnection", registry));
+        fields.push(RelayConnection::<Post>::field("postsConnection", registry));
+        fields.push(RelayConnection::<Comment>::field(
+            "commentsConnection",
+            registry,
+        ));
+        let id = registry.arg::<i32>("id", info);
+        let from = registry.arg::<i32>("from", info);
+        let to = registry.arg::<i32>("to", info);
+        fields.push(
+            registry
+                .field::<PostDiff>("postDiff", info)
+                .argument(id)
+                .argument(from)
+                .argument(to),
+        );
+        registry
+            .build_object_type::<Self>(info, &fields)
+            .into_meta()
+    }
+
+    fn resolve_field(
+        &self,
+        info: &Self::TypeInfo,
+        field_name: &str,
+        arguments: &Arguments<WundergraphScalarValue>,
+        executor: &Executor<Self::Context, WundergraphScalarValue>,
+    ) -> ExecutionResult<WundergraphScalarValue> {
+        match field_name {
+            "usersConnection" => executor.resolve(info, &RelayConnection::<User>::default()),
+            "postsConnection" => executor.resolve(info, &RelayConnection::<Post>::default()),
+            "commentsConnection" => executor.resolve(info, &RelayConnection::<Comment>::default()),
+            "postDiff" => {
+                let conn = executor.context().get_connection();
+                let id = arguments.get("id").expect("Argument is not nullable");
+                let load = |argument| {
+                    let version = arguments.get(argument).expect("Argument is not nullable");
+                    post_version(conn, id, version).map_err(|e| match e {
+                        diesel::result::Error::NotFound => graphql_error(
+                            "NOT_FOUND",
+                            "There is no such version of the post",
+                            Some(&[argument]),
+                        ),
+                        e => ApiError::from(e).into_field_error(),
+                    })
+                };
+                let diff = diff_versions(load("from")?, load("to")?);
+                executor.resolve_with_ctx(info, &diff)
+            }
+            _ => EntityQuery::<RequestContext>::default()
+                .resolve_field(info, field_name, arguments, executor),
+        }
+    }
+}
+
+#[derive(GraphQLInputObject, Identifiable)]
 #[table_name = "users"]
 pub struct UserChangeset {
     id: i32,
@@ -72,10 +251,9 @@ pub struct PostChangeset {
     title: String,
     content: Option<String>,
     author: i32,
-    post_state: PostState,
 }
 
-#[derive(GraphQLInputObject, Identifiable, AsChangeset)]
+#[derive(GraphQLInputObject, Identifiable)]
 #[table_name = "comments"]
 pub struct CommentChangeset {
     id: i32,
@@ -91,71 +269,146 @@ pub struct NewPost {
     author: i32,
 }
 
-impl HandleInsert<Post, NewPost, Pg, PgConnection> for posts::table {
+impl Validate for UserChangeset {
+    fn rules(&self, v: &mut Validator<'_>) -> QueryResult<()> {
+        v.text("name", Some(&self.name), &USER_NAME);
+        Ok(())
+    }
+}
+
+impl Validate for PostChangeset {
+    fn rules(&self, v: &mut Validator<'_>) -> QueryResult<()> {
+        v.text("title", Some(&self.title), &POST_TITLE);
+        v.text("content", self.content.as_deref(), &POST_CONTENT);
+        v.exists::<users::table>("author", Some(self.author))
+    }
+}
+
+impl Validate for CommentChangeset {
+    fn rules(&self, v: &mut Validator<'_>) -> QueryResult<()> {
+        v.text("comment", Some(&self.comment), &COMMENT);
+        v.exists::<users::table>("author", Some(self.author))?;
+        v.exists::<posts::table>("post", Some(self.post))
+    }
+}
+
+impl Validate for NewPost {
+    fn rules(&self, v: &mut Validator<'_>) -> QueryResult<()> {
+        v.text("title", Some(&self.title), &POST_TITLE);
+        v.text("content", self.content.as_deref(), &POST_CONTENT);
+        v.exists::<users::table>("author", Some(self.author))
+    }
+}
+
+impl Authored for PostChangeset {
+    fn author(&self) -> Option<i32> {
+        Some(self.author)
+    }
+}
+
+impl Authored for CommentChangeset {
+    fn author(&self) -> Option<i32> {
+        Some(self.author)
+    }
+}
+
+impl Authored for NewPost {
+    fn author(&self) -> Option<i32> {
+        Some(self.author)
+    }
+}
+
+/// Insert new posts as drafts and return their ids
+fn insert_posts(
+    conn: &DbConnection,
+    editor: Identity,
+    new_posts: Vec<NewPost>,
+) -> QueryResult<Vec<i32>> {
+    let insert = new_posts
+        .into_iter()
+        .map(
+            |NewPost {
+                 title,
+                 content,
+                 author,
+             }| {
+                (
+                    posts::title.eq(title),
+                    posts::content.eq(content),
+                    posts::author.eq(author),
+                    posts::post_state.eq(PostState::Draft),
+                    posts::published_at.eq(Option::<Timestamp>::None),
+                    posts::version_start.eq(0),
+                    posts::version_end.eq(Option::<i32>::None),
+                    posts::editor.eq(editor.user_id),
+                )
+            },
+        )
+        .collect::<Vec<_>>();
+
+    #[cfg(feature = "postgres")]
+    {
+        diesel::insert_into(posts::table)
+            .values(insert)
+            .returning(posts::id)
+            .get_results(conn)
+    }
+    // SQLite does not support `RETURNING`, so each post
+    // is inserted on its own to get its rowid
+    #[cfg(feature = "sqlite")]
+    {
+        insert
+            .into_iter()
+            .map(|post| {
+                diesel::insert_into(posts::table)
+                    .values(post)
+                    .execute(conn)?;
+                posts::table
+                    .select(posts::id)
+                    .filter(diesel::dsl::sql("rowid = last_insert_rowid()"))
+                    .get_result(conn)
+            })
+            .collect()
+    }
+}
+
+impl HandleInsert<Post, NewPost, DbBackend, RequestContext> for posts::table {
     fn handle_insert(
         selection: Option<&'_ [Selection<'_, WundergraphScalarValue>]>,
-        executor: &Executor<'_, PgConnection, WundergraphScalarValue>,
+        executor: &Executor<'_, RequestContext, WundergraphScalarValue>,
         insertable: NewPost,
     ) -> ExecutionResult<WundergraphScalarValue> {
         let ctx = executor.context();
         let conn = ctx.get_connection();
+        let identity = ctx.require_identity()?;
         conn.transaction(|| {
             let look_ahead = executor.look_ahead();
-            let inserted = diesel::insert_into(posts::table)
-                .values((
-                    posts::title.eq(insertable.title),
-                    posts::content.eq(insertable.content),
-                    posts::author.eq(insertable.author),
-                    posts::post_state.eq(PostState::Draft),
-                    posts::version_start.eq(0),
-                    posts::version_end.eq(Option::<i32>::None),
-                ))
-                .returning(posts::id)
-                .get_result::<i32>(conn)?;
+            let inserted = insert_posts(conn, identity, vec![insertable])
+                .map_err(|e| database_error(e, "NewPost"))?;
 
-            let query = <Post as LoadingHandler<_, PgConnection>>::build_query(&[], &look_ahead)?
-                .filter(posts::id.eq(inserted));
+            let query = <Post as LoadingHandler<_, RequestContext>>::build_query(&[], &look_ahead)?
+                .filter(posts::id.eq_any(inserted));
             let items = Post::load(&look_ahead, selection, executor, query)?;
             Ok(items.into_iter().next().unwrap_or(Value::Null))
         })
     }
 }
 
-impl HandleBatchInsert<Post, NewPost, Pg, PgConnection> for posts::table {
+impl HandleBatchInsert<Post, NewPost, DbBackend, RequestContext> for posts::table {
     fn handle_batch_insert(
         selection: Option<&'_ [Selection<'_, WundergraphScalarValue>]>,
-        executor: &Executor<'_, PgConnection, WundergraphScalarValue>,
+        executor: &Executor<'_, RequestContext, WundergraphScalarValue>,
         insertable: Vec<NewPost>,
     ) -> ExecutionResult<WundergraphScalarValue> {
         let ctx = executor.context();
         let conn = ctx.get_connection();
-        let insert = insertable
-            .into_iter()
-            .map(
-                |NewPost {
-                     title,
-                     content,
-                     author,
-                 }| {
-                    (
-                        posts::title.eq(title),
-                        posts::content.eq(content),
-                        posts::author.eq(author),
-                        posts::post_state.eq(PostState::Draft),
-                        posts::version_start.eq(0),
-                        posts::version_end.eq(Option::<i32>::None),
-                    )
-                },
-            )
-            .collect::<Vec<_>>();
+        let identity = ctx.require_identity()?;
         conn.transaction(|| {
             let look_ahead = executor.look_ahead();
-            let inserted = diesel::insert_into(posts::table)
-                .values(insert)
-                .returning(posts::id)
-                .get_results::<i32>(conn)?;
+            let inserted = insert_posts(conn, identity, insertable)
+                .map_err(|e| database_error(e, "NewPosts"))?;
 
-            let query = <Post as LoadingHandler<_, PgConnection>>::build_query(&[], &look_ahead)?
+            let query = <Post as LoadingHandler<_, RequestContext>>::build_query(&[], &look_ahead)?
                 .filter(posts::id.eq_any(inserted));
             let items = Post::load(&look_ahead, selection, executor, query)?;
             Ok(Value::list(items))
@@ -163,58 +416,558 @@ impl HandleBatchInsert<Post, NewPost, Pg, PgConnection> for posts::table {
     }
 }
 
-impl HandleUpdate<Post, PostChangeset, Pg, PgConnection> for posts::table {
-    fn handle_update(
+versioned_update!(
+    User,
+    UserChangeset,
+    users,
+    |update| users::name.eq(&update.name),
+    keep joined_at: Timestamp
+);
+
+// The state is only changed by the transitions of `PostTransition`
+versioned_update!(
+    Post,
+    PostChangeset,
+    posts,
+    |update, identity| (
+        posts::title.eq(&update.title),
+        posts::content.eq(&update.content),
+        posts::author.eq(update.author),
+        posts::editor.eq(identity.user_id),
+    ),
+    keep published_at: Option<Timestamp>,
+    post_state: PostState
+);
+
+versioned_update!(
+    Comment,
+    CommentChangeset,
+    comments,
+    |update| (
+        comments::comment.eq(&update.comment),
+        comments::author.eq(update.author),
+        comments::post.eq(update.post),
+    ),
+    keep published_at: Timestamp
+);
+
+/// The input of `DeleteUser`
+///
+/// The default delete mutation of wundergraph removes all versions,
+/// this type selects the `HandleDelete` implementation below instead
+#[derive(Debug, GraphQLInputObject)]
+#[graphql(name = "UsersPrimaryKey", scalar = "WundergraphScalarValue")]
+pub struct UserKey {
+    id: i32,
+}
+
+/// The input of `DeleteComment`, see `UserKey`
+#[derive(Debug, GraphQLInputObject)]
+#[graphql(name = "CommentsPrimaryKey", scalar = "WundergraphScalarValue")]
+pub struct CommentKey {
+    id: i32,
+}
+
+impl HandleDelete<User, UserKey, DbBackend, RequestContext> for users::table {
+    fn handle_delete(
+        executor: &Executor<'_, RequestContext, WundergraphScalarValue>,
+        to_delete: &UserKey,
+    ) -> ExecutionResult<WundergraphScalarValue> {
+        let conn = executor.context().get_connection();
+        remove_user(conn, to_delete.id).map_err(|e| match e {
+            RemoveUserError::Database(e) => database_error(e, "DeleteUser"),
+            e => ApiError::from(e).into_field_error(),
+        })?;
+        executor.resolve_with_ctx(&(), &DeletedCount { count: 1 })
+    }
+}
+
+impl HandleDelete<Comment, CommentKey, DbBackend, RequestContext> for comments::table {
+    fn handle_delete(
+        executor: &Executor<'_, RequestContext, WundergraphScalarValue>,
+        to_delete: &CommentKey,
+    ) -> ExecutionResult<WundergraphScalarValue> {
+        let conn = executor.context().get_connection();
+        remove_comment(conn, to_delete.id).map_err(|e| database_error(e, "DeleteComment"))?;
+        executor.resolve_with_ctx(&(), &DeletedCount { count: 1 })
+    }
+}
+
+wundergraph::mutation_object! {
+    EntityMutation {
+        User(insert = NewUser, update = UserChangeset, delete = UserKey),
+        Post(insert = NewPost, update = PostChangeset, delete = false),
+        Comment(insert = NewComment, update = CommentChangeset, delete = CommentKey),
+    }
+}
+
+/// The main mutation object
+///
+/// Extends the mutations generated by wundergraph
+/// with reverting posts to an older version, changing
+/// the state of posts and granting or revoking roles
+#[derive(Debug)]
+pub struct Mutation<C>(PhantomData<Arc<Mutex<C>>>);
+
+impl<C> Default for Mutation<C> {
+    fn default() -> Self {
+        Mutation(PhantomData)
+    }
+}
+
+impl GraphQLType<WundergraphScalarValue> for Mutation<RequestContext> {
+    type Context = RequestContext;
+    type TypeInfo = ();
+
+    fn name(_info: &Self::TypeInfo) -> Option<&str> {
+        Some("Mutation")
+    }
+
+    fn meta<'r>(
+        info: &Self::TypeInfo,
+        registry: &mut Registry<'r, WundergraphScalarValue>,
+    ) -> MetaType<'r, WundergraphScalarValue>
+    where
+        WundergraphScalarValue: 'r,
+    {
+        let mut fields = match EntityMutation::<RequestContext>::meta(info, registry) {
+            MetaType::Object(ObjectMeta { fields, .. }) => fields,
+            _ => unreachable!("Wundergraph mutation objects are always objects"),
+        };
+        let id = registry.arg::<i32>("id", info);
+        let to_version = registry.arg::<i32>("toVersion", info);
+        fields.push(
+            registry
+                .field::<Option<GraphqlWrapper<Post, DbBackend, RequestContext>>>(
+                    "revertPost",
+                    info,
+                )
+                .argument(id)
+                .argument(to_version),
+        );
+        for name in &["publishPost", "unpublishPost", "restorePost", "deletePost"] {
+            let id = registry.arg::<i32>("id", info);
+            fields.push(
+                registry
+                    .field::<Option<GraphqlWrapper<Post, DbBackend, RequestContext>>>(name, info)
+                    .argument(id),
+            );
+        }
+        for name in &["grantRole", "revokeRole"] {
+            let user = registry.arg::<i32>("user", info);
+            let role = registry.arg::<Role>("role", info);
+            fields.push(
+                registry
+                    .field::<Vec<Role>>(name, info)
+                    .argument(user)
+                    .argument(role),
+            );
+        }
+        registry
+            .build_object_type::<Self>(info, &fields)
+            .into_meta()
+    }
+
+    fn resolve_field(
+        &self,
+        info: &Self::TypeInfo,
+        field_name: &str,
+        arguments: &Arguments<WundergraphScalarValue>,
+        executor: &Executor<Self::Context, WundergraphScalarValue>,
+    ) -> ExecutionResult<WundergraphScalarValue> {
+        authorize_mutation(field_name, arguments, executor)?;
+        if let Err(errors) = validate_mutation(field_name, arguments, executor) {
+            for e in errors {
+                executor.push_error(e);
+            }
+            return Ok(Value::null());
+        }
+        match field_name {
+            "revertPost" => {
+                let revert = PostMutation {
+                    id: arguments.get("id").expect("Argument is not nullable"),
+                    change: PostChange::Revert {
+                        to_version: arguments
+                            .get("toVersion")
+                            .expect("Argument is not nullable"),
+                    },
+                };
+                executor.resolve(info, &revert)
+            }
+            "publishPost" | "unpublishPost" | "restorePost" | "deletePost" => {
+                let transition = match field_name {
+                    "publishPost" => PostTransition::Publish,
+                    "unpublishPost" => PostTransition::Unpublish,
+                    "restorePost" => PostTransition::Restore,
+                    _ => PostTransition::Delete,
+                };
+                let mutation = PostMutation {
+                    id: arguments.get("id").expect("Argument is not nullable"),
+                    change: PostChange::Transition(transition),
+                };
+                executor.resolve(info, &mutation)
+            }
+            "grantRole" | "revokeRole" => {
+                let user = arguments.get("user").expect("Argument is not nullable");
+                let role = arguments.get("role").expect("Argument is not nullable");
+                let conn = executor.context().get_connection();
+                let user_error = |e| match e {
+                    diesel::result::Error::NotFound => {
+                        graphql_error("NOT_FOUND", "There is no such user", Some(&["user"]))
+                    }
+                    e => ApiError::from(e).into_field_error(),
+                };
+                let roles = if field_name == "grantRole" {
+                    grant_role(conn, user, role).map_err(user_error)?
+                } else {
+                    revoke_role(conn, user, role).map_err(|e| match e {
+                        RevokeRoleError::Database(e) => user_error(e),
+                        e => ApiError::from(e).into_field_error(),
+                    })?
+                };
+                executor.resolve_with_ctx(&(), &roles)
+            }
+            _ => EntityMutation::<RequestContext>::default()
+                .resolve_field(info, field_name, arguments, executor),
+        }
+    }
+}
+
+/// The table whose rows a mutation creates, changes or deletes
+#[derive(Debug, Clone, Copy)]
+enum Entity {
+    Users,
+    Posts,
+    Comments,
+}
+
+/// Where a mutation takes the id of the row it changes or deletes
+#[derive(Debug, Clone, Copy)]
+enum IdArgument {
+    /// The `id` argument of the mutation
+    Id,
+    /// The `id` field of the named input object
+    Input(&'static str),
+}
+
+impl IdArgument {
+    /// The input object errors of the authorization are reported for
+    fn input(self) -> Option<&'static str> {
+        match self {
+            IdArgument::Id => None,
+            IdArgument::Input(input) => Some(input),
+        }
+    }
+}
+
+/// The check `authorize_mutation` runs for a mutation
+#[derive(Debug, Clone, Copy)]
+enum MutationRule {
+    /// Only admins could run the mutation
+    Admin,
+    Create(Entity),
+    Change(Entity, IdArgument),
+    Delete(Entity, IdArgument),
+}
+
+/// The rule of the mutation `field_name`, `None` for unknown mutations
+fn mutation_rule(field_name: &str) -> Option<MutationRule> {
+    use self::Entity::*;
+    use self::IdArgument::*;
+    use self::MutationRule::*;
+
+    let rule = match field_name {
+        "CreateUser" | "CreateUsers" => Create(Users),
+        "CreatePost" | "CreatePosts" => Create(Posts),
+        "CreateComment" | "CreateComments" => Create(Comments),
+        "UpdateUser" => Change(Users, Input("UpdateUser")),
+        "UpdatePost" => Change(Posts, Input("UpdatePost")),
+        "UpdateComment" => Change(Comments, Input("UpdateComment")),
+        "DeleteUser" => Delete(Users, Input("DeleteUser")),
+        "DeleteComment" => Delete(Comments, Input("DeleteComment")),
+        "revertPost" | "publishPost" | "unpublishPost" | "restorePost" => Change(Posts, Id),
+        "deletePost" => Delete(Posts, Id),
+        "grantRole" | "revokeRole" => Admin,
+        _ => return None,
+    };
+    Some(rule)
+}
+
+/// Checks the rules of `crate::authorization`
+/// before an entity is created, changed or deleted
+fn authorize_mutation(
+    field_name: &str,
+    arguments: &Arguments<WundergraphScalarValue>,
+    executor: &Executor<RequestContext, WundergraphScalarValue>,
+) -> FieldResult<(), WundergraphScalarValue> {
+    fn single<T>(
+        conn: &DbConnection,
+        identity: Identity,
+        arguments: &Arguments<WundergraphScalarValue>,
+        argument: &str,
+    ) -> FieldResult<(), WundergraphScalarValue>
+    where
+        T: Authored + FromInputValue<WundergraphScalarValue>,
+    {
+        match arguments.get::<T>(argument) {
+            Some(input) => authorize_author(conn, identity, &input)
+                .map_err(|e| author_error(e, argument, None)),
+            None => Ok(()),
+        }
+    }
+
+    fn batch<T>(
+        conn: &DbConnection,
+        identity: Identity,
+        arguments: &Arguments<WundergraphScalarValue>,
+        argument: &str,
+    ) -> FieldResult<(), WundergraphScalarValue>
+    where
+        T: Authored + FromInputValue<WundergraphScalarValue>,
+    {
+        for (i, input) in arguments
+            .get::<Vec<T>>(argument)
+            .unwrap_or_default()
+            .iter()
+            .enumerate()
+        {
+            authorize_author(conn, identity, input)
+                .map_err(|e| author_error(e, argument, Some(i)))?;
+        }
+        Ok(())
+    }
+
+    let ctx = executor.context();
+    let identity = ctx.require_identity()?;
+    let conn = ctx.get_connection();
+    // Unknown mutations are rejected, a new mutation needs a rule first
+    let unauthorizable = |reason: &str| {
+        log::error!("Could not authorize mutation {}: {}", field_name, reason);
+        ApiError::internal().into_field_error()
+    };
+    let rule = mutation_rule(field_name).ok_or_else(|| unauthorizable("no rule"))?;
+    let look_ahead = executor.look_ahead();
+    let id_of = |id: IdArgument| {
+        let id = match id {
+            IdArgument::Id => arguments.get("id"),
+            IdArgument::Input(input) => match look_ahead.argument(input).map(|a| a.value()) {
+                Some(LookAheadValue::Object(fields)) => fields
+                    .iter()
+                    .find(|(name, _)| *name == "id")
+                    .and_then(|(_, id)| i32::from_look_ahead(id)),
+                _ => None,
+            },
+        };
+        id.ok_or_else(|| unauthorizable("missing id"))
+    };
+    let (entity, action, argument) = match rule {
+        MutationRule::Admin => {
+            return require_role(conn, identity, Role::Admin)
+                .map_err(|e| authorization_error(e, None));
+        }
+        MutationRule::Create(entity) => (entity, Action::Create, None),
+        MutationRule::Change(entity, id) => (entity, Action::Change(id_of(id)?), id.input()),
+        MutationRule::Delete(entity, id) => (entity, Action::Delete(id_of(id)?), id.input()),
+    };
+    let authorized = match entity {
+        Entity::Users => authorize::<users::table>(conn, identity, action),
+        Entity::Posts => authorize::<posts::table>(conn, identity, action),
+        Entity::Comments => authorize::<comments::table>(conn, identity, action),
+    };
+    authorized.map_err(|e| authorization_error(e, argument))?;
+
+    match field_name {
+        "CreatePost" => single::<NewPost>(conn, identity, arguments, "NewPost"),
+        "CreatePosts" => batch::<NewPost>(conn, identity, arguments, "NewPosts"),
+        "CreateComment" => single::<NewComment>(conn, identity, arguments, "NewComment"),
+        "CreateComments" => batch::<NewComment>(conn, identity, arguments, "NewComments"),
+        "UpdatePost" => single::<PostChangeset>(conn, identity, arguments, "UpdatePost"),
+        "UpdateComment" => single::<CommentChangeset>(conn, identity, arguments, "UpdateComment"),
+        _ => Ok(()),
+    }
+}
+
+/// Checks the rules of `crate::validation` for the
+/// input of mutations creating or changing entities
+fn validate_mutation(
+    field_name: &str,
+    arguments: &Arguments<WundergraphScalarValue>,
+    executor: &Executor<RequestContext, WundergraphScalarValue>,
+) -> Result<(), Vec<FieldError<WundergraphScalarValue>>> {
+    fn single<T>(
+        conn: &DbConnection,
+        arguments: &Arguments<WundergraphScalarValue>,
+        argument: &str,
+    ) -> Result<(), Vec<FieldError<WundergraphScalarValue>>>
+    where
+        T: Validate + FromInputValue<WundergraphScalarValue>,
+    {
+        match arguments.get::<T>(argument) {
+            Some(input) => input
+                .validate(conn)
+                .map_err(|e| validation_errors(e, argument, None)),
+            None => Ok(()),
+        }
+    }
+
+    fn batch<T>(
+        conn: &DbConnection,
+        arguments: &Arguments<WundergraphScalarValue>,
+        argument: &str,
+    ) -> Result<(), Vec<FieldError<WundergraphScalarValue>>>
+    where
+        T: Validate + FromInputValue<WundergraphScalarValue>,
+    {
+        let mut errors = Vec::new();
+        for (i, input) in arguments
+            .get::<Vec<T>>(argument)
+            .unwrap_or_default()
+            .iter()
+            .enumerate()
+        {
+            match input.validate(conn) {
+                Ok(()) => {}
+                Err(e @ ValidationError::Invalid(_)) => {
+                    errors.extend(validation_errors(e, argument, Some(i)))
+                }
+                Err(e) => return Err(validation_errors(e, argument, Some(i))),
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    let conn = executor.context().get_connection();
+    match field_name {
+        "CreateUser" => single::<NewUser>(conn, arguments, "NewUser"),
+        "CreateUsers" => batch::<NewUser>(conn, arguments, "NewUsers"),
+        "CreatePost" => single::<NewPost>(conn, arguments, "NewPost"),
+        "CreatePosts" => batch::<NewPost>(conn, arguments, "NewPosts"),
+        "CreateComment" => single::<NewComment>(conn, arguments, "NewComment"),
+        "CreateComments" => batch::<NewComment>(conn, arguments, "NewComments"),
+        "UpdateUser" => single::<UserChangeset>(conn, arguments, "UpdateUser"),
+        "UpdatePost" => single::<PostChangeset>(conn, arguments, "UpdatePost"),
+        "UpdateComment" => single::<CommentChangeset>(conn, arguments, "UpdateComment"),
+        _ => Ok(()),
+    }
+}
+
+/// Helper type to access the selection of the mutations
+/// changing a single post and returning it
+struct PostMutation {
+    id: i32,
+    change: PostChange,
+}
+
+enum PostChange {
+    Revert { to_version: i32 },
+    Transition(PostTransition),
+}
+
+impl PostMutation {
+    fn apply(
+        &self,
         selection: Option<&'_ [Selection<'_, WundergraphScalarValue>]>,
-        executor: &Executor<PgConnection, WundergraphScalarValue>,
-        update: &PostChangeset,
+        executor: &Executor<'_, RequestContext, WundergraphScalarValue>,
     ) -> ExecutionResult<WundergraphScalarValue> {
         let ctx = executor.context();
         let conn = ctx.get_connection();
+        let identity = ctx.require_identity()?;
         conn.transaction(|| {
-            let current_version = posts::table
-                .select(diesel::dsl::max(posts::version_start))
-                .filter(posts::id.eq(update.id))
-                .get_result::<Option<i32>>(conn)?
-                .unwrap_or(0);
-
-            diesel::update(
-                posts::table.filter(
-                    posts::id
-                        .eq(update.id)
-                        .and(posts::version_start.eq(current_version)),
-                ),
-            )
-            .set(posts::version_end.eq(Some(current_version + 1)))
-            .execute(conn)?;
-
-            let inserted = diesel::insert_into(posts::table)
-                .values((
-                    posts::id.eq(update.id),
-                    posts::title.eq(&update.title),
-                    posts::content.eq(&update.content),
-                    posts::author.eq(update.author),
-                    posts::post_state.eq(update.post_state),
-                    posts::version_start.eq(current_version + 1),
-                    posts::version_end.eq(Option::<i32>::None),
-                ))
-                .returning(posts::id)
-                .get_result::<i32>(conn)?;
+            match self.change {
+                PostChange::Revert { to_version } => {
+                    revert_post(conn, self.id, identity, to_version)
+                        .map_err(|e| ApiError::from(e).into_field_error())?
+                }
+                PostChange::Transition(transition) => {
+                    transition_post(conn, self.id, identity, transition)
+                        .map_err(|e| ApiError::from(e).into_field_error())?
+                }
+            };
 
             let look_ahead = executor.look_ahead();
-
-            let query = <Post as LoadingHandler<_, PgConnection>>::build_query(&[], &look_ahead)?
-                .filter(posts::id.eq(inserted));
+            let query = <Post as LoadingHandler<_, RequestContext>>::build_query(&[], &look_ahead)?
+                .filter(posts::id.eq(self.id))
+                .filter(posts::version_end.is_null());
             let items = Post::load(&look_ahead, selection, executor, query)?;
             Ok(items.into_iter().next().unwrap_or(Value::Null))
         })
     }
 }
 
-wundergraph::mutation_object! {
-    Mutation {
-        User(insert = NewUser, update = UserChangeset, delete = true),
-        Post(insert = NewPost, update = PostChangeset, delete = true),
-        Comment(insert = NewComment, update = CommentChangeset, delete = true),
+impl GraphQLType<WundergraphScalarValue> for PostMutation {
+    type Context = RequestContext;
+    type TypeInfo = ();
+
+    fn name(info: &Self::TypeInfo) -> Option<&str> {
+        GraphqlWrapper::<Post, DbBackend, RequestContext>::name(info)
+    }
+
+    fn meta<'r>(
+        info: &Self::TypeInfo,
+        registry: &mut Registry<'r, WundergraphScalarValue>,
+    ) -> MetaType<'r, WundergraphScalarValue>
+    where
+        WundergraphScalarValue: 'r,
+    {
+        GraphqlWrapper::<Post, DbBackend, RequestContext>::meta(info, registry)
+    }
+
+    fn resolve(
+        &self,
+        _info: &Self::TypeInfo,
+        selection_set: Option<&[Selection<WundergraphScalarValue>]>,
+        executor: &Executor<Self::Context, WundergraphScalarValue>,
+    ) -> Value<WundergraphScalarValue> {
+        match self.apply(selection_set, executor) {
+            Ok(v) => v,
+            Err(e) => {
+                executor.push_error(e);
+                Value::null()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::context::DEFAULT_LOCALE;
+    use super::*;
+    use crate::db::DbPool;
+    use crate::Schema;
+    use juniper::Variables;
+    use serde_json::json;
+
+    /// Execute `query` as `caller`, returns the data and the errors
+    pub fn execute(pool: &DbPool, caller: Option<i32>, query: &str) -> serde_json::Value {
+        let metadata = RequestMetadata {
+            identity: caller.map(|user_id| Identity { user_id }),
+            request_id: String::from("test"),
+            locale: String::from(DEFAULT_LOCALE),
+            feature_flags: Arc::new(FeatureFlags::default()),
+        };
+        let ctx = RequestContext::new(pool.get().unwrap(), metadata);
+        let schema = Schema::new(Query::default(), Mutation::default());
+        let (data, errors) =
+            juniper::execute(query, None, &schema, &Variables::new(), &ctx).unwrap();
+        json!({ "data": data, "errors": errors })
+    }
+
+    #[test]
+    fn every_mutation_has_an_authorization_rule() {
+        let schema = Schema::new(Query::default(), Mutation::default());
+        let fields = match schema.schema.concrete_type_by_name("Mutation") {
+            Some(MetaType::Object(ObjectMeta { fields, .. })) => fields,
+            t => panic!("Unexpected mutation type {:?}", t.map(MetaType::name)),
+        };
+        assert!(!fields.is_empty());
+        // Introspection fields are resolved by juniper itself
+        for field in fields.iter().filter(|f| !f.name.starts_with("__")) {
+            assert!(mutation_rule(&field.name).is_some(), "{}", field.name);
+        }
+        assert!(mutation_rule("DeletePost").is_none());
     }
 }
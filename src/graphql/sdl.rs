@@ -0,0 +1,252 @@
+//! Print the schema in the graphql schema definition language
+//!
+//! Juniper 0.14 could only describe a schema by an introspection query,
+//! the printer here works on the type information of the root node instead,
+//! so no database connection is needed.
+
+use juniper::meta::{Argument, DeprecationStatus, EnumValue, Field, MetaType};
+use juniper::{GraphQLType, RootNode};
+use std::fmt::{self, Write};
+use wundergraph::scalar::WundergraphScalarValue;
+
+const BUILTIN_SCALARS: &[&str] = &["Boolean", "Float", "ID", "Int", "String"];
+
+/// The schema definition of `root`, types are ordered by name
+#[cfg(feature = "sqlite")]
+pub fn schema_language<Q, M>(root: &RootNode<'_, Q, M, WundergraphScalarValue>) -> String
+where
+    Q: GraphQLType<WundergraphScalarValue>,
+    M: GraphQLType<WundergraphScalarValue>,
+{
+    render(&roots(root), definitions(root))
+}
+
+/// The schema definition of `root` extended by the subscription root
+/// of `subscriptions`, which juniper 0.14 could only serve separately
+#[cfg(feature = "postgres")]
+pub fn schema_language_with_subscriptions<Q, M, S, SM>(
+    root: &RootNode<'_, Q, M, WundergraphScalarValue>,
+    subscriptions: &RootNode<'_, S, SM, WundergraphScalarValue>,
+) -> String
+where
+    Q: GraphQLType<WundergraphScalarValue>,
+    M: GraphQLType<WundergraphScalarValue>,
+    S: GraphQLType<WundergraphScalarValue>,
+    SM: GraphQLType<WundergraphScalarValue>,
+{
+    let mut roots = roots(root);
+    roots.subscription = subscriptions.schema.concrete_query_type().name();
+    // Types used by both schemas are the same, the mutation
+    // root of the subscription schema is only a placeholder
+    let placeholder = SM::name(&subscriptions.mutation_info);
+    let mut types = definitions(root);
+    for (name, definition) in definitions(subscriptions) {
+        if Some(name) != placeholder && types.iter().all(|(n, _)| *n != name) {
+            types.push((name, definition));
+        }
+    }
+    render(&roots, types)
+}
+
+/// The names of the root types
+struct Roots<'a> {
+    query: Option<&'a str>,
+    mutation: Option<&'a str>,
+    subscription: Option<&'a str>,
+}
+
+fn roots<'a, Q, M>(root: &'a RootNode<'_, Q, M, WundergraphScalarValue>) -> Roots<'a>
+where
+    Q: GraphQLType<WundergraphScalarValue>,
+    M: GraphQLType<WundergraphScalarValue>,
+{
+    Roots {
+        query: root.schema.concrete_query_type().name(),
+        mutation: root
+            .schema
+            .concrete_mutation_type()
+            .and_then(MetaType::name),
+        subscription: None,
+    }
+}
+
+/// The definitions of all types of `root` with their name
+fn definitions<'a, Q, M>(
+    root: &'a RootNode<'_, Q, M, WundergraphScalarValue>,
+) -> Vec<(&'a str, String)>
+where
+    Q: GraphQLType<WundergraphScalarValue>,
+    M: GraphQLType<WundergraphScalarValue>,
+{
+    root.schema
+        .concrete_type_list()
+        .into_iter()
+        .filter_map(|t| {
+            let name = t.name()?;
+            if name.starts_with("__") || BUILTIN_SCALARS.contains(&name) {
+                return None;
+            }
+            let mut definition = String::new();
+            write_type(&mut definition, t)
+                .map(|_| (name, definition))
+                .ok()
+        })
+        .filter(|(_, definition)| !definition.is_empty())
+        .collect()
+}
+
+fn render(roots: &Roots<'_>, types: Vec<(&str, String)>) -> String {
+    let mut sdl = String::new();
+    write_schema(&mut sdl, roots, types).expect("Writing to a string does not fail");
+    sdl
+}
+
+fn write_schema(
+    out: &mut String,
+    roots: &Roots<'_>,
+    mut types: Vec<(&str, String)>,
+) -> fmt::Result {
+    writeln!(out, "schema {{")?;
+    if let Some(query) = roots.query {
+        writeln!(out, "  query: {}", query)?;
+    }
+    if let Some(mutation) = roots.mutation {
+        writeln!(out, "  mutation: {}", mutation)?;
+    }
+    if let Some(subscription) = roots.subscription {
+        writeln!(out, "  subscription: {}", subscription)?;
+    }
+    writeln!(out, "}}")?;
+
+    // Wundergraph registers some types twice under the same name,
+    // so they are sorted by their definition as well
+    types.sort();
+    for (_, definition) in types {
+        writeln!(out)?;
+        out.push_str(&definition);
+    }
+    Ok(())
+}
+
+fn write_type(out: &mut String, t: &MetaType<'_, WundergraphScalarValue>) -> fmt::Result {
+    write_description(out, "", t.description())?;
+    match t {
+        MetaType::Scalar(scalar) => writeln!(out, "scalar {}", scalar.name),
+        MetaType::Object(object) => {
+            write!(out, "type {}", object.name)?;
+            if !object.interface_names.is_empty() {
+                write!(out, " implements {}", object.interface_names.join(" & "))?;
+            }
+            write_fields(out, &object.fields)
+        }
+        MetaType::Interface(interface) => {
+            write!(out, "interface {}", interface.name)?;
+            write_fields(out, &interface.fields)
+        }
+        MetaType::Union(union) => writeln!(
+            out,
+            "union {} = {}",
+            union.name,
+            union.of_type_names.join(" | ")
+        ),
+        MetaType::Enum(enum_meta) => {
+            writeln!(out, "enum {} {{", enum_meta.name)?;
+            for value in &enum_meta.values {
+                write_enum_value(out, value)?;
+            }
+            writeln!(out, "}}")
+        }
+        MetaType::InputObject(input) => {
+            writeln!(out, "input {} {{", input.name)?;
+            for field in &input.input_fields {
+                write_description(out, "  ", field.description.as_ref())?;
+                write!(out, "  ")?;
+                write_argument(out, field)?;
+                writeln!(out)?;
+            }
+            writeln!(out, "}}")
+        }
+        MetaType::List(_) | MetaType::Nullable(_) | MetaType::Placeholder(_) => Ok(()),
+    }
+}
+
+fn write_fields(out: &mut String, fields: &[Field<'_, WundergraphScalarValue>]) -> fmt::Result {
+    writeln!(out, " {{")?;
+    for field in fields.iter().filter(|f| !f.name.starts_with("__")) {
+        write_description(out, "  ", field.description.as_ref())?;
+        write!(out, "  {}", field.name)?;
+        if let Some(ref arguments) = field.arguments {
+            write!(out, "(")?;
+            for (i, argument) in arguments.iter().enumerate() {
+                if i > 0 {
+                    write!(out, ", ")?;
+                }
+                write_argument(out, argument)?;
+            }
+            write!(out, ")")?;
+        }
+        write!(out, ": {}", field.field_type)?;
+        write_deprecation(out, &field.deprecation_status)?;
+        writeln!(out)?;
+    }
+    writeln!(out, "}}")
+}
+
+fn write_argument(
+    out: &mut String,
+    argument: &Argument<'_, WundergraphScalarValue>,
+) -> fmt::Result {
+    write!(out, "{}: {}", argument.name, argument.arg_type)?;
+    if let Some(ref default) = argument.default_value {
+        write!(out, " = {}", default)?;
+    }
+    Ok(())
+}
+
+fn write_enum_value(out: &mut String, value: &EnumValue) -> fmt::Result {
+    write_description(out, "  ", value.description.as_ref())?;
+    write!(out, "  {}", value.name)?;
+    write_deprecation(out, &value.deprecation_status)?;
+    writeln!(out)
+}
+
+fn write_deprecation(out: &mut String, status: &DeprecationStatus) -> fmt::Result {
+    match status {
+        DeprecationStatus::Current => Ok(()),
+        DeprecationStatus::Deprecated(None) => write!(out, " @deprecated"),
+        DeprecationStatus::Deprecated(Some(reason)) => {
+            write!(out, " @deprecated(reason: {:?})", reason)
+        }
+    }
+}
+
+fn write_description(out: &mut String, indent: &str, description: Option<&String>) -> fmt::Result {
+    match description {
+        Some(description) if !description.contains('\n') => {
+            writeln!(out, "{}\"\"\"{}\"\"\"", indent, description)
+        }
+        Some(description) => {
+            writeln!(out, "{}\"\"\"", indent)?;
+            for line in description.lines() {
+                writeln!(out, "{}{}", indent, line)?;
+            }
+            writeln!(out, "{}\"\"\"", indent)
+        }
+        None => Ok(()),
+    }
+}
+
+#[cfg(all(test, feature = "postgres"))]
+mod tests {
+    #[test]
+    fn schema_matches_snapshot() {
+        let sdl = crate::schema_definition();
+        assert!(sdl.contains("  subscription: Subscription\n"));
+        assert!(sdl.contains("\ntype Subscription {\n"));
+        assert!(
+            sdl == include_str!("../../schema.graphql"),
+            "The graphql schema changed, update the snapshot \
+             with `cargo run -- schema -o schema.graphql`"
+        );
+    }
+}
@@ -0,0 +1,271 @@
+use super::connection::{find_field, RelayNode};
+use super::{Comment, Post, RequestContext, RequestMetadata};
+use diesel::pg::PgConnection;
+use diesel::r2d2::{ConnectionManager, Pool};
+use juniper::meta::MetaType;
+use juniper::{
+    Arguments, ExecutionResult, Executor, FieldResult, GraphQLEnum, GraphQLType, Registry,
+    Selection, Value,
+};
+use serde::Deserialize;
+use std::marker::PhantomData;
+use wundergraph::scalar::WundergraphScalarValue;
+
+/// Postgres channel change events are published on
+///
+/// The notifications are sent by triggers on the `posts` and
+/// `comments` tables, see the `change_notifications` migration
+pub const EVENT_CHANNEL: &str = "wundergraph_events";
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, GraphQLEnum)]
+pub enum ChangeKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum ChangedEntity {
+    Post,
+    Comment,
+}
+
+/// A change of a post or a comment
+///
+/// `post` is the id of the affected post, for
+/// post changes it is the same as `id`
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct ChangeEvent {
+    pub entity: ChangedEntity,
+    pub kind: ChangeKind,
+    pub id: i32,
+    pub post: i32,
+}
+
+/// Context used to execute subscriptions
+///
+/// A subscription document is executed once per change event,
+/// each root field resolves to null if the event does not match
+pub struct SubscriptionContext {
+    pub pool: Pool<ConnectionManager<PgConnection>>,
+    /// The metadata of the request opening the websocket
+    pub metadata: RequestMetadata,
+    pub event: Option<ChangeEvent>,
+}
+
+impl juniper::Context for SubscriptionContext {}
+
+/// The subscription root object
+#[derive(Debug, Default)]
+pub struct Subscription;
+
+impl GraphQLType<WundergraphScalarValue> for Subscription {
+    type Context = SubscriptionContext;
+    type TypeInfo = ();
+
+    fn name(_info: &Self::TypeInfo) -> Option<&str> {
+        Some("Subscription")
+    }
+
+    fn meta<'r>(
+        info: &Self::TypeInfo,
+        registry: &mut Registry<'r, WundergraphScalarValue>,
+    ) -> MetaType<'r, WundergraphScalarValue>
+    where
+        WundergraphScalarValue: 'r,
+    {
+        let post_id = registry.arg::<Option<i32>>("id", info);
+        let comment_post = registry.arg::<Option<i32>>("post", info);
+        let fields = &[
+            registry
+                .field::<Option<EntityChange<Post>>>("postChanged", info)
+                .argument(post_id),
+            registry
+                .field::<Option<EntityChange<Comment>>>("commentChanged", info)
+                .argument(comment_post),
+        ];
+        registry.build_object_type::<Self>(info, fields).into_meta()
+    }
+
+    fn resolve_field(
+        &self,
+        info: &Self::TypeInfo,
+        field_name: &str,
+        arguments: &Arguments<WundergraphScalarValue>,
+        executor: &Executor<Self::Context, WundergraphScalarValue>,
+    ) -> ExecutionResult<WundergraphScalarValue> {
+        let event = match executor.context().event {
+            Some(event) => event,
+            None => return Ok(Value::null()),
+        };
+        let matches = |entity, arg, value| {
+            event.entity == entity && arguments.get::<i32>(arg).map_or(true, |v| v == value)
+        };
+        match field_name {
+            "postChanged" if matches(ChangedEntity::Post, "id", event.id) => {
+                executor.resolve(info, &EntityChange::<Post>::new(event))
+            }
+            "commentChanged" if matches(ChangedEntity::Comment, "post", event.post) => {
+                executor.resolve(info, &EntityChange::<Comment>::new(event))
+            }
+            "postChanged" | "commentChanged" => Ok(Value::null()),
+            e => Err(juniper::FieldError::new("Unknown field:", Value::scalar(e))),
+        }
+    }
+}
+
+/// An entity that could be part of a change event
+pub trait ChangeNode: RelayNode {
+    const CHANGE_NAME: &'static str;
+    /// Name of the field containing the changed entity
+    const FIELD_NAME: &'static str;
+}
+
+impl ChangeNode for Post {
+    const CHANGE_NAME: &'static str = "PostChange";
+    const FIELD_NAME: &'static str = "post";
+}
+
+impl ChangeNode for Comment {
+    const CHANGE_NAME: &'static str = "CommentChange";
+    const FIELD_NAME: &'static str = "comment";
+}
+
+/// A change event of the entity `T`
+///
+/// Resolving this type loads the changed entity if requested
+struct EntityChange<T> {
+    event: ChangeEvent,
+    entity: PhantomData<T>,
+}
+
+impl<T: ChangeNode> EntityChange<T> {
+    fn new(event: ChangeEvent) -> Self {
+        Self {
+            event,
+            entity: PhantomData,
+        }
+    }
+
+    fn load(
+        &self,
+        selection: Option<&[Selection<WundergraphScalarValue>]>,
+        executor: &Executor<SubscriptionContext, WundergraphScalarValue>,
+    ) -> FieldResult<LoadedChange<T>, WundergraphScalarValue> {
+        let node = match find_field(executor, selection, T::FIELD_NAME) {
+            Some(field) if self.event.kind != ChangeKind::Deleted => {
+                let ctx = executor.context();
+                let ctx = RequestContext::new(ctx.pool.get()?, ctx.metadata.clone());
+                let executor = executor.replaced_context(&ctx);
+                // Select only the merged field, so the look ahead of the
+                // field executor finds all its sub fields
+                let parent = [field.field.clone()];
+                let executor = executor.type_sub_executor(None, Some(&parent));
+                let executor = executor.field_sub_executor(
+                    field.alias,
+                    T::FIELD_NAME,
+                    field.position,
+                    field.selection(),
+                );
+                let look_ahead = executor.look_ahead();
+                T::load_nodes(&look_ahead, field.selection(), &executor, &[self.event.id])?.pop()
+            }
+            _ => None,
+        };
+        Ok(LoadedChange {
+            event: self.event,
+            node: node.unwrap_or_else(Value::null),
+            entity: PhantomData,
+        })
+    }
+}
+
+fn change_meta<'r, T: ChangeNode, G: GraphQLType<WundergraphScalarValue, TypeInfo = ()>>(
+    registry: &mut Registry<'r, WundergraphScalarValue>,
+) -> MetaType<'r, WundergraphScalarValue> {
+    let fields = &[
+        registry.field::<ChangeKind>("kind", &()),
+        registry.field::<i32>("id", &()),
+        registry.field::<i32>("postId", &()),
+        registry.field::<Option<T::Node>>(T::FIELD_NAME, &()),
+    ];
+    registry.build_object_type::<G>(&(), fields).into_meta()
+}
+
+impl<T: ChangeNode> GraphQLType<WundergraphScalarValue> for EntityChange<T> {
+    type Context = SubscriptionContext;
+    type TypeInfo = ();
+
+    fn name(_info: &Self::TypeInfo) -> Option<&str> {
+        Some(T::CHANGE_NAME)
+    }
+
+    fn meta<'r>(
+        _info: &Self::TypeInfo,
+        registry: &mut Registry<'r, WundergraphScalarValue>,
+    ) -> MetaType<'r, WundergraphScalarValue>
+    where
+        WundergraphScalarValue: 'r,
+    {
+        change_meta::<T, Self>(registry)
+    }
+
+    fn resolve(
+        &self,
+        info: &Self::TypeInfo,
+        selection_set: Option<&[Selection<WundergraphScalarValue>]>,
+        executor: &Executor<Self::Context, WundergraphScalarValue>,
+    ) -> Value<WundergraphScalarValue> {
+        match self.load(selection_set, executor) {
+            Ok(loaded) => executor.resolve_into_value(info, &loaded),
+            Err(e) => {
+                executor.push_error(e);
+                Value::null()
+            }
+        }
+    }
+}
+
+/// A change event with the already loaded entity
+///
+/// Shares the graphql type with `EntityChange<T>`
+struct LoadedChange<T> {
+    event: ChangeEvent,
+    node: Value<WundergraphScalarValue>,
+    entity: PhantomData<T>,
+}
+
+impl<T: ChangeNode> GraphQLType<WundergraphScalarValue> for LoadedChange<T> {
+    type Context = SubscriptionContext;
+    type TypeInfo = ();
+
+    fn name(_info: &Self::TypeInfo) -> Option<&str> {
+        Some(T::CHANGE_NAME)
+    }
+
+    fn meta<'r>(
+        _info: &Self::TypeInfo,
+        registry: &mut Registry<'r, WundergraphScalarValue>,
+    ) -> MetaType<'r, WundergraphScalarValue>
+    where
+        WundergraphScalarValue: 'r,
+    {
+        change_meta::<T, Self>(registry)
+    }
+
+    fn resolve_field(
+        &self,
+        info: &Self::TypeInfo,
+        field_name: &str,
+        _arguments: &Arguments<WundergraphScalarValue>,
+        executor: &Executor<Self::Context, WundergraphScalarValue>,
+    ) -> ExecutionResult<WundergraphScalarValue> {
+        match field_name {
+            "kind" => executor.resolve_with_ctx(info, &self.event.kind),
+            "id" => Ok(Value::scalar(self.event.id)),
+            "postId" => Ok(Value::scalar(self.event.post)),
+            f if f == T::FIELD_NAME => Ok(self.node.clone()),
+            e => Err(juniper::FieldError::new("Unknown field:", Value::scalar(e))),
+        }
+    }
+}
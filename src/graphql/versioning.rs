@@ -0,0 +1,470 @@
+use crate::db::{DbBackend, Timestamp};
+use crate::graphql::{Comment, Post, User};
+use crate::model::posts::PostState;
+use crate::schema::*;
+use chrono::{DateTime, Utc};
+use diesel::associations::HasTable;
+use diesel::connection::Connection;
+use diesel::prelude::*;
+use diesel::query_builder::BoxedSelectStatement;
+use diesel::query_dsl::methods;
+use diesel::Identifiable;
+use juniper::{LookAheadArgument, LookAheadMethods, LookAheadSelection, LookAheadValue};
+use wundergraph::error::Result;
+use wundergraph::graphql_type::{GraphqlWrapper, WundergraphGraphqlMapper};
+use wundergraph::juniper_ext::FromLookAheadValue;
+use wundergraph::query_builder::selection::fields::WundergraphBelongsTo;
+use wundergraph::query_builder::selection::filter::{
+    BuildFilter, BuildFilterHelper, FilterWrapper,
+};
+use wundergraph::query_builder::selection::BoxedQuery;
+use wundergraph::query_builder::selection::LoadingHandler;
+use wundergraph::query_builder::types::{HasMany, HasOne};
+use wundergraph::scalar::WundergraphScalarValue;
+use wundergraph::WundergraphContext;
+
+/// Read the point in time given as `asOf` argument
+///
+/// The argument is a `DateTimeUtc` with both backends,
+/// SQLite stores the point in time without its time zone
+fn as_of_argument(value: &LookAheadValue<WundergraphScalarValue>) -> Option<Timestamp> {
+    let as_of: DateTime<Utc> = FromLookAheadValue::from_look_ahead(value)?;
+    #[cfg(feature = "sqlite")]
+    let as_of = as_of.naive_utc();
+    Some(as_of)
+}
+
+/// Implements `HandleUpdate` for a versioned table
+///
+/// Instead of changing the row in place the current version is closed
+/// and the values returned by the closure are inserted as new version.
+/// The columns given as `keep` are copied over from the current version,
+/// for example to not reset the point in time a row was created.
+/// The closure optionally takes the `Identity` of the caller as second argument.
+/// Database errors are reported for the `Update<Entity>` argument.
+macro_rules! versioned_update {
+    (
+        $entity: ident, $changeset: ident, $table: ident,
+        |$update: ident $(, $identity: ident)?| $values: expr
+        $(, keep $($keep: ident: $keep_ty: ty),+)? $(,)?
+    ) => {
+        impl
+            wundergraph::query_builder::mutations::HandleUpdate<
+                $entity,
+                $changeset,
+                crate::db::DbBackend,
+                crate::graphql::RequestContext,
+            > for $table::table
+        {
+            fn handle_update(
+                selection: Option<
+                    &'_ [juniper::Selection<'_, wundergraph::scalar::WundergraphScalarValue>],
+                >,
+                executor: &juniper::Executor<
+                    crate::graphql::RequestContext,
+                    wundergraph::scalar::WundergraphScalarValue,
+                >,
+                $update: &$changeset,
+            ) -> juniper::ExecutionResult<wundergraph::scalar::WundergraphScalarValue> {
+                use crate::versioning::Versioned;
+                use diesel::prelude::*;
+                use wundergraph::query_builder::selection::LoadingHandler;
+                use wundergraph::WundergraphContext;
+
+                let conn = executor.context().get_connection();
+                $(let $identity = executor.context().require_identity()?;)?
+                conn.transaction(|| {
+                    let new_version = || -> diesel::QueryResult<()> {
+                        $(
+                            let ($($keep,)+) = $table::table
+                                .select(($($table::$keep,)+))
+                                .filter($table::id.eq($update.id))
+                                .filter($table::version_end.is_null())
+                                .first::<($($keep_ty,)+)>(conn)?;
+                        )?
+                        let next_version = $table::table.close_current_version(conn, $update.id)?;
+
+                        diesel::insert_into($table::table)
+                            .values((
+                                $values,
+                                $table::id.eq($update.id),
+                                $table::version_start.eq(next_version),
+                                $($($table::$keep.eq($keep),)+)?
+                            ))
+                            .execute(conn)?;
+                        Ok(())
+                    };
+                    new_version().map_err(|e| {
+                        crate::graphql::errors::database_error(
+                            e,
+                            concat!("Update", stringify!($entity)),
+                        )
+                    })?;
+
+                    let look_ahead = executor.look_ahead();
+                    let query =
+                        <$entity as LoadingHandler<_, crate::graphql::RequestContext>>::build_query(
+                            &[],
+                            &look_ahead,
+                        )?
+                        .filter($table::id.eq($update.id))
+                        .filter($table::version_end.is_null());
+                    let items = <$entity as LoadingHandler<_, crate::graphql::RequestContext>>::load(
+                        &look_ahead,
+                        selection,
+                        executor,
+                        query,
+                    )?;
+                    Ok(items.into_iter().next().unwrap_or(juniper::Value::Null))
+                })
+            }
+        }
+    };
+}
+
+/// Declares a graphql entity loading the current versions of the rows of
+/// a versioned table, used instead of `#[derive(WundergraphEntity)]`
+///
+/// Older versions share the id of the current version, so loading all rows
+/// would return each row once for every version. The filter is applied in
+/// `build_query`, which also loads the `HasOne` and `HasMany` relations.
+/// Fields named differently than their column give the column after `as`,
+/// `HasMany` relations are listed separately as they have no column. Field
+/// types are matched token by token so the derives still see `HasOne`.
+macro_rules! current_entity {
+    (
+        $(#[$meta: meta])*
+        $entity: ident ($table: ident, $table_name: tt) {
+            $(
+                $(#[$field_meta: meta])*
+                $field: ident: $field_ty: ident $(<$($field_arg: ty),+>)? $(as $column: ident)?,
+            )*
+        }
+        relations {
+            $($relation: ident: $relation_ty: ident<$($relation_arg: ty),+>,)*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(
+            Clone,
+            Debug,
+            Identifiable,
+            wundergraph::query_builder::selection::filter::BuildFilterHelper,
+            wundergraph::query_builder::selection::fields::WundergraphBelongsTo,
+        )]
+        #[table_name = $table_name]
+        pub struct $entity {
+            $($(#[$field_meta])* $field: $field_ty $(<$($field_arg),+>)?,)*
+            $($relation: $relation_ty<$($relation_arg),+>,)*
+        }
+
+        impl<Ctx> wundergraph::query_builder::selection::LoadingHandler<crate::db::DbBackend, Ctx>
+            for $entity
+        where
+            Ctx: wundergraph::WundergraphContext + 'static,
+            Ctx::Connection: diesel::Connection<Backend = crate::db::DbBackend>,
+        {
+            type Columns = ($(current_entity!(@column $table $field $($column)?),)*);
+            type FieldList = (
+                $($field_ty $(<$($field_arg),+>)?,)*
+                $($relation_ty<$($relation_arg),+>,)*
+            );
+            type PrimaryKeyIndex = wundergraph::helper::TupleIndex0;
+            type Filter = wundergraph::query_builder::selection::filter::FilterWrapper<
+                Self,
+                crate::db::DbBackend,
+                Ctx,
+            >;
+            const FIELD_NAMES: &'static [&'static str] =
+                &[$(stringify!($field),)* $(stringify!($relation),)*];
+            const TYPE_NAME: &'static str = stringify!($entity);
+
+            fn build_query<'a>(
+                _global_args: &[juniper::LookAheadArgument<wundergraph::scalar::WundergraphScalarValue>],
+                select: &juniper::LookAheadSelection<'_, wundergraph::scalar::WundergraphScalarValue>,
+            ) -> wundergraph::error::Result<
+                wundergraph::query_builder::selection::BoxedQuery<'a, Self, crate::db::DbBackend, Ctx>,
+            >
+            where
+                Self::Table: diesel::query_dsl::methods::BoxedDsl<
+                        'a,
+                        crate::db::DbBackend,
+                        Output = diesel::query_builder::BoxedSelectStatement<
+                            'a,
+                            diesel::dsl::SqlTypeOf<<Self::Table as diesel::Table>::AllColumns>,
+                            Self::Table,
+                            crate::db::DbBackend,
+                        >,
+                    > + 'static,
+                <Self::Filter as wundergraph::query_builder::selection::filter::BuildFilter<
+                    crate::db::DbBackend,
+                >>::Ret: diesel::AppearsOnTable<Self::Table>,
+            {
+                use diesel::prelude::*;
+                use wundergraph::helper::IsPrimaryKeyIndex;
+                use wundergraph::query_builder::selection::fields::WundergraphFieldList;
+                use wundergraph::query_builder::selection::select::BuildSelect;
+                use wundergraph::query_builder::selection::LoadingHandler;
+
+                // Unlike `get_select` the id is always selected,
+                // `relay_node!` loads the ids of a page by this query
+                let columns = <Self::Columns as BuildSelect<Self::Table, crate::db::DbBackend, _>>::build_select(
+                    select,
+                    |local_index| {
+                        <Self::FieldList as WundergraphFieldList<
+                            crate::db::DbBackend,
+                            Self::PrimaryKeyIndex,
+                            Self::Table,
+                            Ctx,
+                        >>::map_table_field(local_index, |global| {
+                            <Self as LoadingHandler<crate::db::DbBackend, Ctx>>::FIELD_NAMES[global]
+                        })
+                        .expect("Field is there")
+                    },
+                    <Self::PrimaryKeyIndex as IsPrimaryKeyIndex>::is_index,
+                    true,
+                )?;
+                let mut query = $table::table
+                    .into_boxed()
+                    .filter($table::version_end.is_null())
+                    .select(columns);
+
+                query = <Self as LoadingHandler<crate::db::DbBackend, Ctx>>::apply_filter(query, select)?;
+                query = <Self as LoadingHandler<crate::db::DbBackend, Ctx>>::apply_limit(query, select)?;
+                query = <Self as LoadingHandler<crate::db::DbBackend, Ctx>>::apply_offset(query, select)?;
+                query = <Self as LoadingHandler<crate::db::DbBackend, Ctx>>::apply_order(query, select)?;
+
+                Ok(query)
+            }
+        }
+
+        impl<Ctx> wundergraph::graphql_type::WundergraphGraphqlMapper<crate::db::DbBackend, Ctx>
+            for $entity
+        where
+            Ctx: wundergraph::WundergraphContext + 'static,
+            Ctx::Connection: diesel::Connection<Backend = crate::db::DbBackend>,
+        {
+            type GraphQLType = wundergraph::graphql_type::GraphqlWrapper<$entity, crate::db::DbBackend, Ctx>;
+
+            // As generated by the derive, fields referring to the entity could be filtered
+            fn register_arguments<'r>(
+                registry: &mut juniper::Registry<'r, wundergraph::scalar::WundergraphScalarValue>,
+                field: juniper::meta::Field<'r, wundergraph::scalar::WundergraphScalarValue>,
+            ) -> juniper::meta::Field<'r, wundergraph::scalar::WundergraphScalarValue> {
+                let filter = registry.arg_with_default::<Option<
+                    wundergraph::query_builder::selection::filter::Filter<
+                        <Self as wundergraph::query_builder::selection::LoadingHandler<
+                            crate::db::DbBackend,
+                            Ctx,
+                        >>::Filter,
+                        $table::table,
+                    >,
+                >>("filter", &None, &Default::default());
+                field.argument(filter)
+            }
+        }
+    };
+    (@column $table: ident $field: ident) => {
+        $table::$field
+    };
+    (@column $table: ident $field: ident $column: ident) => {
+        $table::$column
+    };
+}
+
+/// Declares a graphql entity loading the rows of a versioned table
+/// at a given version or point in time
+///
+/// `$fn_name` is the sql function returning the rows, it accepts the
+/// `version` and `as_of` arguments (see the `versioned_comments_and_users`
+/// migration). Both are read from the `version` and `asOf` arguments of
+/// the query field. Plain fields need their sql type, `HasMany` relations
+/// are listed separately as they have no column. Attributes of plain fields
+/// are forwarded to the columns of the `table_function`.
+///
+/// SQLite has no functions returning a table, there the rows are
+/// selected by the query given as `sqlite_subquery` instead.
+macro_rules! versioned_entity {
+    (
+        $(#[$meta: meta])*
+        $entity: ident ($fn_name: ident, $table_name: tt) as $type_name: literal {
+            $($(#[$column_meta: meta])* $field: ident: $field_ty: ty = $sql_ty: ty,)*
+        }
+        relations {
+            $($relation: ident: $relation_ty: ty,)*
+        }
+        sqlite_subquery = $sqlite_subquery: literal;
+    ) => {
+        #[cfg_attr(
+            feature = "postgres",
+            crate::diesel_ext::table_function(
+                version: Nullable<Integer>,
+                as_of: Nullable<crate::db::Timestamptz>,
+            )
+        )]
+        #[cfg_attr(
+            feature = "sqlite",
+            crate::diesel_ext::table_function(
+                version: Nullable<Integer>,
+                as_of: Nullable<crate::db::Timestamptz>,
+                subquery = $sqlite_subquery,
+            )
+        )]
+        struct $fn_name {
+            $($(#[$column_meta])* $field: $sql_ty,)*
+        }
+
+        $(#[$meta])*
+        #[derive(Clone, Debug, BuildFilterHelper, WundergraphBelongsTo)]
+        #[table_name = $table_name]
+        pub struct $entity {
+            $($field: $field_ty,)*
+            $($relation: $relation_ty,)*
+        }
+
+        impl HasTable for $entity {
+            type Table = $fn_name::$fn_name;
+
+            fn table() -> Self::Table {
+                unimplemented!()
+            }
+        }
+
+        impl<'a> Identifiable for &'a $entity {
+            type Id = &'a i32;
+
+            fn id(self) -> Self::Id {
+                &self.id
+            }
+        }
+
+        impl<Ctx> LoadingHandler<DbBackend, Ctx> for $entity
+        where
+            Ctx: WundergraphContext + 'static,
+            Ctx::Connection: Connection<Backend = DbBackend>,
+        {
+            type Columns = ($($fn_name::$field,)*);
+            type FieldList = ($($field_ty,)* $($relation_ty,)*);
+            type PrimaryKeyIndex = wundergraph::helper::TupleIndex0;
+            type Filter = FilterWrapper<Self, DbBackend, Ctx>;
+            const FIELD_NAMES: &'static [&'static str] =
+                &[$(stringify!($field),)* $(stringify!($relation),)*];
+            const TYPE_NAME: &'static str = $type_name;
+
+            fn build_query<'a>(
+                _global_args: &[LookAheadArgument<WundergraphScalarValue>],
+                select: &LookAheadSelection<'_, WundergraphScalarValue>,
+            ) -> Result<BoxedQuery<'a, Self, DbBackend, Ctx>>
+            where
+                Self::Table: methods::BoxedDsl<
+                        'a,
+                        DbBackend,
+                        Output = BoxedSelectStatement<
+                            'a,
+                            diesel::dsl::SqlTypeOf<<Self::Table as Table>::AllColumns>,
+                            Self::Table,
+                            DbBackend,
+                        >,
+                    > + 'static,
+                <Self::Filter as BuildFilter<DbBackend>>::Ret: AppearsOnTable<Self::Table>,
+            {
+                let version: Option<i32> = select
+                    .argument("version")
+                    .and_then(|v| FromLookAheadValue::from_look_ahead(v.value()));
+                let as_of = select
+                    .argument("asOf")
+                    .and_then(|v| as_of_argument(v.value()));
+                let mut query = $fn_name(version, as_of)
+                    .into_boxed()
+                    .select(<Self as LoadingHandler<DbBackend, Ctx>>::get_select(select)?);
+
+                query = <Self as LoadingHandler<DbBackend, Ctx>>::apply_filter(query, select)?;
+                query = <Self as LoadingHandler<DbBackend, Ctx>>::apply_limit(query, select)?;
+                query = <Self as LoadingHandler<DbBackend, Ctx>>::apply_offset(query, select)?;
+                query = <Self as LoadingHandler<DbBackend, Ctx>>::apply_order(query, select)?;
+
+                Ok(query)
+            }
+        }
+
+        impl<Ctx> WundergraphGraphqlMapper<DbBackend, Ctx> for $entity
+        where
+            Ctx: WundergraphContext + 'static,
+            Ctx::Connection: Connection<Backend = DbBackend>,
+        {
+            type GraphQLType = GraphqlWrapper<$entity, DbBackend, Ctx>;
+        }
+    };
+}
+
+versioned_entity! {
+    PostAtVersion(posts_at_version, "posts_at_version") as "PostsAtVersion" {
+        #[primary_key]
+        id: i32 = Int4,
+        title: String = Text,
+        content: Option<String> = Nullable<Text>,
+        published_at: Option<Timestamp> = Nullable<crate::db::Timestamptz>,
+        author: HasOne<i32, User> = Int4,
+        post_state: PostState = crate::model::posts::Post_state,
+    }
+    relations {
+        comments: HasMany<Comment, comments::post>,
+    }
+    sqlite_subquery = "SELECT posts.* FROM posts, args WHERE version_is_valid(\
+        posts.version_start, posts.version_end, posts.version_start_at, \
+        posts.version_end_at, args.version, args.as_of)";
+}
+
+versioned_entity! {
+    CommentAtVersion(comments_at_version, "comments_at_version") as "CommentsAtVersion" {
+        #[primary_key]
+        id: i32 = Int4,
+        comment: Option<String> = Nullable<Text>,
+        published_at: Timestamp = crate::db::Timestamptz,
+        author: HasOne<i32, User> = Int4,
+        post: HasOne<i32, Post> = Int4,
+    }
+    relations {}
+    sqlite_subquery = "SELECT comments.* FROM comments, args WHERE version_is_valid(\
+        comments.version_start, comments.version_end, comments.version_start_at, \
+        comments.version_end_at, args.version, args.as_of)";
+}
+
+versioned_entity! {
+    UserAtVersion(users_at_version, "users_at_version") as "UsersAtVersion" {
+        #[primary_key]
+        id: i32 = Int4,
+        name: String = Text,
+        joined_at: Timestamp = crate::db::Timestamptz,
+    }
+    relations {}
+    sqlite_subquery = "SELECT users.* FROM users, args WHERE version_is_valid(\
+        users.version_start, users.version_end, users.version_start_at, \
+        users.version_end_at, args.version, args.as_of)";
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::test_pool;
+    use crate::graphql::tests::execute;
+    use crate::model::posts::tests::post_with_two_versions;
+    use serde_json::json;
+
+    // The boundaries of the versions are tested with the REST api
+    #[test]
+    fn as_of_is_passed_to_post_at_versions() {
+        let pool = match test_pool() {
+            Some(pool) => pool,
+            None => return,
+        };
+        let id = post_with_two_versions(&pool.get().unwrap());
+        let query = format!(
+            "{{ PostAtVersions(asOf: \"2019-12-01T10:30:00Z\", filter: {{ id: {{ eq: {} }} }}) {{ title }} }}",
+            id
+        );
+        assert_eq!(
+            execute(&pool, None, &query),
+            json!({ "data": { "PostAtVersions": [{ "title": "A title" }] }, "errors": [] })
+        );
+    }
+}
@@ -2,55 +2,302 @@
 extern crate diesel;
 
 use actix_web::{middleware, web, App, HttpResponse, HttpServer};
-use diesel::pg::PgConnection;
-use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::Connection;
 use juniper::graphiql::graphiql_source;
-use juniper::http::GraphQLRequest;
-use serde::{Deserialize, Serialize};
+use juniper::http::{GraphQLRequest, GraphQLResponse};
+use juniper::{InputValue, IntoFieldError};
+use serde::Deserialize;
+use std::fs;
+use std::path::PathBuf;
 use std::sync::Arc;
 use structopt::StructOpt;
 use wundergraph::scalar::WundergraphScalarValue;
+use wundergraph::WundergraphContext;
 
+mod auth;
+mod authorization;
+mod db;
+mod diesel_ext;
+mod error;
 mod graphql;
 mod model;
+mod openapi;
 mod pagination;
+mod persisted_queries;
 #[allow(unused_imports)]
 mod schema;
-#[macro_use]
-mod diesel_ext;
+#[cfg(feature = "postgres")]
+mod subscriptions;
+mod validation;
+mod versioning;
+#[allow(unused_imports)]
+mod views;
 
-use self::graphql::{Mutation, Query};
+use self::auth::Authentication;
+use self::db::{DbConnection, DbPool};
+#[cfg(feature = "postgres")]
+use self::graphql::subscription::{Subscription, SubscriptionContext};
+use self::graphql::{
+    localize_errors, FeatureFlags, Measure, Measurement, Mutation, Query, QueryLimits,
+    RequestContext, RequestMetadata, LOCALIZED_ERRORS, REQUEST_ID_HEADER,
+};
+use self::openapi::OpenApi;
+use self::persisted_queries::{PersistedQueries, PersistedQuery};
+#[cfg(feature = "postgres")]
+use self::subscriptions::EventBroker;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "rustfest")]
-struct Opt {
-    #[structopt(short = "u", long = "db-url")]
+enum Opt {
+    /// Run the server
+    Serve(ServeOpt),
+    /// Run all pending migrations
+    Migrate(DbOpt),
+    /// Revert the latest migrations
+    Rollback {
+        #[structopt(flatten)]
+        db: DbOpt,
+        /// The number of migrations to revert
+        #[structopt(long = "steps", default_value = "1")]
+        steps: usize,
+    },
+    /// List all migrations and whether they were run
+    MigrationStatus(DbOpt),
+    /// Insert example data into an empty database
+    Seed(DbOpt),
+    /// Print the graphql schema in the schema definition language
+    Schema {
+        /// Write the schema to this file instead of stdout
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+struct DbOpt {
+    /// The postgres database url, or the path
+    /// of the database file with SQLite
+    #[structopt(short = "u", long = "db-url", env = "DATABASE_URL")]
     database_url: String,
+}
+
+impl DbOpt {
+    fn connect(&self) -> PooledConnection<ConnectionManager<DbConnection>> {
+        db::build_pool(&self.database_url)
+            .get()
+            .expect("Failed to get db connection")
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct ServeOpt {
+    #[structopt(flatten)]
+    db: DbOpt,
+    /// Do not run pending migrations on startup
+    #[structopt(long = "skip-migrations")]
+    skip_migrations: bool,
     #[structopt(short = "s", long = "socket", default_value = "127.0.0.1:8000")]
     socket: String,
+    /// The key bearer tokens are signed with (HS256)
+    #[structopt(long = "jwt-secret", env = "JWT_SECRET", hide_env_values = true)]
+    jwt_secret: String,
+    /// Enable an experimental feature, could be given multiple times
+    #[structopt(long = "feature")]
+    features: Vec<String>,
+    /// Directory containing the `.graphql` files of persisted queries
+    #[structopt(long = "persisted-queries", parse(from_os_str))]
+    persisted_queries: Option<PathBuf>,
+    /// Only execute the persisted queries
+    #[structopt(long = "persisted-queries-only")]
+    persisted_queries_only: bool,
+    /// The maximal number of queries registered by clients kept
+    #[structopt(long = "persisted-queries-capacity", default_value = "1000")]
+    persisted_queries_capacity: usize,
+    /// The maximal number of nested fields of a graphql query
+    #[structopt(long = "max-query-depth", default_value = "10")]
+    max_query_depth: usize,
+    /// The maximal number of fields selected by a graphql query
+    #[structopt(long = "max-query-fields", default_value = "200")]
+    max_query_fields: usize,
+    /// The maximal complexity of a graphql query, see `graphql::limits`
+    #[structopt(long = "max-query-complexity", default_value = "5000")]
+    max_query_complexity: usize,
 }
 
-pub type Schema =
-    juniper::RootNode<'static, Query<PgConnection>, Mutation<PgConnection>, WundergraphScalarValue>;
+pub type Schema = juniper::RootNode<
+    'static,
+    Query<RequestContext>,
+    Mutation<RequestContext>,
+    WundergraphScalarValue,
+>;
+
+/// Schema used to check the size of a query before it is executed
+pub type MeasureSchema = juniper::RootNode<
+    'static,
+    Measure<Query<RequestContext>>,
+    Measure<Mutation<RequestContext>>,
+    WundergraphScalarValue,
+>;
+
+/// Juniper 0.14 has no support for a subscription root, so
+/// subscriptions are served by a separate schema
+///
+/// Subscriptions rely on postgres notifications,
+/// they are not available with SQLite
+#[cfg(feature = "postgres")]
+pub type SubscriptionSchema = juniper::RootNode<
+    'static,
+    Subscription,
+    juniper::EmptyMutation<SubscriptionContext>,
+    WundergraphScalarValue,
+>;
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct GraphQLData(GraphQLRequest<WundergraphScalarValue>);
+/// Measures subscription documents like `MeasureSchema` does for queries
+#[cfg(feature = "postgres")]
+pub type MeasureSubscriptionSchema = juniper::RootNode<
+    'static,
+    Measure<Subscription>,
+    Measure<juniper::EmptyMutation<SubscriptionContext>>,
+    WundergraphScalarValue,
+>;
+
+/// A graphql request, the query could be replaced
+/// by the hash of a persisted query
+#[derive(Deserialize, Debug)]
+pub struct GraphQLData {
+    query: Option<String>,
+    #[serde(rename = "operationName")]
+    operation_name: Option<String>,
+    variables: Option<InputValue<WundergraphScalarValue>>,
+    #[serde(default)]
+    extensions: GraphQLExtensions,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct GraphQLExtensions {
+    #[serde(rename = "persistedQuery")]
+    persisted_query: Option<PersistedQuery>,
+}
 
 #[derive(Clone)]
 struct AppState {
-    pool: Pool<ConnectionManager<PgConnection>>,
+    pool: DbPool,
     schema: Arc<Schema>,
+    measure_schema: Arc<MeasureSchema>,
+    query_limits: QueryLimits,
+    persisted_queries: Arc<PersistedQueries>,
+    feature_flags: Arc<FeatureFlags>,
+    openapi: Arc<serde_json::Value>,
+    #[cfg(feature = "postgres")]
+    subscription_schema: Arc<SubscriptionSchema>,
+    #[cfg(feature = "postgres")]
+    measure_subscription_schema: Arc<MeasureSubscriptionSchema>,
+    #[cfg(feature = "postgres")]
+    broker: Arc<EventBroker>,
+    #[cfg(feature = "postgres")]
+    authentication: Authentication,
+}
+
+/// The body of a graphql request, clients could send
+/// several operations at once as array
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum GraphQLBatch {
+    Single(GraphQLData),
+    Batch(Vec<GraphQLData>),
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GraphQLOptions {
+    /// Run all operations of a batch in one transaction,
+    /// it is rolled back if any of them fails
+    #[serde(default)]
+    transaction: bool,
 }
 
 fn graphql(
-    web::Json(GraphQLData(data)): web::Json<GraphQLData>,
+    web::Json(batch): web::Json<GraphQLBatch>,
+    web::Query(options): web::Query<GraphQLOptions>,
     st: web::Data<AppState>,
+    metadata: RequestMetadata,
 ) -> Result<HttpResponse, failure::Error> {
-    let ctx = st.get_ref().pool.get()?;
-    let res = data.execute(&st.get_ref().schema, &ctx);
+    let st = st.get_ref();
+    let ctx = RequestContext::new(st.pool.get()?, metadata);
+    let body = match batch {
+        GraphQLBatch::Single(data) => serde_json::to_string(&execute(st, &ctx, data)?)?,
+        GraphQLBatch::Batch(batch) if options.transaction => {
+            let mut responses = Vec::with_capacity(batch.len());
+            let res = ctx.get_connection().transaction(|| {
+                for data in batch {
+                    responses.push(execute(st, &ctx, data)?);
+                }
+                if responses.iter().any(|r| r.get("errors").is_some()) {
+                    Err(failure::Error::from(
+                        diesel::result::Error::RollbackTransaction,
+                    ))
+                } else {
+                    Ok(())
+                }
+            });
+            // The errors of a failed operation are part of its response
+            match res {
+                Err(e) if !is_rollback(&e) => return Err(e),
+                _ => serde_json::to_string(&responses)?,
+            }
+        }
+        GraphQLBatch::Batch(batch) => serde_json::to_string(
+            &batch
+                .into_iter()
+                .map(|data| execute(st, &ctx, data))
+                .collect::<Result<Vec<_>, _>>()?,
+        )?,
+    };
     Ok(HttpResponse::Ok()
         .content_type("application/json")
-        .body(serde_json::to_string(&res)?))
+        .header(REQUEST_ID_HEADER, ctx.request_id())
+        .body(body))
+}
+
+fn is_rollback(e: &failure::Error) -> bool {
+    matches!(
+        e.downcast_ref(),
+        Some(diesel::result::Error::RollbackTransaction)
+    )
+}
+
+/// Execute a single graphql operation
+fn execute(
+    st: &AppState,
+    ctx: &RequestContext,
+    data: GraphQLData,
+) -> Result<serde_json::Value, failure::Error> {
+    let GraphQLData {
+        query,
+        operation_name,
+        variables,
+        extensions,
+    } = data;
+    let request = st
+        .persisted_queries
+        .resolve(query, extensions.persisted_query.as_ref())
+        .map(|query| GraphQLRequest::new(query, operation_name, variables));
+    let res = match request {
+        Ok(ref request) => {
+            let measurement = Measurement::default();
+            request.execute(&st.measure_schema, &measurement);
+            match st.query_limits.check(measurement.cost()) {
+                Ok(()) => request.execute(&st.schema, ctx),
+                Err(e) => GraphQLResponse::error(e),
+            }
+        }
+        Err(e) => GraphQLResponse::error(e.into_field_error()),
+    };
+    let mut res = serde_json::to_value(&res)?;
+    if ctx.feature_enabled(LOCALIZED_ERRORS) {
+        localize_errors(&mut res, ctx.locale());
+    }
+    Ok(res)
 }
 
 fn graphiql() -> HttpResponse {
@@ -60,34 +307,165 @@ fn graphiql() -> HttpResponse {
         .body(html)
 }
 
+/// The graphql schema in the schema definition language,
+/// including the subscriptions if they are available
+fn schema_definition() -> String {
+    let schema = Schema::new(Query::default(), Mutation::default());
+    #[cfg(feature = "postgres")]
+    let sdl = graphql::schema_language_with_subscriptions(
+        &schema,
+        &SubscriptionSchema::new(Subscription, juniper::EmptyMutation::new()),
+    );
+    #[cfg(feature = "sqlite")]
+    let sdl = graphql::schema_language(&schema);
+    sdl
+}
+
+/// The OpenAPI document of all REST routes
+fn openapi_document() -> serde_json::Value {
+    let mut api = OpenApi::default();
+    model::posts::openapi(&mut api);
+    model::users::openapi(&mut api);
+    model::comments::openapi(&mut api);
+    api.document()
+}
+
+fn openapi(st: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(&*st.openapi)
+}
+
+fn swagger() -> HttpResponse {
+    let html = r##"<!DOCTYPE html>
+<html>
+<head>
+  <title>REST api</title>
+  <meta charset="utf-8">
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@3/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@3/swagger-ui-bundle.js"></script>
+  <script>
+    SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##;
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(html)
+}
+
 fn main() {
-    let opt = Opt::from_args();
-    ::std::env::set_var("RUST_LOG", "actix_web=info");
+    match Opt::from_args() {
+        Opt::Serve(opt) => serve(opt),
+        Opt::Migrate(db) => {
+            db::run_pending_migrations(&db.connect()).expect("Failed to run migrations")
+        }
+        Opt::Rollback { db, steps } => {
+            for version in
+                db::revert_migrations(&db.connect(), steps).expect("Failed to revert migrations")
+            {
+                println!("Reverted migration {}", version);
+            }
+        }
+        Opt::MigrationStatus(db) => {
+            let status = db::migration_status(&db.connect()).expect("Failed to load migrations");
+            for (name, run) in status {
+                println!("[{}] {}", if run { "X" } else { " " }, name);
+            }
+        }
+        Opt::Seed(db) => {
+            if db::seed(&db.connect()).expect("Failed to insert the seed data") {
+                println!("Inserted the seed data");
+            } else {
+                println!("The database is not empty, skipped the seed data");
+            }
+        }
+        Opt::Schema { output } => {
+            let sdl = schema_definition();
+            match output {
+                Some(path) => fs::write(path, sdl).expect("Failed to write the schema"),
+                None => print!("{}", sdl),
+            }
+        }
+    }
+}
+
+fn serve(opt: ServeOpt) {
+    ::std::env::set_var(
+        "RUST_LOG",
+        "actix_web=info,rustfest_wundergraph_workshop=info",
+    );
     env_logger::init();
-    let manager = ConnectionManager::<PgConnection>::new(opt.database_url);
-    let pool = Pool::builder().build(manager).expect("Failed to init pool");
+    let pool = db::build_pool(&opt.db.database_url);
 
-    diesel_migrations::run_pending_migrations(&pool.get().expect("Failed to get db connection"))
-        .expect("Failed to run migrations");
+    if !opt.skip_migrations {
+        db::run_pending_migrations(&pool.get().expect("Failed to get db connection"))
+            .expect("Failed to run migrations");
+    }
 
-    let query = Query::<PgConnection>::default();
-    let mutation = Mutation::<PgConnection>::default();
+    let query = Query::<RequestContext>::default();
+    let mutation = Mutation::<RequestContext>::default();
     let schema = Arc::new(Schema::new(query, mutation));
-    let data = AppState { pool, schema };
+    let measure_schema = Arc::new(MeasureSchema::new(Measure::default(), Measure::default()));
+    let authentication = Authentication::new(&opt.jwt_secret);
+    let data = AppState {
+        pool,
+        schema,
+        measure_schema,
+        query_limits: QueryLimits {
+            max_depth: opt.max_query_depth,
+            max_fields: opt.max_query_fields,
+            max_complexity: opt.max_query_complexity,
+        },
+        persisted_queries: Arc::new(
+            PersistedQueries::load(
+                opt.persisted_queries.as_deref(),
+                opt.persisted_queries_only,
+                opt.persisted_queries_capacity,
+            )
+            .expect("Failed to load persisted queries"),
+        ),
+        feature_flags: Arc::new(FeatureFlags::new(opt.features)),
+        openapi: Arc::new(openapi_document()),
+        #[cfg(feature = "postgres")]
+        subscription_schema: Arc::new(SubscriptionSchema::new(
+            Subscription,
+            juniper::EmptyMutation::new(),
+        )),
+        #[cfg(feature = "postgres")]
+        measure_subscription_schema: Arc::new(MeasureSubscriptionSchema::new(
+            Measure::default(),
+            Measure::default(),
+        )),
+        #[cfg(feature = "postgres")]
+        broker: EventBroker::start(opt.db.database_url.clone()),
+        #[cfg(feature = "postgres")]
+        authentication: authentication.clone(),
+    };
 
     let url = opt.socket;
 
     println!("Started http server: http://{}", url);
 
     HttpServer::new(move || {
-        App::new()
+        let app = App::new()
             .configure(model::posts::config)
             .configure(model::users::config)
-            .configure(model::comments::config)
-            .route("/graphiql", web::get().to(graphiql))
+            .configure(model::comments::config);
+        #[cfg(feature = "postgres")]
+        let app = app.configure(subscriptions::config);
+        app.route("/graphiql", web::get().to(graphiql))
             .route("/graphql", web::get().to(graphql))
             .route("/graphql", web::post().to(graphql))
+            .route("/openapi.json", web::get().to(openapi))
+            .route("/swagger", web::get().to(swagger))
             .data(data.clone())
+            .data(web::JsonConfig::default().error_handler(error::json_error))
+            .data(web::PathConfig::default().error_handler(error::path_error))
+            .data(web::QueryConfig::default().error_handler(error::query_error))
+            .wrap(authentication.clone())
             .wrap(middleware::Logger::default())
             .default_service(web::route().to(|| {
                 HttpResponse::Found()
@@ -100,3 +478,332 @@ fn main() {
     .run()
     .unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Identity;
+    use crate::model::users::tests::insert_user;
+    use crate::model::users::Role;
+    use actix_web::http::{Method, StatusCode};
+    use actix_web::test::{self, TestRequest};
+    use actix_web::HttpMessage;
+    use diesel::r2d2::Pool;
+    use serde_json::{json, Value};
+    use std::collections::BTreeSet;
+    use std::time::Duration;
+
+    fn test_state(pool: DbPool) -> AppState {
+        AppState {
+            pool,
+            schema: Arc::new(Schema::new(Query::default(), Mutation::default())),
+            measure_schema: Arc::new(MeasureSchema::new(Measure::default(), Measure::default())),
+            query_limits: QueryLimits {
+                max_depth: 10,
+                max_fields: 200,
+                max_complexity: 5000,
+            },
+            persisted_queries: Arc::new(PersistedQueries::load(None, false, 10).unwrap()),
+            feature_flags: Arc::new(FeatureFlags::default()),
+            openapi: Arc::new(openapi_document()),
+            #[cfg(feature = "postgres")]
+            subscription_schema: Arc::new(SubscriptionSchema::new(
+                Subscription,
+                juniper::EmptyMutation::new(),
+            )),
+            #[cfg(feature = "postgres")]
+            measure_subscription_schema: Arc::new(MeasureSubscriptionSchema::new(
+                Measure::default(),
+                Measure::default(),
+            )),
+            #[cfg(feature = "postgres")]
+            broker: Arc::new(EventBroker::default()),
+            #[cfg(feature = "postgres")]
+            authentication: Authentication::new("secret"),
+        }
+    }
+
+    /// Post the graphql requests returned by `requests` for the id of a
+    /// new user one after another to `uri` as this user, `None` without
+    /// test database
+    fn post_as_new_user(uri: &str, requests: impl FnOnce(i32) -> Vec<Value>) -> Option<Vec<Value>> {
+        let pool = db::test_pool()?;
+        let user = insert_user(&pool.get().unwrap(), &[Role::Author]);
+        let mut app = test::init_service(
+            App::new()
+                .data(test_state(pool))
+                .route("/graphql", web::post().to(graphql)),
+        );
+        let responses = requests(user)
+            .iter()
+            .map(|body| {
+                let req = TestRequest::post().uri(uri).set_json(body).to_request();
+                req.extensions_mut().insert(Identity { user_id: user });
+                test::read_response_json(&mut app, req)
+            })
+            .collect();
+        Some(responses)
+    }
+
+    fn create_post(title: &str, author: i32) -> Value {
+        json!({
+            "query": format!(
+                "mutation {{ CreatePost(NewPost: {{ title: \"{}\", author: {} }}) {{ title }} }}",
+                title, author
+            ),
+        })
+    }
+
+    fn find_post(title: &str) -> Value {
+        json!({
+            "query": format!(
+                "{{ Posts(filter: {{ title: {{ eq: \"{}\" }} }}) {{ title }} }}",
+                title
+            ),
+        })
+    }
+
+    #[test]
+    fn batch_responses_are_in_request_order() {
+        let titles = ["First", "Second", "Third"];
+        let responses = match post_as_new_user("/graphql", |user| {
+            vec![titles
+                .iter()
+                .map(|title| create_post(title, user))
+                .collect()]
+        }) {
+            Some(responses) => responses,
+            None => return,
+        };
+        let created: Vec<&Value> = responses[0]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| &r["data"]["CreatePost"]["title"])
+            .collect();
+        assert_eq!(created, titles);
+    }
+
+    #[test]
+    fn failing_batches_in_a_transaction_are_rolled_back() {
+        let title = |batch| format!("Batch {} {}", batch, uuid::Uuid::new_v4());
+        let (kept, rolled_back) = (title("kept"), title("rolled back"));
+        let batch = |title: &str, user| {
+            // Creating posts of other users is forbidden
+            json!([create_post(title, user), create_post(title, user + 1)])
+        };
+        let responses = match post_as_new_user("/graphql", |user| {
+            vec![batch(&kept, user), find_post(&kept)]
+        }) {
+            Some(responses) => responses,
+            None => return,
+        };
+        assert_eq!(responses[0][0]["data"]["CreatePost"]["title"], kept);
+        assert_eq!(
+            responses[0][1]["errors"][0]["extensions"]["code"],
+            "FORBIDDEN"
+        );
+        assert_eq!(responses[1]["data"]["Posts"][0]["title"], kept);
+
+        let responses = match post_as_new_user("/graphql?transaction=true", |user| {
+            vec![batch(&rolled_back, user), find_post(&rolled_back)]
+        }) {
+            Some(responses) => responses,
+            None => return,
+        };
+        // The responses of all operations are returned, even of the rolled back ones
+        assert_eq!(responses[0][0]["data"]["CreatePost"]["title"], rolled_back);
+        assert_eq!(
+            responses[0][1]["errors"][0]["extensions"]["code"],
+            "FORBIDDEN"
+        );
+        assert_eq!(responses[1]["data"]["Posts"], json!([]));
+    }
+
+    /// A pool failing to connect, for requests not using the database
+    fn unconnected_pool() -> DbPool {
+        Pool::builder()
+            .min_idle(Some(0))
+            .connection_timeout(Duration::from_millis(10))
+            .build_unchecked(ConnectionManager::new("/nonexistent/database"))
+    }
+
+    #[test]
+    fn openapi_documents_the_known_routes() {
+        let mut app = test::init_service(
+            App::new()
+                .configure(model::posts::config)
+                .configure(model::users::config)
+                .configure(model::comments::config)
+                .route("/openapi.json", web::get().to(openapi))
+                .data(test_state(unconnected_pool()))
+                .default_service(web::route().to(HttpResponse::NotImplemented)),
+        );
+        let req = TestRequest::get().uri("/openapi.json").to_request();
+        let document: Value = test::read_response_json(&mut app, req);
+
+        let operations = document["paths"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .flat_map(|(path, methods)| {
+                methods
+                    .as_object()
+                    .unwrap()
+                    .keys()
+                    .map(move |method| format!("{} {}", method.to_uppercase(), path))
+            })
+            .collect::<BTreeSet<_>>();
+        // The routes of the models when this test was written, new routes
+        // are not found by the test and have to be added here by hand
+        let routes = [
+            "GET /posts",
+            "POST /posts",
+            "GET /posts/page",
+            "GET /posts/{id}",
+            "PATCH /posts/{id}",
+            "DELETE /posts/{id}",
+            "GET /posts/{id}/comments",
+            "GET /posts/{id}/versions",
+            "GET /posts/{id}/versions/{version}",
+            "GET /posts/{id}/diff",
+            "POST /posts/{id}/versions/{version}/revert",
+            "GET /posts/page/{page_number}",
+            "POST /posts/{id}/publish",
+            "POST /posts/{id}/unpublish",
+            "POST /posts/{id}/restore",
+            "GET /users",
+            "POST /users",
+            "GET /users/page",
+            "GET /users/{id}",
+            "PATCH /users/{id}",
+            "DELETE /users/{id}",
+            "GET /users/{id}/posts",
+            "GET /users/{id}/comments",
+            "PUT /users/{id}/roles/{role}",
+            "DELETE /users/{id}/roles/{role}",
+            "GET /comments",
+            "POST /comments",
+            "GET /comments/page",
+            "GET /comments/{id}",
+            "PATCH /comments/{id}",
+            "DELETE /comments/{id}",
+        ];
+        assert_eq!(
+            operations,
+            routes
+                .iter()
+                .map(|r| String::from(*r))
+                .collect::<BTreeSet<_>>()
+        );
+
+        // Each documented operation is handled by one of the routes
+        for operation in &operations {
+            let mut parts = operation.splitn(2, ' ');
+            let method = Method::from_bytes(parts.next().unwrap().as_bytes()).unwrap();
+            let uri = parts
+                .next()
+                .unwrap()
+                .replace("{id}", "1")
+                .replace("{version}", "1")
+                .replace("{page_number}", "1")
+                .replace("{role}", "Author");
+            let req = TestRequest::default().method(method).uri(&uri).to_request();
+            let status = test::call_service(&mut app, req).status();
+            assert!(
+                ![
+                    StatusCode::NOT_FOUND,
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    StatusCode::NOT_IMPLEMENTED
+                ]
+                .contains(&status),
+                "{} is not routed: {}",
+                operation,
+                status
+            );
+        }
+
+        let parameters = |operation: &Value| {
+            operation["parameters"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .filter(|p| p["in"] == "query")
+                .map(|p| p["name"].as_str().unwrap().to_owned())
+                .collect::<BTreeSet<_>>()
+        };
+        let post_query = [
+            "order",
+            "order_direction",
+            "id",
+            "title",
+            "content",
+            "later_than",
+            "author",
+            "as_of",
+        ];
+        let cursor = ["after", "before", "page_size"];
+        let expected_parameters = vec![
+            ("/posts", "get", post_query.to_vec()),
+            (
+                "/posts/page",
+                "get",
+                [&cursor[..], &post_query[..]].concat(),
+            ),
+            (
+                "/posts/page/{page_number}",
+                "get",
+                [&["page_size"][..], &post_query[..]].concat(),
+            ),
+            ("/posts/{id}/diff", "get", vec!["from", "to"]),
+            ("/users/page", "get", cursor.to_vec()),
+            ("/comments/page", "get", cursor.to_vec()),
+        ];
+        let expected_count = expected_parameters.len();
+        for (path, method, expected) in expected_parameters {
+            let expected = expected
+                .into_iter()
+                .map(String::from)
+                .collect::<BTreeSet<_>>();
+            assert_eq!(
+                parameters(&document["paths"][path][method]),
+                expected,
+                "{} {}",
+                method,
+                path
+            );
+        }
+        // All other operations take no query parameters
+        let with_parameters = document["paths"]
+            .as_object()
+            .unwrap()
+            .values()
+            .flat_map(|methods| methods.as_object().unwrap().values())
+            .filter(|operation| !parameters(operation).is_empty())
+            .count();
+        assert_eq!(with_parameters, expected_count);
+    }
+
+    #[test]
+    fn features_could_be_enabled_multiple_times() {
+        let opt = Opt::from_iter(&[
+            "rustfest",
+            "serve",
+            "--db-url",
+            "db",
+            "--jwt-secret",
+            "secret",
+            "--feature",
+            "localized-errors",
+            "--feature",
+            "other",
+        ]);
+        let features = match opt {
+            Opt::Serve(opt) => FeatureFlags::new(opt.features),
+            opt => panic!("Unexpected command {:?}", opt),
+        };
+        assert!(features.is_enabled(LOCALIZED_ERRORS));
+        assert!(features.is_enabled("other"));
+        assert!(!features.is_enabled("localized"));
+    }
+}
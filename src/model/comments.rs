@@ -1,10 +1,17 @@
-use crate::schema::comments;
+use crate::auth::Identity;
+use crate::authorization::{authorize_author, Authored, Authorized};
+use crate::db::{insert_and_load, DbConnection, Timestamp};
+use crate::error::ApiError;
+use crate::openapi::OpenApi;
+use crate::pagination::{CursorPage, CursorParams, Paginate};
+use crate::schema::{comments, posts, users};
+use crate::validation::{Validate, Validator, COMMENT};
+use crate::versioning::Versioned;
 use crate::AppState;
 use actix_web::web::{self, HttpRequest, Json};
-use chrono::{DateTime, Utc};
 use diesel::prelude::*;
-use failure::Error;
 use juniper::GraphQLInputObject;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 pub fn config(cfg: &mut web::ServiceConfig) {
@@ -14,6 +21,8 @@ pub fn config(cfg: &mut web::ServiceConfig) {
             .route(web::post().to(new_comment)),
     );
 
+    cfg.service(web::resource("/comments/page").route(web::get().to(cursor_paginated_comments)));
+
     cfg.service(
         web::resource("/comments/{id}")
             .route(web::get().to(get_comment_with_id))
@@ -22,16 +31,45 @@ pub fn config(cfg: &mut web::ServiceConfig) {
     );
 }
 
-#[derive(Serialize, Deserialize, Queryable, Debug)]
+/// Describe the routes registered by `config`
+pub fn openapi(api: &mut OpenApi) {
+    api.operation("GET", "/comments", "List all comments")
+        .responds_with::<Vec<Comment>>("The comments");
+    api.operation("POST", "/comments", "Comment on a post")
+        .authenticated()
+        .body::<NewComment>()
+        .responds_with::<Comment>("The new comment");
+    api.operation("GET", "/comments/page", "Page through comments by cursor")
+        .query::<CursorParams>()
+        .responds_with::<CursorPage<Comment>>("A page of comments");
+    api.operation("GET", "/comments/{id}", "Load a comment")
+        .path_param::<i32>("id")
+        .responds_with::<Comment>("The comment");
+    api.operation("PATCH", "/comments/{id}", "Change a comment")
+        .authenticated()
+        .path_param::<i32>("id")
+        .body::<CommentChangeset>()
+        .responds_with::<Comment>("The changed comment");
+    api.operation("DELETE", "/comments/{id}", "Delete a comment")
+        .authenticated()
+        .path_param::<i32>("id")
+        .responds_empty("The comment is deleted");
+}
+
+#[derive(Serialize, Deserialize, Queryable, Debug, JsonSchema)]
 pub struct Comment {
     id: i32,
     comment: Option<String>,
-    published_at: DateTime<Utc>,
+    published_at: Timestamp,
     author: i32,
     post: i32,
+    version_start: i32,
+    version_end: Option<i32>,
+    version_start_at: Timestamp,
+    version_end_at: Option<Timestamp>,
 }
 
-#[derive(Deserialize, Insertable, Debug, GraphQLInputObject)]
+#[derive(Deserialize, Insertable, Debug, GraphQLInputObject, JsonSchema)]
 #[table_name = "comments"]
 pub struct NewComment {
     comment: Option<String>,
@@ -39,36 +77,74 @@ pub struct NewComment {
     post: i32,
 }
 
-#[derive(Deserialize, AsChangeset, Debug, GraphQLInputObject)]
-#[table_name = "comments"]
+#[derive(Deserialize, Debug, GraphQLInputObject, JsonSchema)]
 pub struct CommentChangeset {
     comment: Option<Option<String>>,
     author: Option<i32>,
     post: Option<i32>,
 }
 
-fn all_comments(req: HttpRequest) -> Result<Json<Vec<Comment>>, Error> {
+impl Validate for NewComment {
+    fn rules(&self, v: &mut Validator<'_>) -> QueryResult<()> {
+        v.text("comment", self.comment.as_deref(), &COMMENT);
+        v.exists::<users::table>("author", Some(self.author))?;
+        v.exists::<posts::table>("post", Some(self.post))
+    }
+}
+
+impl Validate for CommentChangeset {
+    fn rules(&self, v: &mut Validator<'_>) -> QueryResult<()> {
+        v.text(
+            "comment",
+            self.comment.as_ref().and_then(Option::as_deref),
+            &COMMENT,
+        );
+        v.exists::<users::table>("author", self.author)?;
+        v.exists::<posts::table>("post", self.post)
+    }
+}
+
+impl Authored for NewComment {
+    fn author(&self) -> Option<i32> {
+        Some(self.author)
+    }
+}
+
+impl Authored for CommentChangeset {
+    fn author(&self) -> Option<i32> {
+        self.author
+    }
+}
+
+fn all_comments(req: HttpRequest) -> Result<Json<Vec<Comment>>, ApiError> {
     let conn = req
         .app_data::<AppState>()
         .expect("AppData set")
         .pool
         .get()?;
-    Ok(comments::table.load(&conn).map(Json)?)
+    Ok(comments::table
+        .filter(comments::version_end.is_null())
+        .load(&conn)
+        .map(Json)?)
 }
 
-fn new_comment(req: HttpRequest, new_post: Json<NewComment>) -> Result<Json<Comment>, Error> {
+fn new_comment(
+    req: HttpRequest,
+    _authorized: Authorized<comments::table>,
+    identity: Identity,
+    new_post: Json<NewComment>,
+) -> Result<Json<Comment>, ApiError> {
     let conn = req
         .app_data::<AppState>()
         .expect("AppData set")
         .pool
         .get()?;
-    Ok(diesel::insert_into(comments::table)
-        .values(new_post.0)
-        .get_result(&conn)
-        .map(Json)?)
+    authorize_author(&conn, identity, &*new_post)?;
+    new_post.validate(&conn)?;
+    Ok(insert_and_load(&conn, comments::table, new_post.0).map(Json)?)
 }
 
-fn get_comment_with_id(req: HttpRequest, id: web::Path<i32>) -> Result<Json<Comment>, Error> {
+fn get_comment_with_id(req: HttpRequest, id: web::Path<i32>) -> Result<Json<Comment>, ApiError> {
     let conn = req
         .app_data::<AppState>()
         .expect("AppData set")
@@ -76,35 +152,89 @@ fn get_comment_with_id(req: HttpRequest, id: web::Path<i32>) -> Result<Json<Comm
         .get()?;
 
     Ok(comments::table
-        .find(id.into_inner())
+        .filter(comments::id.eq(id.into_inner()))
+        .filter(comments::version_end.is_null())
         .first(&conn)
         .map(Json)?)
 }
 
 fn update_comment(
     req: HttpRequest,
+    _authorized: Authorized<comments::table>,
+    identity: Identity,
     id: web::Path<i32>,
     changeset: Json<CommentChangeset>,
-) -> Result<Json<Comment>, Error> {
+) -> Result<Json<Comment>, ApiError> {
     let conn = req
         .app_data::<AppState>()
         .expect("AppData set")
         .pool
         .get()?;
 
-    Ok(diesel::update(comments::table.find(id.into_inner()))
-        .set(changeset.0)
-        .get_result(&conn)
-        .map(Json)?)
+    authorize_author(&conn, identity, &*changeset)?;
+    changeset.validate(&conn)?;
+    let id = id.into_inner();
+    let changeset = changeset.into_inner();
+    let comment = conn.transaction(|| {
+        let current = comments::table
+            .filter(comments::id.eq(id))
+            .filter(comments::version_end.is_null())
+            .first::<Comment>(&conn)?;
+        let next_version = comments::table.close_current_version(&conn, id)?;
+
+        insert_and_load(
+            &conn,
+            comments::table,
+            (
+                comments::id.eq(id),
+                comments::comment.eq(changeset.comment.unwrap_or(current.comment)),
+                comments::published_at.eq(current.published_at),
+                comments::author.eq(changeset.author.unwrap_or(current.author)),
+                comments::post.eq(changeset.post.unwrap_or(current.post)),
+                comments::version_start.eq(next_version),
+            ),
+        )
+    })?;
+    Ok(Json(comment))
+}
+
+/// Remove a comment by closing its current version, older versions are kept
+pub fn remove_comment(conn: &DbConnection, id: i32) -> QueryResult<()> {
+    comments::table.close_current_version(conn, id).map(|_| ())
 }
 
-fn delete_comment(req: HttpRequest, id: web::Path<i32>) -> Result<(), Error> {
+fn delete_comment(
+    req: HttpRequest,
+    _authorized: Authorized<comments::table>,
+    id: web::Path<i32>,
+) -> Result<(), ApiError> {
     let conn = req
         .app_data::<AppState>()
         .expect("AppData set")
         .pool
         .get()?;
 
-    diesel::delete(comments::table.find(id.into_inner())).execute(&conn)?;
+    remove_comment(&conn, id.into_inner())?;
     Ok(())
 }
+
+fn cursor_paginated_comments(
+    req: HttpRequest,
+    web::Query(cursor): web::Query<CursorParams>,
+) -> Result<Json<CursorPage<Comment>>, ApiError> {
+    let conn = req
+        .app_data::<AppState>()
+        .expect("AppData set")
+        .pool
+        .get()?;
+
+    // All versions of a comment share the same id, so
+    // `version_start` is required to get an unique key
+    Ok(comments::table
+        .into_boxed()
+        .filter(comments::version_end.is_null())
+        .paginate_by_key((comments::id, comments::version_start))
+        .cursor(&cursor)?
+        .load_page(&conn, |c: &Comment| (c.id, c.version_start))
+        .map(Json)?)
+}
@@ -1,16 +1,26 @@
 use super::comments::Comment;
-use crate::pagination::{Paginate, DEFAULT_PER_PAGE};
-use crate::schema::{comments, posts};
+use crate::auth::Identity;
+use crate::authorization::{authorize_author, Authored, Authorized};
+use crate::db::{insert_and_load, DbBackend, DbConnection, Timestamp};
+use crate::error::ApiError;
+use crate::openapi::OpenApi;
+use crate::pagination::{CursorPage, CursorParams, Paginate, DEFAULT_PER_PAGE};
+use crate::schema::{comments, posts, users};
+use crate::validation::{Validate, Validator, POST_CONTENT, POST_TITLE};
+use crate::versioning::Versioned;
+use crate::views::post_versions;
 use crate::AppState;
+use actix_web::http::StatusCode;
 use actix_web::web::{self, HttpRequest, Json};
-use chrono::{DateTime, Utc};
+use diesel::backend::Backend;
 use diesel::deserialize::{self, FromSql};
-use diesel::pg::Pg;
 use diesel::prelude::*;
 use diesel::serialize::{self, ToSql};
-use failure::Error;
-use juniper::{GraphQLEnum, GraphQLInputObject};
+use diesel::sql_types::Text;
+use juniper::{GraphQLEnum, GraphQLInputObject, GraphQLObject};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::io::Write;
 use wundergraph::query_builder::types::WundergraphValue;
 
@@ -21,6 +31,8 @@ pub fn config(cfg: &mut web::ServiceConfig) {
             .route(web::post().to(new_post)),
     );
 
+    cfg.service(web::resource("/posts/page").route(web::get().to(cursor_paginated_posts)));
+
     cfg.service(
         web::resource("/posts/{id}")
             .route(web::get().to(get_post_with_id))
@@ -30,22 +42,223 @@ pub fn config(cfg: &mut web::ServiceConfig) {
 
     cfg.service(web::resource("/posts/{id}/comments").route(web::get().to(get_comments_for_post)));
 
+    cfg.service(web::resource("/posts/{id}/versions").route(web::get().to(get_post_versions)));
+
+    cfg.service(
+        web::resource("/posts/{id}/versions/{version}").route(web::get().to(get_post_version)),
+    );
+
+    cfg.service(web::resource("/posts/{id}/diff").route(web::get().to(get_post_diff)));
+
+    cfg.service(
+        web::resource("/posts/{id}/versions/{version}/revert")
+            .route(web::post().to(revert_post_to_version)),
+    );
+
     cfg.service(web::resource("/posts/page/{page_number}").route(web::get().to(paginated_posts)));
+
+    cfg.service(
+        web::resource("/posts/{id}/{transition:publish|unpublish|restore}")
+            .route(web::post().to(change_post_state)),
+    );
+}
+
+/// Describe the routes registered by `config`
+pub fn openapi(api: &mut OpenApi) {
+    api.operation("GET", "/posts", "List posts matching the query")
+        .query::<Query>()
+        .responds_with::<Vec<Post>>("The matching posts");
+    api.operation("POST", "/posts", "Create a draft post")
+        .authenticated()
+        .body::<NewPost>()
+        .responds_with::<Post>("The new post");
+    api.operation("GET", "/posts/page", "Page through posts by cursor")
+        .query::<CursorParams>()
+        .query::<Query>()
+        .responds_with::<CursorPage<Post>>("A page of posts");
+    api.operation("GET", "/posts/{id}", "Load a post")
+        .path_param::<i32>("id")
+        .responds_with::<Post>("The post");
+    api.operation("PATCH", "/posts/{id}", "Change a post")
+        .authenticated()
+        .path_param::<i32>("id")
+        .body::<PostChangeset>()
+        .responds_with::<Post>("The changed post");
+    api.operation("DELETE", "/posts/{id}", "Mark a post as deleted")
+        .authenticated()
+        .path_param::<i32>("id")
+        .responds_empty("The post is deleted");
+    api.operation("GET", "/posts/{id}/comments", "List the comments of a post")
+        .path_param::<i32>("id")
+        .responds_with::<Vec<Comment>>("The comments");
+    api.operation("GET", "/posts/{id}/versions", "List all versions of a post")
+        .path_param::<i32>("id")
+        .responds_with::<Vec<PostVersion>>("The versions, oldest first");
+    api.operation(
+        "GET",
+        "/posts/{id}/versions/{version}",
+        "Load a version of a post",
+    )
+    .path_param::<i32>("id")
+    .path_param::<i32>("version")
+    .responds_with::<PostVersion>("The version");
+    api.operation("GET", "/posts/{id}/diff", "Compare two versions of a post")
+        .path_param::<i32>("id")
+        .query::<DiffRange>()
+        .responds_with::<PostDiff>("The changes");
+    api.operation(
+        "POST",
+        "/posts/{id}/versions/{version}/revert",
+        "Copy the title and content of a version forward as current version",
+    )
+    .authenticated()
+    .path_param::<i32>("id")
+    .path_param::<i32>("version")
+    .responds_with::<Post>("The new current version");
+    api.operation(
+        "GET",
+        "/posts/page/{page_number}",
+        "Page through posts by number",
+    )
+    .path_param::<u32>("page_number")
+    .query::<PageSize>()
+    .responds_with::<PostPage>("A page of posts");
+    for (action, summary) in &[
+        ("publish", "Publish a draft"),
+        ("unpublish", "Turn a published post back into a draft"),
+        ("restore", "Restore a deleted post as draft"),
+    ] {
+        api.operation("POST", &format!("/posts/{{id}}/{}", action), summary)
+            .authenticated()
+            .path_param::<i32>("id")
+            .responds_with::<Post>("The post in its new state");
+    }
 }
 
-#[derive(Serialize, Deserialize, Queryable, Debug)]
+#[derive(Serialize, Deserialize, Queryable, Debug, JsonSchema)]
 pub struct Post {
     id: i32,
     title: String,
     content: Option<String>,
-    published_at: DateTime<Utc>,
+    /// When the post was published, drafts that were never published have none
+    published_at: Option<Timestamp>,
     author: i32,
     post_state: PostState,
     version_start: i32,
     version_end: Option<i32>,
+    version_start_at: Timestamp,
+    version_end_at: Option<Timestamp>,
+    /// The user who wrote this version, unknown for older versions
+    editor: Option<i32>,
 }
 
-#[derive(Deserialize, Debug, AsChangeset, GraphQLInputObject)]
+/// A single revision of a post
+///
+/// The revision is valid from `version` until (excluding) `valid_until`,
+/// `changed_fields` lists the fields changed compared to the previous revision
+/// and `editor` is the user who wrote the revision, if it is known.
+/// `version_start_at` and `version_end_at` give the same range as points in time.
+#[derive(Serialize, Debug, JsonSchema)]
+pub struct PostVersion {
+    id: i32,
+    version: i32,
+    valid_until: Option<i32>,
+    title: String,
+    content: Option<String>,
+    published_at: Option<Timestamp>,
+    author: i32,
+    post_state: PostState,
+    editor: Option<i32>,
+    changed_fields: Vec<String>,
+    version_start_at: Timestamp,
+    version_end_at: Option<Timestamp>,
+}
+
+/// A row of the `post_versions` view
+#[derive(Queryable, Debug, Clone)]
+struct PostVersionRow {
+    id: i32,
+    version: i32,
+    valid_until: Option<i32>,
+    title: String,
+    content: Option<String>,
+    published_at: Option<Timestamp>,
+    author: i32,
+    post_state: PostState,
+    editor: Option<i32>,
+    version_start_at: Timestamp,
+    version_end_at: Option<Timestamp>,
+}
+
+impl PostVersion {
+    /// `previous` is the revision replaced by `row`, if there is one
+    fn new(row: PostVersionRow, previous: Option<&PostVersionRow>) -> Self {
+        let mut changed_fields = Vec::new();
+        if let Some(previous) = previous {
+            let changes = [
+                ("title", row.title != previous.title),
+                ("content", row.content != previous.content),
+                ("author", row.author != previous.author),
+                ("post_state", row.post_state != previous.post_state),
+            ];
+            changed_fields.extend(
+                changes
+                    .iter()
+                    .filter(|(_, changed)| *changed)
+                    .map(|(field, _)| String::from(*field)),
+            );
+        }
+        PostVersion {
+            id: row.id,
+            version: row.version,
+            valid_until: row.valid_until,
+            title: row.title,
+            content: row.content,
+            published_at: row.published_at,
+            author: row.author,
+            post_state: row.post_state,
+            editor: row.editor,
+            changed_fields,
+            version_start_at: row.version_start_at,
+            version_end_at: row.version_end_at,
+        }
+    }
+}
+
+/// Changes between two versions of a post
+///
+/// Fields that are equal in both versions are `None`
+#[derive(Serialize, Debug, GraphQLObject, JsonSchema)]
+pub struct PostDiff {
+    id: i32,
+    from: i32,
+    to: i32,
+    title: Option<TitleChange>,
+    content: Option<ContentChange>,
+    post_state: Option<PostStateChange>,
+}
+
+#[derive(Serialize, Debug, GraphQLObject, JsonSchema)]
+pub struct TitleChange {
+    old: String,
+    new: String,
+}
+
+#[derive(Serialize, Debug, GraphQLObject, JsonSchema)]
+pub struct ContentChange {
+    old: Option<String>,
+    new: Option<String>,
+    /// Line based diff in the unified format
+    unified_diff: String,
+}
+
+#[derive(Serialize, Debug, GraphQLObject, JsonSchema)]
+pub struct PostStateChange {
+    old: PostState,
+    new: PostState,
+}
+
+#[derive(Deserialize, Debug, AsChangeset, GraphQLInputObject, JsonSchema)]
 #[table_name = "posts"]
 pub struct PostChangeset {
     title: Option<String>,
@@ -53,7 +266,7 @@ pub struct PostChangeset {
     author: Option<i32>,
 }
 
-#[derive(Deserialize, Insertable, Debug, GraphQLInputObject)]
+#[derive(Deserialize, Insertable, Debug, GraphQLInputObject, JsonSchema)]
 #[table_name = "posts"]
 pub struct NewPost {
     title: String,
@@ -61,9 +274,42 @@ pub struct NewPost {
     author: i32,
 }
 
+impl Validate for NewPost {
+    fn rules(&self, v: &mut Validator<'_>) -> QueryResult<()> {
+        v.text("title", Some(&self.title), &POST_TITLE);
+        v.text("content", self.content.as_deref(), &POST_CONTENT);
+        v.exists::<users::table>("author", Some(self.author))
+    }
+}
+
+impl Validate for PostChangeset {
+    fn rules(&self, v: &mut Validator<'_>) -> QueryResult<()> {
+        v.text("title", self.title.as_deref(), &POST_TITLE);
+        v.text(
+            "content",
+            self.content.as_ref().and_then(Option::as_deref),
+            &POST_CONTENT,
+        );
+        v.exists::<users::table>("author", self.author)
+    }
+}
+
+impl Authored for NewPost {
+    fn author(&self) -> Option<i32> {
+        Some(self.author)
+    }
+}
+
+impl Authored for PostChangeset {
+    fn author(&self) -> Option<i32> {
+        self.author
+    }
+}
+
 #[derive(Debug, Clone, Copy, SqlType, QueryId)]
 #[allow(non_camel_case_types)]
 #[postgres(type_name = "post_state")]
+#[sqlite_type = "Text"]
 pub struct Post_state;
 
 #[derive(
@@ -76,6 +322,8 @@ pub struct Post_state;
     WundergraphValue,
     Clone,
     Copy,
+    PartialEq,
+    JsonSchema,
 )]
 #[sql_type = "Post_state"]
 pub enum PostState {
@@ -84,7 +332,13 @@ pub enum PostState {
     Deleted,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, JsonSchema)]
+struct DiffRange {
+    from: i32,
+    to: i32,
+}
+
+#[derive(Deserialize, Debug, JsonSchema)]
 enum PostColumn {
     Id,
     Title,
@@ -93,36 +347,45 @@ enum PostColumn {
     Author,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone, Copy, JsonSchema)]
 enum OrderDirection {
     Asc,
     Desc,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Default, JsonSchema)]
 struct Query {
     order: Option<PostColumn>,
     order_direction: Option<OrderDirection>,
     id: Option<i32>,
     title: Option<String>,
     content: Option<String>,
-    later_than: Option<DateTime<Utc>>,
+    /// Only return posts published at or after this point in time,
+    /// drafts that were never published are left out
+    later_than: Option<Timestamp>,
     author: Option<i32>,
+    /// Return the versions that were valid at this point in time
+    /// instead of the current versions
+    as_of: Option<Timestamp>,
 }
 
-impl FromSql<Post_state, Pg> for PostState {
-    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
-        match bytes {
-            Some(b"Draft") => Ok(PostState::Draft),
-            Some(b"Published") => Ok(PostState::Published),
-            Some(b"Deleted") => Ok(PostState::Deleted),
+impl<DB> FromSql<Post_state, DB> for PostState
+where
+    DB: Backend,
+    String: FromSql<Text, DB>,
+{
+    fn from_sql(bytes: Option<&DB::RawValue>) -> deserialize::Result<Self> {
+        match String::from_sql(bytes)?.as_str() {
+            "Draft" => Ok(PostState::Draft),
+            "Published" => Ok(PostState::Published),
+            "Deleted" => Ok(PostState::Deleted),
             _ => Err("Unrecognized enum variant".into()),
         }
     }
 }
 
-impl ToSql<Post_state, Pg> for PostState {
-    fn to_sql<W: Write>(&self, out: &mut serialize::Output<W, Pg>) -> serialize::Result {
+impl<DB: Backend> ToSql<Post_state, DB> for PostState {
+    fn to_sql<W: Write>(&self, out: &mut serialize::Output<W, DB>) -> serialize::Result {
         match self {
             PostState::Draft => out.write_all(b"Draft")?,
             PostState::Published => out.write_all(b"Published")?,
@@ -132,62 +395,383 @@ impl ToSql<Post_state, Pg> for PostState {
     }
 }
 
-fn new_post(req: HttpRequest, new_post: Json<NewPost>) -> Result<Json<Post>, Error> {
+/// The changes of the state of a post
+///
+/// Posts are created as drafts. Drafts could be published and published
+/// posts unpublished again, both could be deleted. Deleted posts could only
+/// be restored, which turns them into drafts.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PostTransition {
+    Publish,
+    Unpublish,
+    Delete,
+    Restore,
+}
+
+impl PostTransition {
+    /// The state of a post in `state` after the transition,
+    /// `None` if the transition is not allowed in that state
+    pub fn target(self, state: PostState) -> Option<PostState> {
+        match (self, state) {
+            (PostTransition::Publish, PostState::Draft) => Some(PostState::Published),
+            (PostTransition::Unpublish, PostState::Published) => Some(PostState::Draft),
+            (PostTransition::Delete, PostState::Draft)
+            | (PostTransition::Delete, PostState::Published) => Some(PostState::Deleted),
+            (PostTransition::Restore, PostState::Deleted) => Some(PostState::Draft),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TransitionError {
+    /// The transition is not allowed in the current state of the post
+    NotAllowed(PostTransition, PostState),
+    Database(diesel::result::Error),
+}
+
+impl fmt::Display for TransitionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransitionError::NotAllowed(transition, state) => write!(
+                f,
+                "{:?} is not possible for a post in the {:?} state",
+                transition, state
+            ),
+            TransitionError::Database(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<diesel::result::Error> for TransitionError {
+    fn from(e: diesel::result::Error) -> Self {
+        TransitionError::Database(e)
+    }
+}
+
+impl From<TransitionError> for ApiError {
+    fn from(e: TransitionError) -> Self {
+        match e {
+            TransitionError::NotAllowed(..) => ApiError::new(
+                StatusCode::CONFLICT,
+                "INVALID_STATE_TRANSITION",
+                e.to_string(),
+            ),
+            TransitionError::Database(e) => e.into(),
+        }
+    }
+}
+
+/// Change the content of a post by inserting a new version
+///
+/// The state and `published_at` are kept, they are
+/// only changed by a `PostTransition`.
+pub fn change_post(
+    conn: &DbConnection,
+    id: i32,
+    editor: Identity,
+    changeset: PostChangeset,
+) -> QueryResult<Post> {
+    conn.transaction(|| {
+        let current = posts::table
+            .filter(posts::id.eq(id))
+            .filter(posts::version_end.is_null())
+            .first::<Post>(conn)?;
+        let next_version = posts::table.close_current_version(conn, id)?;
+
+        insert_and_load(
+            conn,
+            posts::table,
+            (
+                posts::id.eq(id),
+                posts::title.eq(changeset.title.unwrap_or(current.title)),
+                posts::content.eq(changeset.content.unwrap_or(current.content)),
+                posts::published_at.eq(current.published_at),
+                posts::author.eq(changeset.author.unwrap_or(current.author)),
+                posts::post_state.eq(current.post_state),
+                posts::version_start.eq(next_version),
+                posts::editor.eq(editor.user_id),
+            ),
+        )
+    })
+}
+
+/// Change the state of a post by inserting a new version
+///
+/// `published_at` is set when the post is published
+/// and copied over from the current version otherwise,
+/// it is never written anywhere else.
+pub fn transition_post(
+    conn: &DbConnection,
+    id: i32,
+    editor: Identity,
+    transition: PostTransition,
+) -> Result<Post, TransitionError> {
+    conn.transaction(|| {
+        let current = posts::table
+            .filter(posts::id.eq(id))
+            .filter(posts::version_end.is_null())
+            .first::<Post>(conn)?;
+        let post_state = transition
+            .target(current.post_state)
+            .ok_or(TransitionError::NotAllowed(transition, current.post_state))?;
+
+        let next_version = posts::table.close_current_version(conn, id)?;
+
+        let values = (
+            posts::id.eq(id),
+            posts::title.eq(current.title),
+            posts::content.eq(current.content),
+            posts::author.eq(current.author),
+            posts::post_state.eq(post_state),
+            posts::version_start.eq(next_version),
+            posts::version_end.eq(Option::<i32>::None),
+            posts::editor.eq(editor.user_id),
+        );
+        let post = if transition == PostTransition::Publish {
+            insert_and_load(
+                conn,
+                posts::table,
+                (values, posts::published_at.eq(diesel::dsl::now)),
+            )
+        } else {
+            insert_and_load(
+                conn,
+                posts::table,
+                (values, posts::published_at.eq(current.published_at)),
+            )
+        };
+        Ok(post?)
+    })
+}
+
+/// Copy an older version of a post forward as new current version
+///
+/// The older versions stay untouched, so a revert could be reverted as well.
+/// Only the title and the content are copied. The author is kept, as the
+/// caller is only authorized for the current author, the state and
+/// `published_at` are kept as they are only changed by a `PostTransition`.
+pub fn revert_post(
+    conn: &DbConnection,
+    id: i32,
+    editor: Identity,
+    version: i32,
+) -> QueryResult<Post> {
+    conn.transaction(|| {
+        let (title, content) = posts::table
+            .select((posts::title, posts::content))
+            .filter(posts::id.eq(id).and(posts::version_start.eq(version)))
+            .first::<(String, Option<String>)>(conn)?;
+        let (author, published_at, post_state) = posts::table
+            .select((posts::author, posts::published_at, posts::post_state))
+            .filter(posts::id.eq(id))
+            .filter(posts::version_end.is_null())
+            .first::<(i32, Option<Timestamp>, PostState)>(conn)?;
+
+        let next_version = posts::table.close_current_version(conn, id)?;
+
+        insert_and_load(
+            conn,
+            posts::table,
+            (
+                posts::id.eq(id),
+                posts::title.eq(title),
+                posts::content.eq(content),
+                posts::author.eq(author),
+                posts::published_at.eq(published_at),
+                posts::post_state.eq(post_state),
+                posts::version_start.eq(next_version),
+                posts::version_end.eq(Option::<i32>::None),
+                posts::editor.eq(editor.user_id),
+            ),
+        )
+    })
+}
+
+/// Load the versions of a post, oldest first, or only the given version
+pub fn post_versions_of(
+    conn: &DbConnection,
+    id: i32,
+    version: Option<i32>,
+) -> QueryResult<Vec<PostVersion>> {
+    let mut query = post_versions::table
+        .filter(post_versions::id.eq(id))
+        .order_by(post_versions::version)
+        .into_boxed();
+    if let Some(version) = version {
+        // The previous version ends where the requested one starts
+        query = query.filter(
+            post_versions::version
+                .eq(version)
+                .or(post_versions::valid_until.eq(version)),
+        );
+    }
+    let rows = query.load::<PostVersionRow>(conn)?;
+
+    Ok(rows
+        .iter()
+        .filter(|row| version.map_or(true, |v| row.version == v))
+        .map(|row| {
+            let previous = rows.iter().find(|p| p.valid_until == Some(row.version));
+            PostVersion::new(row.clone(), previous)
+        })
+        .collect())
+}
+
+/// Compare the versions `from` and `to` of a post
+pub fn diff_post(conn: &DbConnection, id: i32, from: i32, to: i32) -> QueryResult<PostDiff> {
+    Ok(diff_versions(
+        post_version(conn, id, from)?,
+        post_version(conn, id, to)?,
+    ))
+}
+
+/// Load a single version of a post
+pub fn post_version(conn: &DbConnection, id: i32, version: i32) -> QueryResult<Post> {
+    posts::table
+        .filter(posts::id.eq(id).and(posts::version_start.eq(version)))
+        .first::<Post>(conn)
+}
+
+/// Compare the version `old` of a post with the version `new`
+pub fn diff_versions(old: Post, new: Post) -> PostDiff {
+    let (id, from, to) = (old.id, old.version_start, new.version_start);
+    let title = if old.title != new.title {
+        Some(TitleChange {
+            old: old.title,
+            new: new.title,
+        })
+    } else {
+        None
+    };
+    let content = if old.content != new.content {
+        let unified_diff = diffy::create_patch(
+            old.content.as_ref().map_or("", |c| c.as_str()),
+            new.content.as_ref().map_or("", |c| c.as_str()),
+        )
+        .to_string();
+        Some(ContentChange {
+            old: old.content,
+            new: new.content,
+            unified_diff,
+        })
+    } else {
+        None
+    };
+    let post_state = if old.post_state != new.post_state {
+        Some(PostStateChange {
+            old: old.post_state,
+            new: new.post_state,
+        })
+    } else {
+        None
+    };
+
+    PostDiff {
+        id,
+        from,
+        to,
+        title,
+        content,
+        post_state,
+    }
+}
+
+fn new_post(
+    req: HttpRequest,
+    _authorized: Authorized<posts::table>,
+    identity: Identity,
+    new_post: Json<NewPost>,
+) -> Result<Json<Post>, ApiError> {
     let conn = req
         .app_data::<AppState>()
         .expect("AppData set")
         .pool
         .get()?;
-    Ok(diesel::insert_into(posts::table)
-        .values((new_post.0, posts::post_state.eq(PostState::Draft)))
-        .get_result(&conn)
-        .map(Json)?)
+    authorize_author(&conn, identity, &*new_post)?;
+    new_post.validate(&conn)?;
+    Ok(insert_and_load(
+        &conn,
+        posts::table,
+        (
+            new_post.0,
+            posts::post_state.eq(PostState::Draft),
+            posts::published_at.eq(Option::<Timestamp>::None),
+            posts::editor.eq(identity.user_id),
+        ),
+    )
+    .map(Json)?)
 }
 
-fn get_post_with_id(req: HttpRequest, id: web::Path<i32>) -> Result<Json<Post>, Error> {
+fn get_post_with_id(req: HttpRequest, id: web::Path<i32>) -> Result<Json<Post>, ApiError> {
     let conn = req
         .app_data::<AppState>()
         .expect("AppData set")
         .pool
         .get()?;
 
-    Ok(posts::table.find(id.into_inner()).first(&conn).map(Json)?)
+    Ok(posts::table
+        .filter(posts::id.eq(id.into_inner()))
+        .filter(posts::version_end.is_null())
+        .first(&conn)
+        .map(Json)?)
 }
 
 fn update_post(
     req: HttpRequest,
+    _authorized: Authorized<posts::table>,
+    identity: Identity,
     id: web::Path<i32>,
     changeset: Json<PostChangeset>,
-) -> Result<Json<Post>, Error> {
+) -> Result<Json<Post>, ApiError> {
     let conn = req
         .app_data::<AppState>()
         .expect("AppData set")
         .pool
         .get()?;
 
-    Ok(diesel::update(posts::table.find(id.into_inner()))
-        .set(changeset.0)
-        .get_result(&conn)
-        .map(Json)?)
+    authorize_author(&conn, identity, &*changeset)?;
+    changeset.validate(&conn)?;
+    Ok(change_post(&conn, id.into_inner(), identity, changeset.into_inner()).map(Json)?)
 }
 
-fn delete_post(req: HttpRequest, id: web::Path<i32>) -> Result<(), Error> {
+fn delete_post(
+    req: HttpRequest,
+    _authorized: Authorized<posts::table>,
+    identity: Identity,
+    id: web::Path<i32>,
+) -> Result<(), ApiError> {
     let conn = req
         .app_data::<AppState>()
         .expect("AppData set")
         .pool
         .get()?;
 
-    diesel::update(posts::table.find(id.into_inner()))
-        .set(posts::post_state.eq(PostState::Deleted))
-        .execute(&conn)?;
+    transition_post(&conn, id.into_inner(), identity, PostTransition::Delete)?;
     Ok(())
 }
 
+fn change_post_state(
+    req: HttpRequest,
+    _authorized: Authorized<posts::table>,
+    identity: Identity,
+    path: web::Path<(i32, PostTransition)>,
+) -> Result<Json<Post>, ApiError> {
+    let conn = req
+        .app_data::<AppState>()
+        .expect("AppData set")
+        .pool
+        .get()?;
+
+    let (id, transition) = path.into_inner();
+    Ok(transition_post(&conn, id, identity, transition).map(Json)?)
+}
+
 fn get_comments_for_post(
     req: HttpRequest,
     id: web::Path<i32>,
-) -> Result<Json<Vec<Comment>>, Error> {
+) -> Result<Json<Vec<Comment>>, ApiError> {
     let conn = req
         .app_data::<AppState>()
         .expect("AppData set")
@@ -195,12 +779,73 @@ fn get_comments_for_post(
         .get()?;
 
     Ok(comments::table
-        .filter(comments::id.eq(id.into_inner()))
+        .filter(comments::post.eq(id.into_inner()))
+        .filter(comments::version_end.is_null())
         .load(&conn)
         .map(Json)?)
 }
 
-fn build_post_query(query: Query) -> diesel::dsl::IntoBoxed<'static, posts::table, Pg> {
+fn get_post_versions(
+    req: HttpRequest,
+    id: web::Path<i32>,
+) -> Result<Json<Vec<PostVersion>>, ApiError> {
+    let conn = req
+        .app_data::<AppState>()
+        .expect("AppData set")
+        .pool
+        .get()?;
+
+    Ok(post_versions_of(&conn, id.into_inner(), None).map(Json)?)
+}
+
+fn get_post_version(
+    req: HttpRequest,
+    path: web::Path<(i32, i32)>,
+) -> Result<Json<PostVersion>, ApiError> {
+    let conn = req
+        .app_data::<AppState>()
+        .expect("AppData set")
+        .pool
+        .get()?;
+
+    let (id, version) = path.into_inner();
+    post_versions_of(&conn, id, Some(version))?
+        .pop()
+        .map(Json)
+        .ok_or_else(ApiError::not_found)
+}
+
+fn revert_post_to_version(
+    req: HttpRequest,
+    _authorized: Authorized<posts::table>,
+    identity: Identity,
+    path: web::Path<(i32, i32)>,
+) -> Result<Json<Post>, ApiError> {
+    let conn = req
+        .app_data::<AppState>()
+        .expect("AppData set")
+        .pool
+        .get()?;
+
+    let (id, version) = path.into_inner();
+    Ok(revert_post(&conn, id, identity, version).map(Json)?)
+}
+
+fn get_post_diff(
+    req: HttpRequest,
+    id: web::Path<i32>,
+    web::Query(range): web::Query<DiffRange>,
+) -> Result<Json<PostDiff>, ApiError> {
+    let conn = req
+        .app_data::<AppState>()
+        .expect("AppData set")
+        .pool
+        .get()?;
+
+    Ok(diff_post(&conn, id.into_inner(), range.from, range.to).map(Json)?)
+}
+
+fn build_post_query(query: Query) -> diesel::dsl::IntoBoxed<'static, posts::table, DbBackend> {
     let mut post_query = posts::table.into_boxed();
 
     if let Some(id) = query.id {
@@ -218,6 +863,17 @@ fn build_post_query(query: Query) -> diesel::dsl::IntoBoxed<'static, posts::tabl
     if let Some(author) = query.author {
         post_query = post_query.filter(posts::author.eq(author));
     }
+    if let Some(as_of) = query.as_of {
+        post_query = post_query.filter(
+            posts::version_start_at.le(as_of).and(
+                posts::version_end_at
+                    .gt(as_of)
+                    .or(posts::version_end_at.is_null()),
+            ),
+        );
+    } else {
+        post_query = post_query.filter(posts::version_end.is_null());
+    }
 
     match (query.order, query.order_direction) {
         (Some(PostColumn::Id), Some(OrderDirection::Desc)) => {
@@ -238,11 +894,14 @@ fn build_post_query(query: Query) -> diesel::dsl::IntoBoxed<'static, posts::tabl
         (Some(PostColumn::Content), _) => {
             post_query = post_query.order_by(posts::content);
         }
+        // Postgres and SQLite sort `NULL` differently,
+        // drafts that were never published are always listed last
         (Some(PostColumn::PublishedAt), Some(OrderDirection::Desc)) => {
-            post_query = post_query.order_by(posts::published_at.desc());
+            post_query =
+                post_query.order_by((posts::published_at.is_null(), posts::published_at.desc()));
         }
         (Some(PostColumn::PublishedAt), _) => {
-            post_query = post_query.order_by(posts::published_at);
+            post_query = post_query.order_by((posts::published_at.is_null(), posts::published_at));
         }
         (Some(PostColumn::Author), Some(OrderDirection::Desc)) => {
             post_query = post_query.order_by(posts::author.desc());
@@ -259,7 +918,7 @@ fn build_post_query(query: Query) -> diesel::dsl::IntoBoxed<'static, posts::tabl
 fn get_posts_with_query(
     req: HttpRequest,
     web::Query(query): web::Query<Query>,
-) -> Result<Json<Vec<Post>>, Error> {
+) -> Result<Json<Vec<Post>>, ApiError> {
     let conn = req
         .app_data::<AppState>()
         .expect("AppData set")
@@ -271,14 +930,14 @@ fn get_posts_with_query(
     Ok(post_query.load(&conn).map(Json)?)
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct PageSize {
     page_size: Option<u32>,
     #[serde(flatten)]
     query: Query,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, JsonSchema)]
 struct PostPage {
     page_number: u32,
     posts: Vec<Post>,
@@ -289,7 +948,7 @@ fn paginated_posts(
     req: HttpRequest,
     page: web::Path<u32>,
     web::Query(query): web::Query<PageSize>,
-) -> Result<Json<PostPage>, Error> {
+) -> Result<Json<PostPage>, ApiError> {
     let conn = req
         .app_data::<AppState>()
         .expect("AppData set")
@@ -311,3 +970,497 @@ fn paginated_posts(
         })
         .map(Json)?)
 }
+
+fn cursor_paginated_posts(
+    req: HttpRequest,
+    web::Query(cursor): web::Query<CursorParams>,
+    web::Query(query): web::Query<Query>,
+) -> Result<Json<CursorPage<Post>>, ApiError> {
+    let conn = req
+        .app_data::<AppState>()
+        .expect("AppData set")
+        .pool
+        .get()?;
+
+    Ok(Json(load_cursor_page(&conn, &cursor, query)?))
+}
+
+/// The page of posts matching `query` selected by `cursor`
+fn load_cursor_page(
+    conn: &DbConnection,
+    cursor: &CursorParams,
+    mut query: Query,
+) -> Result<CursorPage<Post>, ApiError> {
+    let descending = matches!(query.order_direction, Some(OrderDirection::Desc));
+    // The order is applied by the keyset pagination itself
+    let order = query.order.take();
+    let post_query = build_post_query(query);
+
+    // All versions of a post share the same id, so
+    // `version_start` is required to get an unique key
+    let page = match order {
+        None | Some(PostColumn::Id) => post_query
+            .paginate_by_key((posts::id, posts::version_start))
+            .descending(descending)
+            .cursor(cursor)?
+            .load_page(conn, |p: &Post| (p.id, p.version_start))?,
+        Some(PostColumn::Title) => post_query
+            .paginate_by_key((posts::title, posts::id, posts::version_start))
+            .descending(descending)
+            .cursor(cursor)?
+            .load_page(conn, |p: &Post| (p.title.clone(), p.id, p.version_start))?,
+        Some(PostColumn::Author) => post_query
+            .paginate_by_key((posts::author, posts::id, posts::version_start))
+            .descending(descending)
+            .cursor(cursor)?
+            .load_page(conn, |p: &Post| (p.author, p.id, p.version_start))?,
+        Some(column @ PostColumn::Content) | Some(column @ PostColumn::PublishedAt) => {
+            // Both columns are nullable and `NULL` values could not be compared
+            // in a row comparison, so there is no way to build a stable key
+            let column = match column {
+                PostColumn::Content => "content",
+                _ => "published_at",
+            };
+            return Err(ApiError::unprocessable(
+                "UNSUPPORTED_ORDER",
+                format!(
+                    "Ordering by {} is not supported for cursor based pagination",
+                    column
+                ),
+            ));
+        }
+    };
+
+    Ok(page)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::test_connection;
+
+    const EDITOR: Identity = Identity { user_id: 1 };
+
+    fn new_post(conn: &DbConnection) -> Post {
+        let new_post = NewPost {
+            title: "A title".into(),
+            content: Some("Some content".into()),
+            author: 1,
+        };
+        insert_and_load(
+            conn,
+            posts::table,
+            (new_post, posts::post_state.eq(PostState::Draft)),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn changed_posts_are_listed_once() {
+        let conn = match test_connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let post = new_post(&conn);
+        let changeset = PostChangeset {
+            title: Some("A new title".into()),
+            content: None,
+            author: None,
+        };
+        change_post(&conn, post.id, EDITOR, changeset).unwrap();
+
+        let query = Query {
+            id: Some(post.id),
+            ..Query::default()
+        };
+        let posts = build_post_query(query).load::<Post>(&conn).unwrap();
+        assert_eq!(posts.len(), 1);
+        assert_eq!(posts[0].title, "A new title");
+        assert_eq!(posts[0].content, post.content);
+        assert_eq!(posts[0].version_start, 1);
+        assert_eq!(posts[0].post_state, PostState::Draft);
+    }
+
+    #[test]
+    fn posts_are_created_as_the_caller() {
+        use crate::authorization::{authorize, Action, AuthorizationError};
+        use crate::model::users::{tests::insert_user, Role};
+
+        let conn = match test_connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let author = Identity {
+            user_id: insert_user(&conn, &[Role::Author]),
+        };
+        let admin = Identity {
+            user_id: insert_user(&conn, &[Role::Admin]),
+        };
+        let other = insert_user(&conn, &[Role::Author]);
+        let new_post = |author| NewPost {
+            title: "A title".into(),
+            content: None,
+            author,
+        };
+
+        authorize::<posts::table>(&conn, author, Action::Create).unwrap();
+        authorize_author(&conn, author, &new_post(author.user_id)).unwrap();
+        match authorize_author(&conn, author, &new_post(other)) {
+            Err(AuthorizationError::NotAuthor) => {}
+            other => panic!("Expected the post to be rejected, got {:?}", other),
+        }
+        let changeset = PostChangeset {
+            title: None,
+            content: None,
+            author: Some(other),
+        };
+        match authorize_author(&conn, author, &changeset) {
+            Err(AuthorizationError::NotAuthor) => {}
+            other => panic!("Expected the change to be rejected, got {:?}", other),
+        }
+
+        authorize_author(&conn, admin, &new_post(other)).unwrap();
+    }
+
+    #[test]
+    fn changes_keep_the_state() {
+        let conn = match test_connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let post = new_post(&conn);
+        let published = transition_post(&conn, post.id, EDITOR, PostTransition::Publish).unwrap();
+        let changeset = PostChangeset {
+            title: None,
+            content: Some(None),
+            author: None,
+        };
+        let changed = change_post(&conn, post.id, EDITOR, changeset).unwrap();
+        assert_eq!(changed.content, None);
+        assert_eq!(changed.post_state, PostState::Published);
+        assert_eq!(changed.published_at, published.published_at);
+    }
+
+    #[test]
+    fn only_publishing_sets_published_at() {
+        let conn = match test_connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let draft = new_post(&conn);
+        assert_eq!(draft.published_at, None);
+        let post = new_post(&conn);
+        let published = transition_post(&conn, post.id, EDITOR, PostTransition::Publish).unwrap();
+        assert!(published.published_at.is_some());
+        let unpublished =
+            transition_post(&conn, post.id, EDITOR, PostTransition::Unpublish).unwrap();
+        assert_eq!(unpublished.published_at, published.published_at);
+
+        let ids = [draft.id, post.id];
+        for &direction in &[OrderDirection::Asc, OrderDirection::Desc] {
+            let query = Query {
+                order: Some(PostColumn::PublishedAt),
+                order_direction: Some(direction),
+                ..Query::default()
+            };
+            let listed = build_post_query(query)
+                .load::<Post>(&conn)
+                .unwrap()
+                .into_iter()
+                .map(|p| p.id)
+                .filter(|id| ids.contains(id))
+                .collect::<Vec<_>>();
+            assert_eq!(listed, [post.id, draft.id], "{:?}", direction);
+        }
+
+        let query = Query {
+            later_than: published.published_at,
+            ..Query::default()
+        };
+        let listed = build_post_query(query).load::<Post>(&conn).unwrap();
+        assert!(listed.iter().any(|p| p.id == post.id));
+        assert!(listed.iter().all(|p| p.id != draft.id));
+    }
+
+    #[test]
+    fn cursor_pages_are_not_ordered_by_published_at() {
+        let conn = match test_connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let draft = new_post(&conn);
+        let query = |order| Query {
+            id: Some(draft.id),
+            order,
+            ..Query::default()
+        };
+        let page = load_cursor_page(&conn, &CursorParams::default(), query(None)).unwrap();
+        assert_eq!(page.items.len(), 1);
+        // Drafts have no `published_at` and could not be part of the key
+        let e = load_cursor_page(
+            &conn,
+            &CursorParams::default(),
+            query(Some(PostColumn::PublishedAt)),
+        )
+        .unwrap_err();
+        assert_eq!(e.code(), "UNSUPPORTED_ORDER");
+    }
+
+    #[test]
+    fn reverts_keep_the_author() {
+        let conn = match test_connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let post = new_post(&conn);
+        let changeset = PostChangeset {
+            title: Some("A new title".into()),
+            content: None,
+            author: Some(2),
+        };
+        change_post(&conn, post.id, EDITOR, changeset).unwrap();
+
+        let reverted = revert_post(&conn, post.id, EDITOR, post.version_start).unwrap();
+        assert_eq!(reverted.title, post.title);
+        assert_eq!(reverted.author, 2);
+        assert_eq!(reverted.version_start, 2);
+    }
+
+    #[test]
+    fn versions_list_their_changes_and_editor() {
+        let conn = match test_connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let post = new_post(&conn);
+        let changeset = PostChangeset {
+            title: Some("A new title".into()),
+            content: None,
+            author: None,
+        };
+        let editor = Identity { user_id: 2 };
+        change_post(&conn, post.id, editor, changeset).unwrap();
+        transition_post(&conn, post.id, EDITOR, PostTransition::Publish).unwrap();
+
+        let versions = post_versions_of(&conn, post.id, None).unwrap();
+        let changes = versions
+            .iter()
+            .map(|v| (v.version, v.editor, v.changed_fields.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            [
+                (0, None, vec![]),
+                (1, Some(2), vec![String::from("title")]),
+                (2, Some(1), vec![String::from("post_state")]),
+            ]
+        );
+
+        let version = post_versions_of(&conn, post.id, Some(1)).unwrap();
+        assert_eq!(version.len(), 1);
+        assert_eq!(version[0].changed_fields, ["title"]);
+        assert!(post_versions_of(&conn, post.id, Some(3))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn diffs_contain_the_changed_fields() {
+        let conn = match test_connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let post = new_post(&conn);
+        let changeset = PostChangeset {
+            title: Some("A new title".into()),
+            content: None,
+            author: None,
+        };
+        change_post(&conn, post.id, EDITOR, changeset).unwrap();
+        let changeset = PostChangeset {
+            title: None,
+            content: Some(Some("Some content\nand more".into())),
+            author: None,
+        };
+        change_post(&conn, post.id, EDITOR, changeset).unwrap();
+
+        let diff = diff_post(&conn, post.id, 0, 1).unwrap();
+        assert_eq!(
+            diff.title.map(|t| (t.old, t.new)),
+            Some(("A title".into(), "A new title".into()))
+        );
+        assert!(diff.content.is_none());
+        assert!(diff.post_state.is_none());
+
+        let diff = diff_post(&conn, post.id, 1, 2).unwrap();
+        assert!(diff.title.is_none());
+        let content = diff.content.unwrap();
+        assert_eq!(content.old.as_deref(), Some("Some content"));
+        assert_eq!(content.new.as_deref(), Some("Some content\nand more"));
+        assert_eq!(
+            content.unified_diff,
+            "--- original\n+++ modified\n@@ -1 +1,2 @@\n-Some content\n\\ No newline at end of file\n+Some content\n+and more\n\\ No newline at end of file\n"
+        );
+
+        let diff = diff_post(&conn, post.id, 2, 2).unwrap();
+        assert!(diff.title.is_none() && diff.content.is_none() && diff.post_state.is_none());
+    }
+
+    /// Parse a point in time given in UTC
+    pub fn timestamp(utc: &str) -> Timestamp {
+        let timestamp = utc.parse::<chrono::DateTime<chrono::Utc>>().unwrap();
+        #[cfg(feature = "sqlite")]
+        let timestamp = timestamp.naive_utc();
+        timestamp
+    }
+
+    /// Insert a post titled "A title" and change its title to "A new title"
+    ///
+    /// The first version is valid from `2019-12-01T10:00:00Z`, the second
+    /// from `2019-12-01T11:00:00Z`. Within the transaction of the tests all
+    /// versions would start at the same point in time otherwise.
+    pub fn post_with_two_versions(conn: &DbConnection) -> i32 {
+        let post = new_post(conn);
+        let changeset = PostChangeset {
+            title: Some("A new title".into()),
+            content: None,
+            author: None,
+        };
+        change_post(conn, post.id, EDITOR, changeset).unwrap();
+        let version =
+            |start| posts::table.filter(posts::id.eq(post.id).and(posts::version_start.eq(start)));
+        let changed_at = timestamp("2019-12-01T11:00:00Z");
+        diesel::update(version(0))
+            .set((
+                posts::version_start_at.eq(timestamp("2019-12-01T10:00:00Z")),
+                posts::version_end_at.eq(changed_at),
+            ))
+            .execute(conn)
+            .unwrap();
+        diesel::update(version(1))
+            .set(posts::version_start_at.eq(changed_at))
+            .execute(conn)
+            .unwrap();
+        post.id
+    }
+
+    #[test]
+    fn as_of_lists_the_versions_valid_at_that_point_in_time() {
+        let conn = match test_connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let id = post_with_two_versions(&conn);
+        let titles_as_of = |as_of| {
+            let query = Query {
+                id: Some(id),
+                as_of: Some(timestamp(as_of)),
+                ..Query::default()
+            };
+            build_post_query(query)
+                .load::<Post>(&conn)
+                .unwrap()
+                .into_iter()
+                .map(|post| post.title)
+                .collect::<Vec<_>>()
+        };
+        assert!(titles_as_of("2019-12-01T09:59:59Z").is_empty());
+        assert_eq!(titles_as_of("2019-12-01T10:00:00Z"), ["A title"]);
+        assert_eq!(titles_as_of("2019-12-01T10:59:59.999999Z"), ["A title"]);
+        // A version ends where the next one starts
+        assert_eq!(titles_as_of("2019-12-01T11:00:00Z"), ["A new title"]);
+        assert_eq!(titles_as_of("2020-01-01T00:00:00Z"), ["A new title"]);
+    }
+
+    #[test]
+    fn transition_targets() {
+        use self::PostState::*;
+        use self::PostTransition::*;
+
+        let expected = [
+            (Publish, Draft, Some(Published)),
+            (Publish, Published, None),
+            (Publish, Deleted, None),
+            (Unpublish, Draft, None),
+            (Unpublish, Published, Some(Draft)),
+            (Unpublish, Deleted, None),
+            (Delete, Draft, Some(Deleted)),
+            (Delete, Published, Some(Deleted)),
+            (Delete, Deleted, None),
+            (Restore, Draft, None),
+            (Restore, Published, None),
+            (Restore, Deleted, Some(Draft)),
+        ];
+        for &(transition, state, target) in &expected {
+            assert_eq!(
+                transition.target(state),
+                target,
+                "{:?} in {:?}",
+                transition,
+                state
+            );
+        }
+    }
+
+    #[test]
+    fn allowed_transitions_insert_a_new_version() {
+        let conn = match test_connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let post = new_post(&conn);
+
+        let steps = [
+            (PostTransition::Publish, PostState::Published),
+            (PostTransition::Unpublish, PostState::Draft),
+            (PostTransition::Delete, PostState::Deleted),
+            (PostTransition::Restore, PostState::Draft),
+        ];
+        for (version, &(transition, state)) in steps.iter().enumerate() {
+            let post = transition_post(&conn, post.id, EDITOR, transition).unwrap();
+            assert_eq!(post.post_state, state);
+            assert_eq!(post.version_start, version as i32 + 1);
+        }
+
+        let versions = posts::table
+            .filter(posts::id.eq(post.id))
+            .count()
+            .get_result::<i64>(&conn)
+            .unwrap();
+        assert_eq!(versions, steps.len() as i64 + 1);
+    }
+
+    #[test]
+    fn rejected_transitions_keep_the_post() {
+        let conn = match test_connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let post = new_post(&conn);
+
+        match transition_post(&conn, post.id, EDITOR, PostTransition::Restore) {
+            Err(TransitionError::NotAllowed(PostTransition::Restore, PostState::Draft)) => {}
+            other => panic!("Expected the restore to be rejected, got {:?}", other),
+        }
+        let current = posts::table
+            .filter(posts::id.eq(post.id))
+            .filter(posts::version_end.is_null())
+            .first::<Post>(&conn)
+            .unwrap();
+        assert_eq!(current.version_start, 0);
+        assert_eq!(current.post_state, PostState::Draft);
+    }
+
+    #[test]
+    fn rejected_transitions_are_conflicts() {
+        let e = ApiError::from(TransitionError::NotAllowed(
+            PostTransition::Unpublish,
+            PostState::Deleted,
+        ));
+        assert_eq!(e.code(), "INVALID_STATE_TRANSITION");
+        assert_eq!(
+            actix_web::ResponseError::error_response(&e).status(),
+            StatusCode::CONFLICT
+        );
+    }
+}
@@ -1,13 +1,29 @@
 use super::comments::Comment;
 use super::posts::Post;
-use crate::schema::{comments, posts, users};
+use crate::authorization::{Admin, Authorized};
+use crate::db::{insert_and_load, DbConnection, Timestamp};
+use crate::error::ApiError;
+use crate::openapi::OpenApi;
+use crate::pagination::{CursorPage, CursorParams, Paginate};
+use crate::schema::{comments, posts, user_roles, users};
+use crate::validation::{Validate, Validator, USER_NAME};
+use crate::versioning::Versioned;
 use crate::AppState;
+use actix_web::http::StatusCode;
 use actix_web::web::{self, HttpRequest, Json};
-use chrono::{DateTime, Utc};
+use diesel::backend::Backend;
+use diesel::deserialize::{self, FromSql};
+use diesel::dsl::exists;
 use diesel::prelude::*;
-use failure::Error;
-use juniper::GraphQLInputObject;
+use diesel::serialize::{self, ToSql};
+use diesel::sql_types::Text;
+use juniper::{GraphQLEnum, GraphQLInputObject};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use wundergraph::query_builder::types::WundergraphValue;
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(
@@ -16,6 +32,8 @@ pub fn config(cfg: &mut web::ServiceConfig) {
             .route(web::post().to(new_user)),
     );
 
+    cfg.service(web::resource("/users/page").route(web::get().to(cursor_paginated_users)));
+
     cfg.service(
         web::resource("/users/{id}")
             .route(web::get().to(get_user_with_id))
@@ -25,87 +43,474 @@ pub fn config(cfg: &mut web::ServiceConfig) {
 
     cfg.service(web::resource("/users/{id}/posts").route(web::get().to(get_posts_for_user)));
     cfg.service(web::resource("/users/{id}/comments").route(web::get().to(get_comments_for_user)));
+
+    cfg.service(
+        web::resource("/users/{id}/roles/{role}")
+            .route(web::put().to(grant_role_to_user))
+            .route(web::delete().to(revoke_role_from_user)),
+    );
 }
 
-#[derive(Serialize, Deserialize, Queryable, Debug)]
+/// Describe the routes registered by `config`
+pub fn openapi(api: &mut OpenApi) {
+    api.operation("GET", "/users", "List all users")
+        .responds_with::<Vec<UserWithRoles>>("The users");
+    api.operation("POST", "/users", "Create a user")
+        .authenticated()
+        .body::<NewUser>()
+        .responds_with::<UserWithRoles>("The new user");
+    api.operation("GET", "/users/page", "Page through users by cursor")
+        .query::<CursorParams>()
+        .responds_with::<CursorPage<UserWithRoles>>("A page of users");
+    api.operation("GET", "/users/{id}", "Load a user")
+        .path_param::<i32>("id")
+        .responds_with::<UserWithRoles>("The user");
+    api.operation("PATCH", "/users/{id}", "Change a user")
+        .authenticated()
+        .path_param::<i32>("id")
+        .body::<UserChangeset>()
+        .responds_with::<UserWithRoles>("The changed user");
+    api.operation(
+        "DELETE",
+        "/users/{id}",
+        "Delete a user without posts or comments",
+    )
+    .authenticated()
+    .path_param::<i32>("id")
+    .responds_empty("The user is deleted");
+    api.operation("GET", "/users/{id}/posts", "List the posts of a user")
+        .path_param::<i32>("id")
+        .responds_with::<Vec<Post>>("The posts");
+    api.operation("GET", "/users/{id}/comments", "List the comments of a user")
+        .path_param::<i32>("id")
+        .responds_with::<Vec<Comment>>("The comments");
+    api.operation(
+        "PUT",
+        "/users/{id}/roles/{role}",
+        "Grant a role, requires the Admin role",
+    )
+    .authenticated()
+    .path_param::<i32>("id")
+    .path_param::<Role>("role")
+    .responds_with::<Vec<Role>>("All roles of the user");
+    api.operation(
+        "DELETE",
+        "/users/{id}/roles/{role}",
+        "Revoke a role, requires the Admin role",
+    )
+    .authenticated()
+    .path_param::<i32>("id")
+    .path_param::<Role>("role")
+    .responds_with::<Vec<Role>>("All roles of the user");
+}
+
+#[derive(Serialize, Deserialize, Queryable, Debug, JsonSchema)]
 pub struct User {
     id: i32,
     name: String,
-    joined_at: DateTime<Utc>,
+    joined_at: Timestamp,
+    version_start: i32,
+    version_end: Option<i32>,
+    version_start_at: Timestamp,
+    version_end_at: Option<Timestamp>,
+}
+
+/// A user together with its roles
+#[derive(Serialize, Debug, JsonSchema)]
+pub struct UserWithRoles {
+    #[serde(flatten)]
+    user: User,
+    roles: Vec<Role>,
+}
+
+#[derive(Debug, Clone, Copy, SqlType, QueryId)]
+#[allow(non_camel_case_types)]
+#[postgres(type_name = "user_role")]
+#[sqlite_type = "Text"]
+pub struct User_role;
+
+/// Roles granted to a user
+///
+/// New users are readers, the other roles are granted by admins.
+/// Admins implicitly have all other roles.
+#[derive(
+    Debug,
+    FromSqlRow,
+    AsExpression,
+    Deserialize,
+    Serialize,
+    GraphQLEnum,
+    WundergraphValue,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    JsonSchema,
+)]
+#[sql_type = "User_role"]
+pub enum Role {
+    /// May write comments
+    Reader,
+    /// May write posts
+    Author,
+    /// May delete comments of other users
+    Moderator,
+    /// May change everything and manage roles
+    Admin,
+}
+
+impl<DB> FromSql<User_role, DB> for Role
+where
+    DB: Backend,
+    String: FromSql<Text, DB>,
+{
+    fn from_sql(bytes: Option<&DB::RawValue>) -> deserialize::Result<Self> {
+        match String::from_sql(bytes)?.as_str() {
+            "Reader" => Ok(Role::Reader),
+            "Author" => Ok(Role::Author),
+            "Moderator" => Ok(Role::Moderator),
+            "Admin" => Ok(Role::Admin),
+            _ => Err("Unrecognized enum variant".into()),
+        }
+    }
+}
+
+impl<DB: Backend> ToSql<User_role, DB> for Role {
+    fn to_sql<W: Write>(&self, out: &mut serialize::Output<W, DB>) -> serialize::Result {
+        match self {
+            Role::Reader => out.write_all(b"Reader")?,
+            Role::Author => out.write_all(b"Author")?,
+            Role::Moderator => out.write_all(b"Moderator")?,
+            Role::Admin => out.write_all(b"Admin")?,
+        }
+        Ok(serialize::IsNull::No)
+    }
+}
+
+/// The roles of a user, ordered by their privileges
+pub fn roles_of(conn: &DbConnection, user: i32) -> QueryResult<Vec<Role>> {
+    let mut roles = user_roles::table
+        .select(user_roles::role)
+        .filter(user_roles::user_id.eq(user))
+        .load::<Role>(conn)?;
+    roles.sort();
+    Ok(roles)
+}
+
+/// Checks whether a user has the given role, admins have all roles
+pub fn has_role(conn: &DbConnection, user: i32, role: Role) -> QueryResult<bool> {
+    diesel::select(exists(
+        user_roles::table
+            .filter(user_roles::user_id.eq(user))
+            .filter(user_roles::role.eq_any(vec![role, Role::Admin])),
+    ))
+    .get_result(conn)
+}
+
+/// Grant a role to an existing user and return all roles of the user
+pub fn grant_role(conn: &DbConnection, user: i32, role: Role) -> QueryResult<Vec<Role>> {
+    conn.transaction(|| {
+        users::table
+            .select(users::id)
+            .filter(users::id.eq(user))
+            .filter(users::version_end.is_null())
+            .first::<i32>(conn)?;
+        let granted = diesel::select(exists(
+            user_roles::table
+                .filter(user_roles::user_id.eq(user))
+                .filter(user_roles::role.eq(role)),
+        ))
+        .get_result::<bool>(conn)?;
+        if !granted {
+            diesel::insert_into(user_roles::table)
+                .values((user_roles::user_id.eq(user), user_roles::role.eq(role)))
+                .execute(conn)?;
+        }
+        roles_of(conn, user)
+    })
+}
+
+#[derive(Debug)]
+pub enum RevokeRoleError {
+    /// Nobody could manage roles without the last `Admin`
+    LastAdmin,
+    Database(diesel::result::Error),
+}
+
+impl fmt::Display for RevokeRoleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RevokeRoleError::LastAdmin => write!(f, "The last admin could not be revoked"),
+            RevokeRoleError::Database(e) => write!(f, "{}", e),
+        }
+    }
 }
 
-#[derive(Deserialize, Debug, Insertable, GraphQLInputObject)]
+impl From<diesel::result::Error> for RevokeRoleError {
+    fn from(e: diesel::result::Error) -> Self {
+        RevokeRoleError::Database(e)
+    }
+}
+
+impl From<RevokeRoleError> for ApiError {
+    fn from(e: RevokeRoleError) -> Self {
+        match e {
+            RevokeRoleError::LastAdmin => {
+                ApiError::new(StatusCode::CONFLICT, "LAST_ADMIN", e.to_string())
+            }
+            RevokeRoleError::Database(e) => e.into(),
+        }
+    }
+}
+
+/// Revoke a role from a user and return the remaining roles of the user
+///
+/// The `Admin` role of the only admin is kept, with postgres the
+/// admins are locked so concurrent revocations could not remove all.
+pub fn revoke_role(
+    conn: &DbConnection,
+    user: i32,
+    role: Role,
+) -> Result<Vec<Role>, RevokeRoleError> {
+    conn.transaction(|| {
+        users::table
+            .select(users::id)
+            .filter(users::id.eq(user))
+            .filter(users::version_end.is_null())
+            .first::<i32>(conn)?;
+        if role == Role::Admin {
+            let admins = user_roles::table
+                .select(user_roles::user_id)
+                .filter(user_roles::role.eq(Role::Admin));
+            #[cfg(feature = "postgres")]
+            let admins = admins.for_update();
+            if admins.load::<i32>(conn)? == [user] {
+                return Err(RevokeRoleError::LastAdmin);
+            }
+        }
+        diesel::delete(
+            user_roles::table
+                .filter(user_roles::user_id.eq(user))
+                .filter(user_roles::role.eq(role)),
+        )
+        .execute(conn)?;
+        Ok(roles_of(conn, user)?)
+    })
+}
+
+/// Load the roles of the given users
+fn with_roles(conn: &DbConnection, users: Vec<User>) -> QueryResult<Vec<UserWithRoles>> {
+    let mut roles = HashMap::<i32, Vec<Role>>::new();
+    for (user, role) in user_roles::table
+        .select((user_roles::user_id, user_roles::role))
+        .filter(user_roles::user_id.eq_any(users.iter().map(|u| u.id).collect::<Vec<_>>()))
+        .load::<(i32, Role)>(conn)?
+    {
+        roles.entry(user).or_default().push(role);
+    }
+    Ok(users
+        .into_iter()
+        .map(|user| {
+            let mut roles = roles.get(&user.id).cloned().unwrap_or_default();
+            roles.sort();
+            UserWithRoles { user, roles }
+        })
+        .collect())
+}
+
+#[derive(Deserialize, Debug, Insertable, GraphQLInputObject, JsonSchema)]
 #[table_name = "users"]
 pub struct NewUser {
     name: String,
 }
 
-#[derive(Deserialize, Debug, AsChangeset, GraphQLInputObject)]
-#[table_name = "users"]
+#[derive(Deserialize, Debug, GraphQLInputObject, JsonSchema)]
 pub struct UserChangeset {
     name: Option<String>,
 }
 
-fn all_users(req: HttpRequest) -> Result<Json<Vec<User>>, Error> {
+impl Validate for NewUser {
+    fn rules(&self, v: &mut Validator<'_>) -> QueryResult<()> {
+        v.text("name", Some(&self.name), &USER_NAME);
+        Ok(())
+    }
+}
+
+impl Validate for UserChangeset {
+    fn rules(&self, v: &mut Validator<'_>) -> QueryResult<()> {
+        v.text("name", self.name.as_deref(), &USER_NAME);
+        Ok(())
+    }
+}
+
+/// The current versions of all users
+fn current_users(conn: &DbConnection) -> QueryResult<Vec<User>> {
+    users::table.filter(users::version_end.is_null()).load(conn)
+}
+
+/// Change an user by inserting a new version
+fn change_user(conn: &DbConnection, id: i32, changeset: UserChangeset) -> QueryResult<User> {
+    conn.transaction(|| {
+        let current = users::table
+            .filter(users::id.eq(id))
+            .filter(users::version_end.is_null())
+            .first::<User>(conn)?;
+        let next_version = users::table.close_current_version(conn, id)?;
+
+        insert_and_load(
+            conn,
+            users::table,
+            (
+                users::id.eq(id),
+                users::name.eq(changeset.name.unwrap_or(current.name)),
+                users::joined_at.eq(current.joined_at),
+                users::version_start.eq(next_version),
+            ),
+        )
+    })
+}
+
+#[derive(Debug)]
+pub enum RemoveUserError {
+    /// The user is the author of current posts or comments
+    HasContent,
+    Database(diesel::result::Error),
+}
+
+impl fmt::Display for RemoveUserError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RemoveUserError::HasContent => {
+                write!(f, "The user is still the author of posts or comments")
+            }
+            RemoveUserError::Database(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<diesel::result::Error> for RemoveUserError {
+    fn from(e: diesel::result::Error) -> Self {
+        RemoveUserError::Database(e)
+    }
+}
+
+impl From<RemoveUserError> for ApiError {
+    fn from(e: RemoveUserError) -> Self {
+        match e {
+            RemoveUserError::HasContent => {
+                ApiError::new(StatusCode::CONFLICT, "USER_HAS_CONTENT", e.to_string())
+            }
+            RemoveUserError::Database(e) => e.into(),
+        }
+    }
+}
+
+/// Remove a user by closing its current version, older versions are kept
+///
+/// Users still authoring the current version of a post or comment are
+/// not removed, postgres checks this again when the transaction commits.
+/// The roles of removed users are revoked.
+pub fn remove_user(conn: &DbConnection, id: i32) -> Result<(), RemoveUserError> {
+    conn.transaction(|| {
+        let has_posts = diesel::select(exists(
+            posts::table
+                .filter(posts::author.eq(id))
+                .filter(posts::version_end.is_null()),
+        ))
+        .get_result::<bool>(conn)?;
+        let has_comments = diesel::select(exists(
+            comments::table
+                .filter(comments::author.eq(id))
+                .filter(comments::version_end.is_null()),
+        ))
+        .get_result::<bool>(conn)?;
+        if has_posts || has_comments {
+            return Err(RemoveUserError::HasContent);
+        }
+        users::table.close_current_version(conn, id)?;
+        diesel::delete(user_roles::table.filter(user_roles::user_id.eq(id))).execute(conn)?;
+        Ok(())
+    })
+}
+
+fn all_users(req: HttpRequest) -> Result<Json<Vec<UserWithRoles>>, ApiError> {
     let conn = req
         .app_data::<AppState>()
         .expect("AppData set")
         .pool
         .get()?;
-    Ok(users::table.load(&conn).map(Json)?)
+    let users = current_users(&conn)?;
+    Ok(with_roles(&conn, users).map(Json)?)
 }
 
-fn new_user(req: HttpRequest, new_user: Json<NewUser>) -> Result<Json<User>, Error> {
+fn new_user(
+    req: HttpRequest,
+    _authorized: Authorized<users::table>,
+    new_user: Json<NewUser>,
+) -> Result<Json<UserWithRoles>, ApiError> {
     let conn = req
         .app_data::<AppState>()
         .expect("AppData set")
         .pool
         .get()?;
-    Ok(diesel::insert_into(users::table)
-        .values(new_user.0)
-        .get_result(&conn)
-        .map(Json)?)
+    new_user.validate(&conn)?;
+    let user: User = insert_and_load(&conn, users::table, new_user.0)?;
+    let roles = roles_of(&conn, user.id)?;
+    Ok(Json(UserWithRoles { user, roles }))
 }
 
-fn get_user_with_id(req: HttpRequest, id: web::Path<i32>) -> Result<Json<User>, Error> {
+fn get_user_with_id(req: HttpRequest, id: web::Path<i32>) -> Result<Json<UserWithRoles>, ApiError> {
     let conn = req
         .app_data::<AppState>()
         .expect("AppData set")
         .pool
         .get()?;
 
-    Ok(users::table.find(id.into_inner()).first(&conn).map(Json)?)
+    let user: User = users::table
+        .filter(users::id.eq(id.into_inner()))
+        .filter(users::version_end.is_null())
+        .first(&conn)?;
+    let roles = roles_of(&conn, user.id)?;
+    Ok(Json(UserWithRoles { user, roles }))
 }
 
 fn update_user(
     req: HttpRequest,
+    _authorized: Authorized<users::table>,
     id: web::Path<i32>,
     changeset: Json<UserChangeset>,
-) -> Result<Json<User>, Error> {
+) -> Result<Json<UserWithRoles>, ApiError> {
     let conn = req
         .app_data::<AppState>()
         .expect("AppData set")
         .pool
         .get()?;
 
-    Ok(diesel::update(users::table.find(id.into_inner()))
-        .set(changeset.0)
-        .get_result(&conn)
-        .map(Json)?)
+    changeset.validate(&conn)?;
+    let id = id.into_inner();
+    let user = change_user(&conn, id, changeset.into_inner())?;
+    let roles = roles_of(&conn, id)?;
+    Ok(Json(UserWithRoles { user, roles }))
 }
 
-fn delete_user(req: HttpRequest, id: web::Path<i32>) -> Result<(), Error> {
+fn delete_user(
+    req: HttpRequest,
+    _authorized: Authorized<users::table>,
+    id: web::Path<i32>,
+) -> Result<(), ApiError> {
     let conn = req
         .app_data::<AppState>()
         .expect("AppData set")
         .pool
         .get()?;
 
-    diesel::delete(users::table.find(id.into_inner())).execute(&conn)?;
+    remove_user(&conn, id.into_inner())?;
     Ok(())
 }
 
-fn get_posts_for_user(req: HttpRequest, id: web::Path<i32>) -> Result<Json<Vec<Post>>, Error> {
+fn get_posts_for_user(req: HttpRequest, id: web::Path<i32>) -> Result<Json<Vec<Post>>, ApiError> {
     let conn = req
         .app_data::<AppState>()
         .expect("AppData set")
@@ -114,6 +519,7 @@ fn get_posts_for_user(req: HttpRequest, id: web::Path<i32>) -> Result<Json<Vec<P
 
     Ok(posts::table
         .filter(posts::author.eq(id.into_inner()))
+        .filter(posts::version_end.is_null())
         .load(&conn)
         .map(Json)?)
 }
@@ -121,7 +527,7 @@ fn get_posts_for_user(req: HttpRequest, id: web::Path<i32>) -> Result<Json<Vec<P
 fn get_comments_for_user(
     req: HttpRequest,
     id: web::Path<i32>,
-) -> Result<Json<Vec<Comment>>, Error> {
+) -> Result<Json<Vec<Comment>>, ApiError> {
     let conn = req
         .app_data::<AppState>()
         .expect("AppData set")
@@ -130,6 +536,222 @@ fn get_comments_for_user(
 
     Ok(comments::table
         .filter(comments::author.eq(id.into_inner()))
+        .filter(comments::version_end.is_null())
         .load(&conn)
         .map(Json)?)
 }
+
+fn cursor_paginated_users(
+    req: HttpRequest,
+    web::Query(cursor): web::Query<CursorParams>,
+) -> Result<Json<CursorPage<UserWithRoles>>, ApiError> {
+    let conn = req
+        .app_data::<AppState>()
+        .expect("AppData set")
+        .pool
+        .get()?;
+
+    // All versions of an user share the same id, so
+    // `version_start` is required to get an unique key
+    let page = users::table
+        .into_boxed()
+        .filter(users::version_end.is_null())
+        .paginate_by_key((users::id, users::version_start))
+        .cursor(&cursor)?
+        .load_page(&conn, |u: &User| (u.id, u.version_start))?;
+    Ok(Json(CursorPage {
+        items: with_roles(&conn, page.items)?,
+        next_cursor: page.next_cursor,
+        prev_cursor: page.prev_cursor,
+    }))
+}
+
+fn grant_role_to_user(
+    req: HttpRequest,
+    _admin: Admin,
+    path: web::Path<(i32, Role)>,
+) -> Result<Json<Vec<Role>>, ApiError> {
+    let conn = req
+        .app_data::<AppState>()
+        .expect("AppData set")
+        .pool
+        .get()?;
+
+    let (id, role) = path.into_inner();
+    Ok(grant_role(&conn, id, role).map(Json)?)
+}
+
+fn revoke_role_from_user(
+    req: HttpRequest,
+    _admin: Admin,
+    path: web::Path<(i32, Role)>,
+) -> Result<Json<Vec<Role>>, ApiError> {
+    let conn = req
+        .app_data::<AppState>()
+        .expect("AppData set")
+        .pool
+        .get()?;
+
+    let (id, role) = path.into_inner();
+    Ok(revoke_role(&conn, id, role).map(Json)?)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::test_connection;
+
+    /// Insert a new user with the given roles, returns its id
+    pub fn insert_user(conn: &DbConnection, roles: &[Role]) -> i32 {
+        let new_user = NewUser {
+            name: "Test user".into(),
+        };
+        let user: User = insert_and_load(conn, users::table, new_user).unwrap();
+        for &role in roles {
+            grant_role(conn, user.id, role).unwrap();
+        }
+        user.id
+    }
+
+    #[test]
+    fn changed_users_are_listed_once() {
+        let conn = match test_connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let new_user = NewUser {
+            name: "Before".into(),
+        };
+        let user: User = insert_and_load(&conn, users::table, new_user).unwrap();
+        let changeset = UserChangeset {
+            name: Some("After".into()),
+        };
+        change_user(&conn, user.id, changeset).unwrap();
+
+        let users = current_users(&conn)
+            .unwrap()
+            .into_iter()
+            .filter(|u| u.id == user.id)
+            .collect::<Vec<_>>();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].name, "After");
+        assert_eq!(users[0].joined_at, user.joined_at);
+        assert_eq!(users[0].version_start, 1);
+    }
+
+    fn insert_comment(conn: &DbConnection, author: i32) -> i32 {
+        diesel::insert_into(comments::table)
+            .values((comments::author.eq(author), comments::post.eq(1)))
+            .execute(conn)
+            .unwrap();
+        comments::table
+            .select(comments::id)
+            .filter(comments::author.eq(author))
+            .get_result(conn)
+            .unwrap()
+    }
+
+    #[test]
+    fn only_users_without_content_are_removed() {
+        let conn = match test_connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let id = insert_user(&conn, &[Role::Author]);
+        let comment = insert_comment(&conn, id);
+        match remove_user(&conn, id) {
+            Err(RemoveUserError::HasContent) => {}
+            r => panic!("Expected HasContent, got {:?}", r),
+        }
+
+        crate::model::comments::remove_comment(&conn, comment).unwrap();
+        remove_user(&conn, id).unwrap();
+        assert!(roles_of(&conn, id).unwrap().is_empty());
+        assert!(current_users(&conn).unwrap().iter().all(|u| u.id != id));
+        let versions = users::table
+            .filter(users::id.eq(id))
+            .count()
+            .get_result::<i64>(&conn)
+            .unwrap();
+        assert_eq!(versions, 1);
+        match remove_user(&conn, id) {
+            Err(RemoveUserError::Database(diesel::result::Error::NotFound)) => {}
+            r => panic!("Expected NotFound, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn the_last_admin_is_kept() {
+        let conn = match test_connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let (first, second) = (
+            insert_user(&conn, &[Role::Admin]),
+            insert_user(&conn, &[Role::Admin, Role::Author]),
+        );
+        // The database could contain other admins
+        let others = user_roles::table
+            .select(user_roles::user_id)
+            .filter(user_roles::role.eq(Role::Admin))
+            .filter(user_roles::user_id.ne_all(vec![first, second]))
+            .load::<i32>(&conn)
+            .unwrap();
+        for other in others {
+            revoke_role(&conn, other, Role::Admin).unwrap();
+        }
+        assert!(!revoke_role(&conn, first, Role::Admin)
+            .unwrap()
+            .contains(&Role::Admin));
+        match revoke_role(&conn, second, Role::Admin) {
+            Err(RevokeRoleError::LastAdmin) => {}
+            r => panic!("Expected LastAdmin, got {:?}", r),
+        }
+        let roles = revoke_role(&conn, second, Role::Author).unwrap();
+        assert!(roles.contains(&Role::Admin) && !roles.contains(&Role::Author));
+    }
+
+    #[test]
+    fn roles_of_unknown_users_are_not_revoked() {
+        let conn = match test_connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        match revoke_role(&conn, -1, Role::Author) {
+            Err(RevokeRoleError::Database(diesel::result::Error::NotFound)) => {}
+            r => panic!("Expected NotFound, got {:?}", r),
+        }
+        let id = insert_user(&conn, &[Role::Author]);
+        remove_user(&conn, id).unwrap();
+        match revoke_role(&conn, id, Role::Author) {
+            Err(RevokeRoleError::Database(diesel::result::Error::NotFound)) => {}
+            r => panic!("Expected NotFound, got {:?}", r),
+        }
+    }
+
+    #[cfg(feature = "postgres")]
+    #[test]
+    fn authors_are_checked_by_the_database() {
+        use diesel::result::{DatabaseErrorKind, Error};
+
+        let conn = match test_connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let id = insert_user(&conn, &[Role::Author]);
+        insert_comment(&conn, id);
+        // Runs the checks deferred to the commit of the transaction
+        let check = || {
+            diesel::sql_query("SET CONSTRAINTS ALL IMMEDIATE").execute(&conn)?;
+            diesel::sql_query("SET CONSTRAINTS ALL DEFERRED").execute(&conn)
+        };
+        check().unwrap();
+        users::table.close_current_version(&conn, id).unwrap();
+        match check() {
+            Err(Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, info)) => {
+                assert_eq!(info.constraint_name(), Some("users_author_fkey"))
+            }
+            r => panic!("Expected a foreign key violation, got {:?}", r),
+        }
+    }
+}
@@ -0,0 +1,184 @@
+//! OpenAPI 3 document of the REST api
+//!
+//! Each model module describes the routes it registers in `config` with
+//! an `openapi` function next to it. Schemas of parameters, bodies and
+//! responses are generated from the serde types used by the handlers,
+//! so renamed or flattened fields are documented as they are sent.
+//!
+//! The document is served at `/openapi.json` and rendered by `/swagger`.
+
+use crate::error::{Problem, PROBLEM_JSON};
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::{InstanceType, Schema, SingleOrVec};
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+
+/// Builder of the OpenAPI document
+pub struct OpenApi {
+    schemas: SchemaGenerator,
+    /// Generates the schemas of parameters, they could not be references
+    parameters: SchemaGenerator,
+    paths: Map<String, Value>,
+}
+
+impl Default for OpenApi {
+    fn default() -> Self {
+        let settings = SchemaSettings::openapi3();
+        OpenApi {
+            parameters: settings
+                .clone()
+                .with(|s| s.inline_subschemas = true)
+                .into_generator(),
+            schemas: settings.into_generator(),
+            paths: Map::new(),
+        }
+    }
+}
+
+impl OpenApi {
+    /// Describe the handler of `method` requests to `path`
+    pub fn operation(&mut self, method: &str, path: &str, summary: &str) -> Operation<'_> {
+        Operation {
+            api: self,
+            method: method.to_lowercase(),
+            path: path.to_owned(),
+            operation: json!({
+                "summary": summary,
+                "tags": [path.split('/').nth(1).unwrap_or_default()],
+                "parameters": [],
+                "responses": {},
+            }),
+        }
+    }
+
+    /// The complete document
+    pub fn document(mut self) -> Value {
+        let problem = self.schemas.subschema_for::<Problem<'static>>();
+        let schemas = self
+            .schemas
+            .take_definitions()
+            .into_iter()
+            .map(|(name, schema)| (name, json!(schema)))
+            .collect::<Map<_, _>>();
+        json!({
+            "openapi": "3.0.3",
+            "info": {
+                "title": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "paths": self.paths,
+            "components": {
+                "schemas": schemas,
+                "responses": {
+                    "Problem": {
+                        "description": "The request failed, see `code` for the reason",
+                        "content": { PROBLEM_JSON: { "schema": problem } },
+                    },
+                },
+                "securitySchemes": {
+                    "bearer": { "type": "http", "scheme": "bearer", "bearerFormat": "JWT" },
+                },
+            },
+        })
+    }
+}
+
+/// A single operation, added to the document by one of the `responds_*` methods
+pub struct Operation<'a> {
+    api: &'a mut OpenApi,
+    method: String,
+    path: String,
+    operation: Value,
+}
+
+impl<'a> Operation<'a> {
+    fn parameter(mut self, name: &str, location: &str, required: bool, schema: Value) -> Self {
+        self.operation["parameters"]
+            .as_array_mut()
+            .expect("Parameters are an array")
+            .push(json!({
+                "name": name,
+                "in": location,
+                "required": required,
+                "schema": schema,
+            }));
+        self
+    }
+
+    /// A `{name}` segment of the path
+    pub fn path_param<T: JsonSchema>(self, name: &str) -> Self {
+        let schema = json!(self.api.parameters.subschema_for::<T>());
+        self.parameter(name, "path", true, schema)
+    }
+
+    /// The query string, each field of `T` is a parameter
+    pub fn query<T: JsonSchema>(self) -> Self {
+        let schema = self.api.parameters.root_schema_for::<T>().schema;
+        let object = match schema.object {
+            Some(object) => object,
+            None => return self,
+        };
+        let required = object.required;
+        object
+            .properties
+            .into_iter()
+            .fold(self, |operation, (name, schema)| {
+                let required = required.contains(&name);
+                operation.parameter(&name, "query", required, json!(non_nullable(schema)))
+            })
+    }
+
+    /// The json request body
+    pub fn body<T: JsonSchema>(mut self) -> Self {
+        let schema = self.api.schemas.subschema_for::<T>();
+        self.operation["requestBody"] = json!({
+            "required": true,
+            "content": { "application/json": { "schema": schema } },
+        });
+        self
+    }
+
+    /// Requires a bearer token
+    pub fn authenticated(mut self) -> Self {
+        self.operation["security"] = json!([{ "bearer": [] }]);
+        self
+    }
+
+    /// Add the operation answering with `T` as json
+    pub fn responds_with<T: JsonSchema>(mut self, description: &str) -> &'a mut OpenApi {
+        let schema = self.api.schemas.subschema_for::<T>();
+        self.operation["responses"]["200"] = json!({
+            "description": description,
+            "content": { "application/json": { "schema": schema } },
+        });
+        self.add()
+    }
+
+    /// Add the operation answering with an empty body
+    pub fn responds_empty(mut self, description: &str) -> &'a mut OpenApi {
+        self.operation["responses"]["200"] = json!({ "description": description });
+        self.add()
+    }
+
+    fn add(mut self) -> &'a mut OpenApi {
+        self.operation["responses"]["default"] =
+            json!({ "$ref": "#/components/responses/Problem" });
+        let path = self.api.paths.entry(self.path).or_insert_with(|| json!({}));
+        path[self.method] = self.operation;
+        self.api
+    }
+}
+
+/// Optional query parameters are simply missing, they could not be `null`
+fn non_nullable(schema: Schema) -> Schema {
+    match schema {
+        Schema::Object(mut schema) => {
+            schema.extensions.remove("nullable");
+            if let Some(SingleOrVec::Vec(ref mut types)) = schema.instance_type {
+                types.retain(|t| *t != InstanceType::Null);
+            }
+            Schema::Object(schema)
+        }
+        schema => schema,
+    }
+}
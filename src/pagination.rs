@@ -1,11 +1,25 @@
-use diesel::pg::Pg;
+use crate::db::{DbBackend, DbConnection};
+use crate::error::ApiError;
 use diesel::prelude::*;
 use diesel::query_builder::*;
 use diesel::query_dsl::methods::LoadQuery;
-use diesel::sql_types::BigInt;
+use diesel::serialize::ToSql;
+use diesel::sql_types::{BigInt, HasSqlType};
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::marker::PhantomData;
 
 pub trait Paginate: Sized {
     fn paginate(self, page: i64) -> Paginated<Self>;
+
+    /// Paginate by the given key columns instead of a page number
+    ///
+    /// This avoids scanning all skipped rows and counting the
+    /// total number of records, so it stays fast for deep pages.
+    fn paginate_by_key<C, K>(self, key_columns: C) -> KeysetPaginated<Self, C, K>
+    where
+        C: KeyColumns;
 }
 
 impl<T> Paginate for T {
@@ -16,10 +30,27 @@ impl<T> Paginate for T {
             page,
         }
     }
+
+    fn paginate_by_key<C, K>(self, _key_columns: C) -> KeysetPaginated<Self, C, K>
+    where
+        C: KeyColumns,
+    {
+        KeysetPaginated {
+            query: self,
+            key_columns: PhantomData,
+            descending: false,
+            cursor: None,
+            per_page: DEFAULT_PER_PAGE,
+        }
+    }
 }
 
 pub const DEFAULT_PER_PAGE: i64 = 10;
 
+/// The maximal number of records of a keyset paginated page or relay
+/// connection, larger requested page sizes are capped to it
+pub const MAX_PAGE_SIZE: i64 = 100;
+
 #[derive(Debug, Clone, Copy, QueryId)]
 pub struct Paginated<T> {
     query: T,
@@ -32,9 +63,9 @@ impl<T> Paginated<T> {
         Paginated { per_page, ..self }
     }
 
-    pub fn load_and_count_pages<U>(self, conn: &PgConnection) -> QueryResult<(Vec<U>, i64)>
+    pub fn load_and_count_pages<U>(self, conn: &DbConnection) -> QueryResult<(Vec<U>, i64)>
     where
-        Self: LoadQuery<PgConnection, (U, i64)>,
+        Self: LoadQuery<DbConnection, (U, i64)>,
     {
         let per_page = self.per_page;
         let results = self.load::<(U, i64)>(conn)?;
@@ -49,13 +80,13 @@ impl<T: Query> Query for Paginated<T> {
     type SqlType = (T::SqlType, BigInt);
 }
 
-impl<T> RunQueryDsl<PgConnection> for Paginated<T> {}
+impl<T> RunQueryDsl<DbConnection> for Paginated<T> {}
 
-impl<T> QueryFragment<Pg> for Paginated<T>
+impl<T> QueryFragment<DbBackend> for Paginated<T>
 where
-    T: QueryFragment<Pg>,
+    T: QueryFragment<DbBackend>,
 {
-    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+    fn walk_ast(&self, mut out: AstPass<DbBackend>) -> QueryResult<()> {
         out.push_sql("SELECT *, COUNT(*) OVER () FROM (");
         self.query.walk_ast(out.reborrow())?;
         out.push_sql(") t LIMIT ");
@@ -66,3 +97,322 @@ where
         Ok(())
     }
 }
+
+/// Query parameters accepted by listings that support cursor based pagination
+#[derive(Deserialize, Debug, Default, JsonSchema)]
+pub struct CursorParams {
+    pub after: Option<String>,
+    pub before: Option<String>,
+    pub page_size: Option<u32>,
+}
+
+/// A page of results loaded via keyset pagination
+///
+/// The cursors are opaque to clients, they should only be
+/// passed back as `after`/`before` parameter
+#[derive(Serialize, Debug, JsonSchema)]
+pub struct CursorPage<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+    pub prev_cursor: Option<String>,
+}
+
+pub fn encode_cursor<K: Serialize>(key: &K) -> String {
+    let json = serde_json::to_vec(key).expect("Cursor keys are always serializable");
+    base64::encode_config(&json, base64::URL_SAFE_NO_PAD)
+}
+
+pub fn decode_cursor<K: DeserializeOwned>(cursor: &str) -> Result<K, ApiError> {
+    let invalid = || ApiError::unprocessable("INVALID_CURSOR", "Invalid cursor");
+    let json = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD).map_err(|_| invalid())?;
+    serde_json::from_slice(&json).map_err(|_| invalid())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CursorDirection {
+    After,
+    Before,
+}
+
+/// A query paginated by the values of a set of key columns
+/// instead of `LIMIT/OFFSET`
+///
+/// `C` is a tuple of key columns, `K` the corresponding rust tuple
+/// stored in the cursor. The key columns together must be unique,
+/// otherwise rows sharing a key could be skipped at page boundaries.
+#[derive(Debug, Clone)]
+pub struct KeysetPaginated<T, C, K> {
+    query: T,
+    key_columns: PhantomData<C>,
+    descending: bool,
+    cursor: Option<(CursorDirection, K)>,
+    per_page: i64,
+}
+
+impl<T, C, K> KeysetPaginated<T, C, K> {
+    pub fn descending(self, descending: bool) -> Self {
+        KeysetPaginated { descending, ..self }
+    }
+
+    /// Start the page at the cursor given in `params`
+    ///
+    /// Also applies the requested page size, capped to `MAX_PAGE_SIZE`
+    pub fn cursor(mut self, params: &CursorParams) -> Result<Self, ApiError>
+    where
+        K: DeserializeOwned,
+    {
+        self.cursor = match (&params.after, &params.before) {
+            (Some(_), Some(_)) => {
+                return Err(ApiError::unprocessable(
+                    "INVALID_CURSOR",
+                    "Only one of `after` and `before` could be set",
+                ))
+            }
+            (Some(after), None) => Some((CursorDirection::After, decode_cursor(after)?)),
+            (None, Some(before)) => Some((CursorDirection::Before, decode_cursor(before)?)),
+            (None, None) => None,
+        };
+        if let Some(page_size) = params.page_size {
+            self.per_page = i64::from(page_size).clamp(1, MAX_PAGE_SIZE);
+        }
+        Ok(self)
+    }
+
+    /// Load a single page
+    ///
+    /// `key` extracts the values of the key columns from a loaded record,
+    /// in the same order as the key columns were given to `paginate_by_key`
+    pub fn load_page<U, F>(mut self, conn: &DbConnection, key: F) -> QueryResult<CursorPage<U>>
+    where
+        Self: LoadQuery<DbConnection, U>,
+        F: Fn(&U) -> K,
+        K: Serialize,
+    {
+        let per_page = self.per_page;
+        let direction = self.cursor.as_ref().map(|(d, _)| *d);
+        let has_cursor = direction.is_some();
+        // Fetch one additional record to know if there is another page
+        self.per_page += 1;
+        let mut items = self.load::<U>(conn)?;
+        let has_more = items.len() as i64 > per_page;
+        items.truncate(per_page as usize);
+
+        let (has_next, has_prev) = if direction == Some(CursorDirection::Before) {
+            items.reverse();
+            (true, has_more)
+        } else {
+            (has_more, has_cursor)
+        };
+
+        let next_cursor = items
+            .last()
+            .filter(|_| has_next)
+            .map(|item| encode_cursor(&key(item)));
+        let prev_cursor = items
+            .first()
+            .filter(|_| has_prev)
+            .map(|item| encode_cursor(&key(item)));
+
+        Ok(CursorPage {
+            items,
+            next_cursor,
+            prev_cursor,
+        })
+    }
+}
+
+impl<T, C, K> QueryId for KeysetPaginated<T, C, K> {
+    type QueryId = ();
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<T: Query, C, K> Query for KeysetPaginated<T, C, K> {
+    type SqlType = T::SqlType;
+}
+
+impl<T, C, K> RunQueryDsl<DbConnection> for KeysetPaginated<T, C, K> {}
+
+impl<T, C, K> QueryFragment<DbBackend> for KeysetPaginated<T, C, K>
+where
+    T: QueryFragment<DbBackend>,
+    C: KeyColumns,
+    K: KeysetKey<C::SqlType>,
+{
+    fn walk_ast(&self, mut out: AstPass<DbBackend>) -> QueryResult<()> {
+        let push_columns = |out: &mut AstPass<DbBackend>, suffix: &str| -> QueryResult<()> {
+            for (idx, column) in C::NAMES.iter().enumerate() {
+                if idx != 0 {
+                    out.push_sql(", ");
+                }
+                out.push_sql("t.");
+                out.push_identifier(column)?;
+                out.push_sql(suffix);
+            }
+            Ok(())
+        };
+        let backwards = self
+            .cursor
+            .as_ref()
+            .map(|(d, _)| *d == CursorDirection::Before)
+            .unwrap_or(false);
+        // Walking backwards means we need to flip the order and
+        // reverse the loaded records afterwards
+        let descending = self.descending != backwards;
+
+        out.push_sql("SELECT * FROM (");
+        self.query.walk_ast(out.reborrow())?;
+        out.push_sql(") t");
+        if let Some((_, ref key)) = self.cursor {
+            out.push_sql(" WHERE (");
+            push_columns(&mut out, "")?;
+            out.push_sql(if descending { ") < (" } else { ") > (" });
+            key.walk_binds(out.reborrow())?;
+            out.push_sql(")");
+        }
+        out.push_sql(" ORDER BY ");
+        push_columns(&mut out, if descending { " DESC" } else { " ASC" })?;
+        out.push_sql(" LIMIT ");
+        out.push_bind_param::<BigInt, _>(&self.per_page)?;
+        Ok(())
+    }
+}
+
+/// A tuple of columns used as key for keyset pagination
+pub trait KeyColumns {
+    type SqlType;
+    const NAMES: &'static [&'static str];
+}
+
+/// A tuple of key values that could be bound as sql
+/// type `ST`, one bind per key column
+pub trait KeysetKey<ST> {
+    fn walk_binds(&self, out: AstPass<DbBackend>) -> QueryResult<()>;
+}
+
+macro_rules! impl_keyset_key {
+    ($($T: ident: $ST: ident: $idx: tt),+) => {
+        impl<$($T,)+> KeyColumns for ($($T,)+)
+        where
+            $($T: Column,)+
+        {
+            type SqlType = ($(<$T as Expression>::SqlType,)+);
+            const NAMES: &'static [&'static str] = &[$($T::NAME,)+];
+        }
+
+        impl<$($T, $ST,)+> KeysetKey<($($ST,)+)> for ($($T,)+)
+        where
+            $(
+                $T: ToSql<$ST, DbBackend>,
+                DbBackend: HasSqlType<$ST>,
+            )+
+        {
+            fn walk_binds(&self, mut out: AstPass<DbBackend>) -> QueryResult<()> {
+                $(
+                    if $idx != 0 {
+                        out.push_sql(", ");
+                    }
+                    out.push_bind_param::<$ST, _>(&self.$idx)?;
+                )+
+                Ok(())
+            }
+        }
+    };
+}
+
+impl_keyset_key!(A: SA: 0);
+impl_keyset_key!(A: SA: 0, B: SB: 1);
+impl_keyset_key!(A: SA: 0, B: SB: 1, C: SC: 2);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_connection;
+    use crate::model::users::tests::insert_user;
+    use crate::schema::users;
+
+    fn params(after: Option<String>, before: Option<String>, page_size: u32) -> CursorParams {
+        CursorParams {
+            after,
+            before,
+            page_size: Some(page_size),
+        }
+    }
+
+    #[test]
+    fn cursors_roundtrip() {
+        let key = (String::from("A title"), 42, 7);
+        let cursor = encode_cursor(&key);
+        assert_eq!(decode_cursor::<(String, i32, i32)>(&cursor).unwrap(), key);
+    }
+
+    #[test]
+    fn invalid_cursors_are_rejected() {
+        let not_base64 = decode_cursor::<(i32,)>("not a cursor!").unwrap_err();
+        assert_eq!(not_base64.code(), "INVALID_CURSOR");
+        let wrong_key = decode_cursor::<(i32,)>(&encode_cursor(&("text",))).unwrap_err();
+        assert_eq!(wrong_key.code(), "INVALID_CURSOR");
+        let both = users::table
+            .select(users::id)
+            .paginate_by_key::<_, (i32,)>((users::id,))
+            .cursor(&params(
+                Some(encode_cursor(&(1,))),
+                Some(encode_cursor(&(2,))),
+                2,
+            ));
+        assert_eq!(both.unwrap_err().code(), "INVALID_CURSOR");
+    }
+
+    #[test]
+    fn page_sizes_are_capped() {
+        let page = |page_size| {
+            users::table
+                .select(users::id)
+                .paginate_by_key::<_, (i32,)>((users::id,))
+                .cursor(&params(None, None, page_size))
+                .unwrap()
+                .per_page
+        };
+        assert_eq!(page(20), 20);
+        assert_eq!(page(u32::MAX), MAX_PAGE_SIZE);
+        assert_eq!(page(0), 1);
+    }
+
+    #[test]
+    fn pages_are_walked_in_both_directions() {
+        let conn = match test_connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let ids = (0..5).map(|_| insert_user(&conn, &[])).collect::<Vec<_>>();
+        let load = |params: CursorParams| {
+            users::table
+                .filter(users::id.eq_any(&ids))
+                .filter(users::version_end.is_null())
+                .select(users::id)
+                .paginate_by_key((users::id,))
+                .cursor(&params)
+                .unwrap()
+                .load_page(&conn, |id: &i32| (*id,))
+                .unwrap()
+        };
+
+        let first = load(params(None, None, 2));
+        assert_eq!(first.items, &ids[..2]);
+        assert_eq!(first.prev_cursor, None);
+        let second = load(params(first.next_cursor.clone(), None, 2));
+        assert_eq!(second.items, &ids[2..4]);
+        let last = load(params(second.next_cursor.clone(), None, 2));
+        assert_eq!(last.items, &ids[4..]);
+        assert_eq!(last.next_cursor, None);
+
+        // Walking backwards loads the records in reverse
+        // order, the page itself keeps the ascending order
+        let back = load(params(None, last.prev_cursor.clone(), 2));
+        assert_eq!(back.items, second.items);
+        assert_eq!(back.next_cursor, second.next_cursor);
+        let front = load(params(None, back.prev_cursor.clone(), 2));
+        assert_eq!(front.items, first.items);
+        assert_eq!(front.prev_cursor, None);
+        assert!(front.next_cursor.is_some());
+    }
+}
@@ -0,0 +1,294 @@
+//! Persisted graphql queries
+//!
+//! Instead of the query text clients could send the hex encoded sha256
+//! hash of a query in `extensions.persistedQuery.sha256Hash`, following
+//! the automatic persisted queries protocol of apollo. Known queries are
+//! loaded from the `.graphql` files of a directory, each file contains a
+//! single document and is known by the hash of its content.
+//!
+//! In allow-list mode only known queries are executed, regardless of
+//! whether they are sent as text or as hash. Otherwise a request sending
+//! both the query and its hash registers the query for later requests.
+//! The registered queries are kept apart from the loaded ones, only a
+//! limited number of them is kept, the least recently used are dropped.
+
+use juniper::{FieldError, IntoFieldError, Object, Value};
+use lru::LruCache;
+use ring::digest::{digest, SHA256};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use wundergraph::scalar::WundergraphScalarValue;
+
+/// The `persistedQuery` entry of the `extensions` of a graphql request
+#[derive(Debug, Deserialize)]
+pub struct PersistedQuery {
+    pub version: i32,
+    #[serde(rename = "sha256Hash")]
+    pub sha256_hash: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PersistedQueryError {
+    /// Neither a query nor a hash was sent
+    MissingQuery,
+    /// A protocol version other than 1 was requested
+    UnsupportedVersion(i32),
+    /// The hash is unknown, clients retry with the full query
+    NotFound,
+    /// The hash sent does not match the query sent
+    HashMismatch,
+    /// The query is not allow-listed
+    NotAllowed,
+}
+
+impl PersistedQueryError {
+    /// Stable error code returned as `extensions.code`
+    pub fn code(&self) -> &'static str {
+        match self {
+            PersistedQueryError::MissingQuery => "QUERY_MISSING",
+            PersistedQueryError::UnsupportedVersion(_) => "PERSISTED_QUERY_NOT_SUPPORTED",
+            PersistedQueryError::NotFound => "PERSISTED_QUERY_NOT_FOUND",
+            PersistedQueryError::HashMismatch => "PERSISTED_QUERY_HASH_MISMATCH",
+            PersistedQueryError::NotAllowed => "PERSISTED_QUERY_NOT_ALLOWED",
+        }
+    }
+}
+
+impl fmt::Display for PersistedQueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PersistedQueryError::MissingQuery => write!(f, "No query given"),
+            PersistedQueryError::UnsupportedVersion(v) => {
+                write!(f, "Unsupported persisted query version {}", v)
+            }
+            // Clients of the apollo protocol check for this message
+            PersistedQueryError::NotFound => write!(f, "PersistedQueryNotFound"),
+            PersistedQueryError::HashMismatch => {
+                write!(f, "The sha256Hash does not match the query")
+            }
+            PersistedQueryError::NotAllowed => write!(f, "Only persisted queries are allowed"),
+        }
+    }
+}
+
+impl IntoFieldError<WundergraphScalarValue> for PersistedQueryError {
+    fn into_field_error(self) -> FieldError<WundergraphScalarValue> {
+        let mut extensions = Object::with_capacity(1);
+        extensions.add_field("code", Value::scalar(self.code()));
+        FieldError::new(self, Value::Object(extensions))
+    }
+}
+
+/// The queries known by their hash
+#[derive(Debug)]
+pub struct PersistedQueries {
+    /// The queries loaded from files
+    loaded: HashMap<String, String>,
+    /// The queries registered by clients
+    registered: Mutex<LruCache<String, String>>,
+    allow_list: bool,
+}
+
+impl PersistedQueries {
+    /// Load the `.graphql` files of `dir`, if given, and keep up to
+    /// `capacity` queries registered by clients
+    pub fn load(dir: Option<&Path>, allow_list: bool, capacity: usize) -> io::Result<Self> {
+        let mut loaded = HashMap::new();
+        if let Some(dir) = dir {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.extension().map_or(false, |e| e == "graphql") {
+                    let query = fs::read_to_string(&path)?;
+                    loaded.insert(sha256_hex(&query), query);
+                }
+            }
+        }
+        Ok(PersistedQueries {
+            loaded,
+            registered: Mutex::new(LruCache::new(capacity)),
+            allow_list,
+        })
+    }
+
+    /// The query to execute for a request sending `query` and `persisted`
+    pub fn resolve(
+        &self,
+        query: Option<String>,
+        persisted: Option<&PersistedQuery>,
+    ) -> Result<String, PersistedQueryError> {
+        let hash = match persisted {
+            Some(PersistedQuery {
+                version: 1,
+                sha256_hash,
+            }) => sha256_hash.to_lowercase(),
+            Some(PersistedQuery { version, .. }) => {
+                return Err(PersistedQueryError::UnsupportedVersion(*version));
+            }
+            None => match query {
+                Some(ref query) if self.allow_list => sha256_hex(query),
+                Some(query) => return Ok(query),
+                None => return Err(PersistedQueryError::MissingQuery),
+            },
+        };
+        if let Some(known) = self.loaded.get(&hash) {
+            return Ok(known.clone());
+        }
+        if self.allow_list {
+            return Err(PersistedQueryError::NotAllowed);
+        }
+        let mut registered = self.registered.lock().expect("Lock poisoned");
+        if let Some(known) = registered.get(&hash) {
+            return Ok(known.clone());
+        }
+        match query {
+            None => Err(PersistedQueryError::NotFound),
+            Some(query) if sha256_hex(&query) != hash => Err(PersistedQueryError::HashMismatch),
+            Some(query) => {
+                registered.put(hash, query.clone());
+                Ok(query)
+            }
+        }
+    }
+}
+
+fn sha256_hex(query: &str) -> String {
+    digest(&SHA256, query.as_bytes()).as_ref().iter().fold(
+        String::with_capacity(64),
+        |mut hex, byte| {
+            let _ = write!(hex, "{:02x}", byte);
+            hex
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUERY: &str = "{ users { id } }";
+
+    fn persisted(query: &str) -> PersistedQuery {
+        PersistedQuery {
+            version: 1,
+            sha256_hash: sha256_hex(query),
+        }
+    }
+
+    /// Persisted queries containing `QUERY` as loaded query
+    fn load(allow_list: bool, capacity: usize) -> PersistedQueries {
+        let mut queries = PersistedQueries::load(None, allow_list, capacity).unwrap();
+        queries
+            .loaded
+            .insert(sha256_hex(QUERY), String::from(QUERY));
+        queries
+    }
+
+    #[test]
+    fn known_hashes_resolve_to_their_query() {
+        let queries = load(false, 1);
+        assert_eq!(
+            queries.resolve(None, Some(&persisted(QUERY))),
+            Ok(String::from(QUERY))
+        );
+        // Hashes are hex encoded, whatever the case
+        let upper = PersistedQuery {
+            version: 1,
+            sha256_hash: sha256_hex(QUERY).to_uppercase(),
+        };
+        assert_eq!(queries.resolve(None, Some(&upper)), Ok(String::from(QUERY)));
+    }
+
+    #[test]
+    fn unknown_hashes_are_not_found() {
+        let queries = load(false, 1);
+        let query = "{ posts { id } }";
+        assert_eq!(
+            queries.resolve(None, Some(&persisted(query))),
+            Err(PersistedQueryError::NotFound)
+        );
+        assert_eq!(
+            queries.resolve(None, None),
+            Err(PersistedQueryError::MissingQuery)
+        );
+    }
+
+    #[test]
+    fn queries_not_matching_their_hash_are_rejected() {
+        let queries = load(false, 1);
+        let query = String::from("{ posts { id } }");
+        assert_eq!(
+            queries.resolve(Some(query), Some(&persisted("{ comments { id } }"))),
+            Err(PersistedQueryError::HashMismatch)
+        );
+    }
+
+    #[test]
+    fn other_versions_are_not_supported() {
+        let queries = load(false, 1);
+        let persisted = PersistedQuery {
+            version: 2,
+            sha256_hash: sha256_hex(QUERY),
+        };
+        assert_eq!(
+            queries.resolve(None, Some(&persisted)),
+            Err(PersistedQueryError::UnsupportedVersion(2))
+        );
+    }
+
+    #[test]
+    fn registered_queries_are_bounded() {
+        let queries = load(false, 1);
+        let first = "{ posts { id } }";
+        let second = "{ comments { id } }";
+        for query in &[first, second] {
+            assert_eq!(
+                queries.resolve(Some(String::from(*query)), Some(&persisted(query))),
+                Ok(String::from(*query))
+            );
+        }
+        assert_eq!(
+            queries.resolve(None, Some(&persisted(second))),
+            Ok(String::from(second))
+        );
+        assert_eq!(
+            queries.resolve(None, Some(&persisted(first))),
+            Err(PersistedQueryError::NotFound)
+        );
+        // Loaded queries are never dropped
+        assert_eq!(
+            queries.resolve(None, Some(&persisted(QUERY))),
+            Ok(String::from(QUERY))
+        );
+    }
+
+    #[test]
+    fn allow_lists_execute_loaded_queries_only() {
+        let queries = load(true, 1);
+        assert_eq!(
+            queries.resolve(Some(String::from(QUERY)), None),
+            Ok(String::from(QUERY))
+        );
+        assert_eq!(
+            queries.resolve(None, Some(&persisted(QUERY))),
+            Ok(String::from(QUERY))
+        );
+        let query = "{ posts { id } }";
+        assert_eq!(
+            queries.resolve(Some(String::from(query)), None),
+            Err(PersistedQueryError::NotAllowed)
+        );
+        assert_eq!(
+            queries.resolve(Some(String::from(query)), Some(&persisted(query))),
+            Err(PersistedQueryError::NotAllowed)
+        );
+        assert_eq!(
+            queries.resolve(None, Some(&persisted(query))),
+            Err(PersistedQueryError::NotAllowed)
+        );
+    }
+}
@@ -1,6 +1,8 @@
 table! {
     use diesel::sql_types::*;
+    use crate::db::Timestamptz;
     use crate::model::posts::Post_state;
+    use crate::model::users::User_role;
 
     comments (id) {
         id -> Int4,
@@ -8,33 +10,61 @@ table! {
         published_at -> Timestamptz,
         author -> Int4,
         post -> Int4,
+        version_start -> Int4,
+        version_end -> Nullable<Int4>,
+        version_start_at -> Timestamptz,
+        version_end_at -> Nullable<Timestamptz>,
     }
 }
 
 table! {
     use diesel::sql_types::*;
+    use crate::db::Timestamptz;
     use crate::model::posts::Post_state;
+    use crate::model::users::User_role;
 
     posts (id) {
         id -> Int4,
         title -> Text,
         content -> Nullable<Text>,
-        published_at -> Timestamptz,
+        published_at -> Nullable<Timestamptz>,
         author -> Int4,
         post_state -> Post_state,
         version_start -> Int4,
         version_end -> Nullable<Int4>,
+        version_start_at -> Timestamptz,
+        version_end_at -> Nullable<Timestamptz>,
+        editor -> Nullable<Int4>,
     }
 }
 
 table! {
     use diesel::sql_types::*;
+    use crate::db::Timestamptz;
     use crate::model::posts::Post_state;
+    use crate::model::users::User_role;
 
     users (id) {
         id -> Int4,
         name -> Text,
         joined_at -> Timestamptz,
+        version_start -> Int4,
+        version_end -> Nullable<Int4>,
+        version_start_at -> Timestamptz,
+        version_end_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::db::Timestamptz;
+    use crate::model::posts::Post_state;
+    use crate::model::users::User_role;
+
+    user_roles (id) {
+        id -> Int4,
+        user_id -> Int4,
+        role -> User_role,
     }
 }
 
@@ -44,5 +74,6 @@ joinable!(posts -> users (author));
 allow_tables_to_appear_in_same_query!(
     comments,
     posts,
+    user_roles,
     users,
 );
@@ -0,0 +1,583 @@
+//! GraphQL subscriptions served over a websocket
+//!
+//! Changes are published by database triggers via `NOTIFY`, see the
+//! `change_notifications` migration. A single listener connection
+//! forwards them to all connected websocket sessions, which speak the
+//! `graphql-ws` protocol used by apollo and graphiql.
+//!
+//! Browsers cannot send an `Authorization` header with the upgrade
+//! request, so the bearer token could be sent as `authorization` in the
+//! payload of `connection_init` instead. Operations are only accepted
+//! after `connection_init`, a token sent with it replaces the identity
+//! of the upgrade request and invalid tokens close the connection.
+//!
+//! Subscription documents pass the same persisted query resolution and
+//! query limits as requests to `/graphql`. Each session buffers up to
+//! `EVENT_BUFFER` events, sessions falling further behind are closed.
+
+use crate::auth::Authentication;
+use crate::graphql::subscription::{ChangeEvent, SubscriptionContext, EVENT_CHANNEL};
+use crate::graphql::{Measurement, QueryLimits, RequestMetadata};
+use crate::persisted_queries::PersistedQueries;
+use crate::{AppState, GraphQLData, MeasureSubscriptionSchema, SubscriptionSchema};
+use actix_codec::{Decoder, Encoder};
+use actix_http::ws::{self, CloseCode, Frame, Message};
+use actix_web::web::{self, HttpRequest};
+use actix_web::{Error, HttpResponse};
+use bytes::{Bytes, BytesMut};
+use diesel::pg::PgConnection;
+use diesel::r2d2::{ConnectionManager, Pool};
+use futures::sync::mpsc::{self, Receiver, Sender};
+use futures::{Async, Poll, Stream};
+use juniper::http::{GraphQLRequest, GraphQLResponse};
+use juniper::parser::{Lexer, Token};
+use juniper::{FieldError, IntoFieldError};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use wundergraph::scalar::WundergraphScalarValue;
+
+const PROTOCOL: &str = "graphql-ws";
+
+/// The number of change events buffered for a session
+/// before it is closed for lagging behind
+const EVENT_BUFFER: usize = 64;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/subscriptions").route(web::get().to(subscriptions)));
+}
+
+/// Distributes change events to all active subscription sessions
+#[derive(Default)]
+pub struct EventBroker {
+    subscribers: Mutex<Vec<Sender<ChangeEvent>>>,
+}
+
+impl EventBroker {
+    /// Start listening for change events on a dedicated connection
+    ///
+    /// Lost connections are reestablished, events sent in the meantime
+    /// are not delivered.
+    pub fn start(database_url: String) -> Arc<Self> {
+        let broker = Arc::new(Self::default());
+        let listener = broker.clone();
+        std::thread::Builder::new()
+            .name(String::from("change-listener"))
+            .spawn(move || loop {
+                if let Err(e) = listener.listen(&database_url) {
+                    log::error!("Listening for change events failed: {}", e);
+                }
+                std::thread::sleep(Duration::from_secs(5));
+            })
+            .expect("Failed to start change listener");
+        broker
+    }
+
+    pub fn subscribe(&self) -> Receiver<ChangeEvent> {
+        let (sender, receiver) = mpsc::channel(EVENT_BUFFER);
+        self.subscribers
+            .lock()
+            .expect("Subscriber list is poisoned")
+            .push(sender);
+        receiver
+    }
+
+    fn publish(&self, event: ChangeEvent) {
+        let mut subscribers = self
+            .subscribers
+            .lock()
+            .expect("Subscriber list is poisoned");
+        // Closed channels belong to sessions that are already gone, dropping
+        // the sender of a full one ends the event stream of its session
+        *subscribers = std::mem::take(&mut *subscribers)
+            .into_iter()
+            .filter_map(|mut s| match s.try_send(event) {
+                Ok(()) => Some(s),
+                Err(e) => {
+                    if e.is_full() {
+                        log::warn!("Closing a subscription session lagging behind");
+                    }
+                    None
+                }
+            })
+            .collect();
+    }
+
+    /// Diesel does not expose notifications, so we use libpq directly here
+    fn listen(&self, database_url: &str) -> Result<(), failure::Error> {
+        let conn = ListenConnection::connect(database_url)?;
+        conn.execute(&format!("LISTEN {}", EVENT_CHANNEL))?;
+        loop {
+            conn.wait_for_input()?;
+            conn.consume_input()?;
+            while let Some(payload) = conn.next_notification() {
+                match serde_json::from_str(&payload) {
+                    Ok(event) => self.publish(event),
+                    Err(e) => log::error!("Invalid change event {}: {}", payload, e),
+                }
+            }
+        }
+    }
+}
+
+struct ListenConnection(*mut pq_sys::PGconn);
+
+impl ListenConnection {
+    fn connect(database_url: &str) -> Result<Self, failure::Error> {
+        let database_url = CString::new(database_url)?;
+        let conn = ListenConnection(unsafe { pq_sys::PQconnectdb(database_url.as_ptr()) });
+        if conn.0.is_null() {
+            return Err(failure::err_msg("Failed to allocate a connection"));
+        }
+        if unsafe { pq_sys::PQstatus(conn.0) } != pq_sys::CONNECTION_OK {
+            return Err(conn.last_error());
+        }
+        Ok(conn)
+    }
+
+    fn execute(&self, query: &str) -> Result<(), failure::Error> {
+        let query = CString::new(query)?;
+        unsafe {
+            let res = pq_sys::PQexec(self.0, query.as_ptr());
+            let ok = !res.is_null() && pq_sys::PQresultStatus(res) == pq_sys::PGRES_COMMAND_OK;
+            pq_sys::PQclear(res);
+            if ok {
+                Ok(())
+            } else {
+                Err(self.last_error())
+            }
+        }
+    }
+
+    fn wait_for_input(&self) -> Result<(), failure::Error> {
+        let mut fd = libc::pollfd {
+            fd: unsafe { pq_sys::PQsocket(self.0) },
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        loop {
+            if unsafe { libc::poll(&mut fd, 1, -1) } >= 0 {
+                return Ok(());
+            }
+            let err = std::io::Error::last_os_error();
+            if err.kind() != std::io::ErrorKind::Interrupted {
+                return Err(err.into());
+            }
+        }
+    }
+
+    fn consume_input(&self) -> Result<(), failure::Error> {
+        if unsafe { pq_sys::PQconsumeInput(self.0) } == 1 {
+            Ok(())
+        } else {
+            Err(self.last_error())
+        }
+    }
+
+    fn next_notification(&self) -> Option<String> {
+        unsafe {
+            let notify = pq_sys::PQnotifies(self.0);
+            if notify.is_null() {
+                return None;
+            }
+            let payload = CStr::from_ptr((*notify).extra)
+                .to_string_lossy()
+                .into_owned();
+            pq_sys::PQfreemem(notify as *mut libc::c_void);
+            Some(payload)
+        }
+    }
+
+    fn last_error(&self) -> failure::Error {
+        let msg = unsafe { CStr::from_ptr(pq_sys::PQerrorMessage(self.0)) };
+        failure::err_msg(msg.to_string_lossy().trim().to_owned())
+    }
+}
+
+impl Drop for ListenConnection {
+    fn drop(&mut self) {
+        unsafe { pq_sys::PQfinish(self.0) }
+    }
+}
+
+fn subscriptions(
+    req: HttpRequest,
+    payload: web::Payload,
+    st: web::Data<AppState>,
+    metadata: RequestMetadata,
+) -> Result<HttpResponse, Error> {
+    let mut res = ws::handshake(req.head())?;
+    let requested_protocol = req
+        .headers()
+        .get("sec-websocket-protocol")
+        .and_then(|h| h.to_str().ok())
+        .map_or(false, |h| h.split(',').any(|p| p.trim() == PROTOCOL));
+    if requested_protocol {
+        res.header("sec-websocket-protocol", PROTOCOL);
+    }
+    let st = st.get_ref();
+    let session = Session {
+        payload,
+        events: st.broker.subscribe(),
+        schema: st.subscription_schema.clone(),
+        measure_schema: st.measure_subscription_schema.clone(),
+        query_limits: st.query_limits,
+        persisted_queries: st.persisted_queries.clone(),
+        pool: st.pool.clone(),
+        authentication: st.authentication.clone(),
+        metadata,
+        initialized: false,
+        codec: ws::Codec::new(),
+        buffer: BytesMut::new(),
+        subscriptions: HashMap::new(),
+        closed: false,
+    };
+    Ok(res.streaming(session))
+}
+
+/// Messages sent by the client
+///
+/// See https://github.com/apollographql/subscriptions-transport-ws/blob/master/PROTOCOL.md
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    ConnectionInit {
+        #[serde(default)]
+        payload: Option<InitPayload>,
+    },
+    Start {
+        id: String,
+        payload: GraphQLData,
+    },
+    Stop {
+        id: String,
+    },
+    ConnectionTerminate,
+}
+
+#[derive(Deserialize)]
+struct InitPayload {
+    /// `Bearer <token>`, as in the `Authorization` header
+    #[serde(alias = "Authorization")]
+    authorization: Option<String>,
+}
+
+/// A single websocket connection
+///
+/// Incoming frames and change events are handled as they arrive,
+/// the resulting messages are the body of the upgrade response.
+struct Session {
+    payload: web::Payload,
+    events: Receiver<ChangeEvent>,
+    schema: Arc<SubscriptionSchema>,
+    measure_schema: Arc<MeasureSubscriptionSchema>,
+    query_limits: QueryLimits,
+    persisted_queries: Arc<PersistedQueries>,
+    pool: Pool<ConnectionManager<PgConnection>>,
+    authentication: Authentication,
+    metadata: RequestMetadata,
+    /// Whether `connection_init` was accepted
+    initialized: bool,
+    codec: ws::Codec,
+    buffer: BytesMut,
+    subscriptions: HashMap<String, GraphQLRequest<WundergraphScalarValue>>,
+    closed: bool,
+}
+
+impl Session {
+    fn send(&mut self, msg: Message, out: &mut BytesMut) -> Result<(), Error> {
+        self.codec.encode(msg, out)?;
+        Ok(())
+    }
+
+    fn send_json(&mut self, msg: serde_json::Value, out: &mut BytesMut) -> Result<(), Error> {
+        self.send(Message::Text(msg.to_string()), out)
+    }
+
+    fn handle_frame(&mut self, frame: Frame, out: &mut BytesMut) -> Result<(), Error> {
+        match frame {
+            Frame::Text(Some(text)) => {
+                let msg = match serde_json::from_slice(&text) {
+                    Ok(msg) => msg,
+                    Err(e) => {
+                        let error = json!({"type": "connection_error", "payload": {"message": e.to_string()}});
+                        return self.send_json(error, out);
+                    }
+                };
+                self.handle_message(msg, out)
+            }
+            Frame::Ping(msg) => self.send(Message::Pong(msg), out),
+            Frame::Close(reason) => {
+                self.closed = true;
+                self.send(Message::Close(reason), out)
+            }
+            Frame::Text(None) | Frame::Binary(_) | Frame::Pong(_) => Ok(()),
+        }
+    }
+
+    fn handle_message(&mut self, msg: ClientMessage, out: &mut BytesMut) -> Result<(), Error> {
+        match msg {
+            ClientMessage::ConnectionInit { payload } => {
+                let credential = payload.and_then(|p| p.authorization);
+                if let Some(credential) = credential {
+                    match self.authentication.verify_bearer(&credential) {
+                        Ok(identity) => self.metadata.identity = Some(identity),
+                        Err(e) => {
+                            let error = json!({"type": "connection_error", "payload": {"message": e.to_string()}});
+                            self.send_json(error, out)?;
+                            self.closed = true;
+                            return self.send(Message::Close(None), out);
+                        }
+                    }
+                }
+                self.initialized = true;
+                self.send_json(json!({"type": "connection_ack"}), out)
+            }
+            ClientMessage::Start { id, .. } if !self.initialized => {
+                let errors = json!([{"message": "Expected connection_init before start"}]);
+                self.send_json(json!({"type": "error", "id": id, "payload": errors}), out)
+            }
+            ClientMessage::Start { id, payload } => {
+                let request = match prepare(
+                    &self.persisted_queries,
+                    self.query_limits,
+                    &self.measure_schema,
+                    payload,
+                ) {
+                    Ok(request) => request,
+                    Err(e) => {
+                        let errors =
+                            serde_json::to_value(GraphQLResponse::error(e))?["errors"].take();
+                        let error = json!({"type": "error", "id": id, "payload": errors});
+                        return self.send_json(error, out);
+                    }
+                };
+                // Executing without an event resolves all fields to null,
+                // but reports invalid documents before the first event
+                let res = request.execute(&self.schema, &self.context(None));
+                if res.is_ok() {
+                    self.subscriptions.insert(id, request);
+                    Ok(())
+                } else {
+                    let errors = serde_json::to_value(&res)?["errors"].take();
+                    self.send_json(json!({"type": "error", "id": id, "payload": errors}), out)
+                }
+            }
+            ClientMessage::Stop { id } => {
+                if self.subscriptions.remove(&id).is_some() {
+                    self.send_json(json!({"type": "complete", "id": id}), out)?;
+                }
+                Ok(())
+            }
+            ClientMessage::ConnectionTerminate => {
+                self.closed = true;
+                self.send(Message::Close(None), out)
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: ChangeEvent, out: &mut BytesMut) -> Result<(), Error> {
+        let ctx = self.context(Some(event));
+        let mut messages = Vec::new();
+        for (id, request) in &self.subscriptions {
+            let res = serde_json::to_value(request.execute(&self.schema, &ctx))?;
+            let matched = res["data"]
+                .as_object()
+                .map_or(false, |data| data.values().any(|v| !v.is_null()));
+            if matched || res.get("errors").is_some() {
+                messages.push(json!({"type": "data", "id": id, "payload": res}));
+            }
+        }
+        for msg in messages {
+            self.send_json(msg, out)?;
+        }
+        Ok(())
+    }
+
+    fn context(&self, event: Option<ChangeEvent>) -> SubscriptionContext {
+        SubscriptionContext {
+            pool: self.pool.clone(),
+            metadata: self.metadata.clone(),
+            event,
+        }
+    }
+}
+
+impl Stream for Session {
+    type Item = Bytes;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Bytes>, Error> {
+        let mut out = BytesMut::new();
+        while !self.closed {
+            match self.payload.poll()? {
+                Async::Ready(Some(chunk)) => {
+                    self.buffer.extend_from_slice(&chunk);
+                    while let Some(frame) = self.codec.decode(&mut self.buffer)? {
+                        self.handle_frame(frame, &mut out)?;
+                    }
+                }
+                Async::Ready(None) => self.closed = true,
+                Async::NotReady => break,
+            }
+        }
+        while !self.closed {
+            match self.events.poll() {
+                Ok(Async::Ready(Some(event))) => self.handle_event(event, &mut out)?,
+                Ok(Async::NotReady) => break,
+                // The broker dropped this session for lagging behind
+                Ok(Async::Ready(None)) | Err(()) => {
+                    self.closed = true;
+                    let reason = (CloseCode::Again, "Too many pending change events");
+                    self.send(Message::Close(Some(reason.into())), &mut out)?;
+                }
+            }
+        }
+        if !out.is_empty() {
+            Ok(Async::Ready(Some(out.freeze())))
+        } else if self.closed {
+            Ok(Async::Ready(None))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+/// Resolve a persisted query and check the query limits,
+/// like `execute` does for requests to `/graphql`
+fn prepare(
+    persisted_queries: &PersistedQueries,
+    query_limits: QueryLimits,
+    measure_schema: &MeasureSubscriptionSchema,
+    data: GraphQLData,
+) -> Result<GraphQLRequest<WundergraphScalarValue>, FieldError<WundergraphScalarValue>> {
+    let query = persisted_queries
+        .resolve(data.query, data.extensions.persisted_query.as_ref())
+        .map_err(IntoFieldError::into_field_error)?;
+    let request = GraphQLRequest::new(
+        subscription_as_query(&query),
+        data.operation_name,
+        data.variables,
+    );
+    let measurement = Measurement::default();
+    request.execute(measure_schema, &measurement);
+    query_limits.check(measurement.cost())?;
+    Ok(request)
+}
+
+/// Juniper 0.14 does not execute subscription operations, therefore
+/// they are executed as query against the subscription schema
+///
+/// Its syntax tree is private, so the document is tokenized by the lexer
+/// of its parser and the `subscription` keyword of each operation
+/// definition is replaced. Invalid documents are returned unchanged, the
+/// parser reports them on execution.
+fn subscription_as_query(document: &str) -> String {
+    let mut out = String::with_capacity(document.len());
+    let mut copied = 0;
+    let mut depth = 0;
+    // Definitions start at the top level, after the end of the previous one
+    let mut definition_start = true;
+    for token in Lexer::new(document) {
+        let token = match token {
+            Ok(token) => token,
+            Err(_) => return document.to_owned(),
+        };
+        match token.item {
+            Token::Name("subscription") if depth == 0 && definition_start => {
+                out.push_str(&document[copied..token.start.index()]);
+                out.push_str("query");
+                copied = token.end.index();
+            }
+            Token::CurlyOpen => depth += 1,
+            Token::CurlyClose => depth -= 1,
+            _ => {}
+        }
+        definition_start = depth == 0 && token.item == Token::CurlyClose;
+    }
+    out.push_str(&document[copied..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphql::Measure;
+
+    #[test]
+    fn subscription_operations_become_queries() {
+        assert_eq!(
+            subscription_as_query("subscription { postChanged { id } }"),
+            "query { postChanged { id } }"
+        );
+        assert_eq!(
+            subscription_as_query(
+                "subscription Posts($subscription: Int) { postChanged(id: $subscription) { id } }\n\
+                 fragment F on PostChange { id }\n\
+                 subscription { commentChanged { id } }"
+            ),
+            "query Posts($subscription: Int) { postChanged(id: $subscription) { id } }\n\
+             fragment F on PostChange { id }\n\
+             query { commentChanged { id } }"
+        );
+    }
+
+    #[test]
+    fn strings_comments_and_fields_are_kept() {
+        let document = "# subscription\n\
+                        { subscription: postChanged(filter: \"subscription {\") { id } }";
+        assert_eq!(subscription_as_query(document), document);
+    }
+
+    #[test]
+    fn invalid_documents_are_kept() {
+        let document = "subscription { postChanged(id: \"unterminated) { id } }";
+        assert_eq!(subscription_as_query(document), document);
+    }
+
+    fn start(payload: serde_json::Value) -> GraphQLData {
+        serde_json::from_value(payload).unwrap()
+    }
+
+    fn limits(max_depth: usize) -> QueryLimits {
+        QueryLimits {
+            max_depth,
+            max_fields: 100,
+            max_complexity: 1000,
+        }
+    }
+
+    fn error_extensions(e: FieldError<WundergraphScalarValue>) -> serde_json::Value {
+        let mut res = serde_json::to_value(GraphQLResponse::error(e)).unwrap();
+        res["errors"][0]["extensions"].take()
+    }
+
+    #[test]
+    fn subscriptions_are_checked_against_the_query_limits() {
+        let schema = MeasureSubscriptionSchema::new(Measure::default(), Measure::default());
+        let queries = PersistedQueries::load(None, false, 10).unwrap();
+        let query = "subscription { postChanged { kind post { id } } }";
+        let data = || start(json!({ "query": query }));
+        assert!(prepare(&queries, limits(3), &schema, data()).is_ok());
+        let e = prepare(&queries, limits(2), &schema, data()).unwrap_err();
+        assert_eq!(error_extensions(e)["limit"], "depth");
+    }
+
+    #[test]
+    fn subscriptions_are_resolved_through_persisted_queries() {
+        let schema = MeasureSubscriptionSchema::new(Measure::default(), Measure::default());
+        let allow_list = PersistedQueries::load(None, true, 10).unwrap();
+        let data = start(json!({ "query": "subscription { postChanged { kind } }" }));
+        let e = prepare(&allow_list, limits(10), &schema, data).unwrap_err();
+        assert_eq!(error_extensions(e)["code"], "PERSISTED_QUERY_NOT_ALLOWED");
+        let unknown = start(json!({
+            "extensions": {"persistedQuery": {"version": 1, "sha256Hash": "abc"}}
+        }));
+        let queries = PersistedQueries::load(None, false, 10).unwrap();
+        let e = prepare(&queries, limits(10), &schema, unknown).unwrap_err();
+        assert_eq!(error_extensions(e)["code"], "PERSISTED_QUERY_NOT_FOUND");
+    }
+}
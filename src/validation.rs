@@ -0,0 +1,383 @@
+//! Validation of user input
+//!
+//! Inputs implement `Validate` by applying the rules to each of their
+//! fields. Fields missing in a changeset are not changed and therefore
+//! not validated. All rules are checked, so every failing field is
+//! reported at once. The REST handlers and the graphql mutations
+//! validate inputs before they are written.
+
+use crate::db::DbConnection;
+use crate::schema::{posts, users};
+use diesel::dsl::exists;
+use diesel::prelude::*;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::fmt;
+
+/// Rules for a text field
+#[derive(Debug, Clone, Copy)]
+pub struct TextRule {
+    /// The maximal number of characters
+    pub max_length: usize,
+    /// Whether empty and whitespace-only text is allowed
+    pub allow_blank: bool,
+    /// The characters the text could consist of, `None` allows every character
+    pub allowed_chars: Option<AllowedChars>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AllowedChars {
+    pub is_allowed: fn(char) -> bool,
+    /// Description of the allowed characters used in the error message
+    pub description: &'static str,
+}
+
+pub const USER_NAME: TextRule = TextRule {
+    max_length: 100,
+    allow_blank: false,
+    allowed_chars: Some(AllowedChars {
+        is_allowed: |c| c.is_alphanumeric() || " -_.'".contains(c),
+        description: "letters, digits, spaces and -_.'",
+    }),
+};
+
+pub const POST_TITLE: TextRule = TextRule {
+    max_length: 200,
+    allow_blank: false,
+    allowed_chars: Some(AllowedChars {
+        is_allowed: |c| !c.is_control(),
+        description: "printable characters",
+    }),
+};
+
+pub const POST_CONTENT: TextRule = TextRule {
+    max_length: 100_000,
+    allow_blank: true,
+    allowed_chars: None,
+};
+
+pub const COMMENT: TextRule = TextRule {
+    max_length: 10_000,
+    allow_blank: false,
+    allowed_chars: None,
+};
+
+/// A single failed rule
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct Violation {
+    #[serde(rename = "name")]
+    pub field: &'static str,
+    /// Name of the failed rule, one of `notBlank`,
+    /// `maxLength`, `allowedCharacters` and `exists`
+    pub rule: &'static str,
+    #[serde(rename = "reason")]
+    pub message: String,
+}
+
+#[derive(Debug)]
+pub enum ValidationError {
+    /// The fields failing a rule
+    Invalid(Vec<Violation>),
+    Database(diesel::result::Error),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::Invalid(violations) => {
+                write!(f, "Invalid input: ")?;
+                for (i, violation) in violations.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "`{}` {}", violation.field, violation.message)?;
+                }
+                Ok(())
+            }
+            ValidationError::Database(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<diesel::result::Error> for ValidationError {
+    fn from(e: diesel::result::Error) -> Self {
+        ValidationError::Database(e)
+    }
+}
+
+/// A table containing rows other rows refer to by id
+pub trait Referenced {
+    /// Whether there is a current version of the row with the given id
+    fn exists(conn: &DbConnection, id: i32) -> QueryResult<bool>;
+}
+
+macro_rules! referenced {
+    ($table: ident) => {
+        impl Referenced for $table::table {
+            fn exists(conn: &DbConnection, id: i32) -> QueryResult<bool> {
+                diesel::select(exists(
+                    $table::table
+                        .filter($table::id.eq(id))
+                        .filter($table::version_end.is_null()),
+                ))
+                .get_result(conn)
+            }
+        }
+    };
+}
+
+referenced!(users);
+referenced!(posts);
+
+/// Collects the violations of an input
+pub struct Validator<'a> {
+    conn: &'a DbConnection,
+    violations: Vec<Violation>,
+}
+
+impl<'a> Validator<'a> {
+    fn violation(&mut self, field: &'static str, rule: &'static str, message: String) {
+        self.violations.push(Violation {
+            field,
+            rule,
+            message,
+        });
+    }
+
+    /// Check a text field, `None` if it is not changed
+    pub fn text(&mut self, field: &'static str, value: Option<&str>, rule: &TextRule) {
+        let value = match value {
+            Some(value) => value,
+            None => return,
+        };
+        if !rule.allow_blank && value.trim().is_empty() {
+            self.violation(field, "notBlank", String::from("must not be blank"));
+        }
+        let length = value.chars().count();
+        if length > rule.max_length {
+            self.violation(
+                field,
+                "maxLength",
+                format!(
+                    "must not be longer than {} characters, but has {}",
+                    rule.max_length, length
+                ),
+            );
+        }
+        if let Some(allowed) = rule.allowed_chars {
+            if let Some(c) = value.chars().find(|c| !(allowed.is_allowed)(*c)) {
+                self.violation(
+                    field,
+                    "allowedCharacters",
+                    format!(
+                        "must only contain {}, but contains {:?}",
+                        allowed.description, c
+                    ),
+                );
+            }
+        }
+    }
+
+    /// Check that a referenced row of `T` exists, `None` if it is not changed
+    pub fn exists<T: Referenced>(
+        &mut self,
+        field: &'static str,
+        id: Option<i32>,
+    ) -> QueryResult<()> {
+        if let Some(id) = id {
+            if !T::exists(self.conn, id)? {
+                self.violation(
+                    field,
+                    "exists",
+                    format!("refers to {}, which does not exist", id),
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Input checked before it is written
+pub trait Validate {
+    /// Apply the rules of all fields
+    fn rules(&self, validator: &mut Validator<'_>) -> QueryResult<()>;
+
+    fn validate(&self, conn: &DbConnection) -> Result<(), ValidationError> {
+        let mut validator = Validator {
+            conn,
+            violations: Vec::new(),
+        };
+        self.rules(&mut validator)?;
+        if validator.violations.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError::Invalid(validator.violations))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_connection;
+    use crate::model::users::tests::insert_user;
+    use crate::versioning::Versioned;
+
+    /// An input with a field for each rule
+    #[derive(Default)]
+    struct Input {
+        name: Option<String>,
+        title: Option<String>,
+        content: Option<String>,
+        comment: Option<String>,
+        author: Option<i32>,
+    }
+
+    impl Validate for Input {
+        fn rules(&self, v: &mut Validator<'_>) -> QueryResult<()> {
+            v.text("name", self.name.as_deref(), &USER_NAME);
+            v.text("title", self.title.as_deref(), &POST_TITLE);
+            v.text("content", self.content.as_deref(), &POST_CONTENT);
+            v.text("comment", self.comment.as_deref(), &COMMENT);
+            v.exists::<users::table>("author", self.author)
+        }
+    }
+
+    /// The field and rule of each violation
+    fn violations(conn: &DbConnection, input: &Input) -> Vec<(&'static str, &'static str)> {
+        match input.validate(conn) {
+            Ok(()) => Vec::new(),
+            Err(ValidationError::Invalid(violations)) => {
+                violations.iter().map(|v| (v.field, v.rule)).collect()
+            }
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn missing_fields_are_not_validated() {
+        let conn = match test_connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        assert!(violations(&conn, &Input::default()).is_empty());
+    }
+
+    #[test]
+    fn lengths_are_counted_in_characters() {
+        let conn = match test_connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let input = Input {
+            name: Some("ä".repeat(USER_NAME.max_length)),
+            ..Input::default()
+        };
+        assert!(violations(&conn, &input).is_empty());
+
+        let input = Input {
+            name: Some("ä".repeat(USER_NAME.max_length + 1)),
+            title: Some("t".repeat(POST_TITLE.max_length + 1)),
+            ..Input::default()
+        };
+        assert_eq!(
+            violations(&conn, &input),
+            [("name", "maxLength"), ("title", "maxLength")]
+        );
+        match input.validate(&conn) {
+            Err(ValidationError::Invalid(violations)) => assert_eq!(
+                violations[0].message,
+                "must not be longer than 100 characters, but has 101"
+            ),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn only_allowed_characters_are_accepted() {
+        let conn = match test_connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let input = Input {
+            name: Some(String::from("Jean-Luc O'Neil_2.0")),
+            title: Some(String::from("Ünïcode ✓")),
+            content: Some(String::from("Line\nbreaks\tand\u{0}anything")),
+            ..Input::default()
+        };
+        assert!(violations(&conn, &input).is_empty());
+
+        let input = Input {
+            name: Some(String::from("<script>")),
+            title: Some(String::from("Line\nbreak")),
+            ..Input::default()
+        };
+        assert_eq!(
+            violations(&conn, &input),
+            [
+                ("name", "allowedCharacters"),
+                ("title", "allowedCharacters")
+            ]
+        );
+    }
+
+    #[test]
+    fn blank_text_is_rejected_unless_allowed() {
+        let conn = match test_connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let input = Input {
+            name: Some(String::new()),
+            content: Some(String::from("  ")),
+            comment: Some(String::from(" \n ")),
+            ..Input::default()
+        };
+        assert_eq!(
+            violations(&conn, &input),
+            [("name", "notBlank"), ("comment", "notBlank")]
+        );
+    }
+
+    #[test]
+    fn all_failing_fields_are_reported() {
+        let conn = match test_connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let input = Input {
+            name: Some(String::from(" ")),
+            title: Some(format!("\n{}", "t".repeat(POST_TITLE.max_length))),
+            content: Some(String::from("fine")),
+            comment: Some(String::new()),
+            author: Some(-1),
+        };
+        assert_eq!(
+            violations(&conn, &input),
+            [
+                ("name", "notBlank"),
+                ("title", "maxLength"),
+                ("title", "allowedCharacters"),
+                ("comment", "notBlank"),
+                ("author", "exists"),
+            ]
+        );
+    }
+
+    #[test]
+    fn references_need_a_current_version() {
+        let conn = match test_connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let user = insert_user(&conn, &[]);
+        let input = Input {
+            author: Some(user),
+            ..Input::default()
+        };
+        assert!(violations(&conn, &input).is_empty());
+
+        users::table.close_current_version(&conn, user).unwrap();
+        assert_eq!(violations(&conn, &input), [("author", "exists")]);
+    }
+}
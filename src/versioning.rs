@@ -0,0 +1,60 @@
+use crate::db::DbConnection;
+use crate::schema::{comments, posts, users};
+use diesel::prelude::*;
+
+/// A table keeping older versions of its rows
+///
+/// Each row is identified by `(id, version_start)`, the current version
+/// of a row is the one without `version_end`. Updates close the current
+/// version and insert the changed row as new version, deletes only close
+/// the current version.
+pub trait Versioned {
+    /// Close the current version of the row with the given id
+    ///
+    /// Returns the number of the next version. The new version
+    /// must be inserted in the same transaction, so that it starts
+    /// at the same point in time as this version ends.
+    ///
+    /// Fails with `NotFound` if there is no current version of
+    /// the row with the given id.
+    fn close_current_version(&self, conn: &DbConnection, id: i32) -> QueryResult<i32>;
+}
+
+/// Implements `Versioned` for a table with the
+/// `version_start`, `version_end` and `version_end_at` columns
+macro_rules! versioned_table {
+    ($table: ident) => {
+        impl Versioned for $table::table {
+            fn close_current_version(&self, conn: &DbConnection, id: i32) -> QueryResult<i32> {
+                let current_version = $table::table
+                    .select(diesel::dsl::max($table::version_start))
+                    .filter($table::id.eq(id))
+                    .get_result::<Option<i32>>(conn)?
+                    .unwrap_or(0);
+
+                let closed = diesel::update(
+                    $table::table.filter(
+                        $table::id
+                            .eq(id)
+                            .and($table::version_start.eq(current_version))
+                            .and($table::version_end.is_null()),
+                    ),
+                )
+                .set((
+                    $table::version_end.eq(Some(current_version + 1)),
+                    $table::version_end_at.eq(diesel::dsl::now),
+                ))
+                .execute(conn)?;
+
+                if closed == 0 {
+                    return Err(diesel::result::Error::NotFound);
+                }
+                Ok(current_version + 1)
+            }
+        }
+    };
+}
+
+versioned_table!(posts);
+versioned_table!(comments);
+versioned_table!(users);
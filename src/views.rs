@@ -0,0 +1,24 @@
+//! Database views
+//!
+//! `diesel print-schema` only picks up tables, so
+//! views are declared by hand here.
+
+table! {
+    use diesel::sql_types::*;
+    use crate::db::Timestamptz;
+    use crate::model::posts::Post_state;
+
+    post_versions (id, version) {
+        id -> Int4,
+        version -> Int4,
+        valid_until -> Nullable<Int4>,
+        title -> Text,
+        content -> Nullable<Text>,
+        published_at -> Nullable<Timestamptz>,
+        author -> Int4,
+        post_state -> Post_state,
+        editor -> Nullable<Int4>,
+        version_start_at -> Timestamptz,
+        version_end_at -> Nullable<Timestamptz>,
+    }
+}